@@ -0,0 +1,472 @@
+//! A two-pass assembler for the mnemonics in Cowgod's CHIP-8 reference,
+//! plus the SUPER-CHIP and XO-CHIP extensions (`SCD`, `HIGH`, `LD HF, Vx`,
+//! `SAVE Vx, Vy`, `LD I, LONG addr`, `PLANE n` and so on).
+//!
+//! ```text
+//! ; comments run to the end of the line
+//!         LD   V1, 10
+//! loop:   CALL add_twice
+//!         SE   V0, 45
+//!         JP   loop
+//!         HALT
+//! add_twice:
+//!         ADD  V0, V1
+//!         ADD  V0, V1
+//!         RET
+//! sprite: DB   0xf0, 0x90, 0xf0
+//! ```
+//!
+//! Numbers are decimal, `0x` hex or `0b` binary. Labels may be used wherever
+//! an address or value is expected. `DB` and `DW` emit bytes and big-endian
+//! words, and `ORG` moves the output forward, padding with zeros. Programs
+//! may fill XO-CHIP's 64 KiB; whether they fit the machine they run on is
+//! checked when they are loaded.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::rom::PROGRAM_START;
+
+const MEMORY_LEN: u32 = 0x10000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+  /// 1-based line number in the source.
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for AsmError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+impl Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+  V(u8),
+  I,
+  /// `[I]`, the memory `I` points at.
+  AtI,
+  Dt,
+  St,
+  K,
+  F,
+  B,
+  /// `HF`, the large font, for `LD HF, Vx`.
+  Hf,
+  /// `R`, the flag registers.
+  R,
+  /// `LONG addr`, the 16-bit address of `LD I, LONG addr`.
+  Long(Box<Operand>),
+  Number(u32),
+  Label(String),
+}
+
+#[derive(Debug)]
+enum Item {
+  Instruction(String, Vec<Operand>),
+  Bytes(Vec<Operand>),
+  Words(Vec<Operand>),
+}
+
+struct Statement {
+  line: usize,
+  address: u32,
+  item: Item,
+}
+
+/// Assembles `source` into a ROM image to be loaded at `PROGRAM_START`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+  assemble_at(source, PROGRAM_START as u16)
+}
+
+/// Assembles `source` into an image whose first byte lives at `origin`, for
+/// copying straight into `CPU::memory`.
+pub fn assemble_at(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+  let origin = origin as u32;
+  let mut labels = HashMap::new();
+  let mut statements = vec![];
+  let mut address = origin;
+
+  for (i, raw) in source.lines().enumerate() {
+    let line = i + 1;
+    let err = |message: String| AsmError { line, message };
+
+    let mut text = raw.split(';').next().unwrap().trim();
+
+    if let Some((label, rest)) = text.split_once(':') {
+      let label = label.trim();
+      if !is_label(label) {
+        return Err(err(format!("invalid label {:?}", label)));
+      }
+      if is_reserved(label) {
+        return Err(err(format!("{:?} is a reserved name and cannot be a label", label)));
+      }
+      if labels.insert(label.to_string(), address).is_some() {
+        return Err(err(format!("label {:?} is defined twice", label)));
+      }
+      text = rest.trim();
+    }
+
+    if text.is_empty() {
+      continue;
+    }
+
+    let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+      Some((mnemonic, rest)) => (mnemonic, parse_operands(rest).map_err(err)?),
+      None => (text, vec![]),
+    };
+    let mnemonic = mnemonic.to_ascii_uppercase();
+
+    let (item, len) = match mnemonic.as_str() {
+      "ORG" => {
+        let target = match operands.as_slice() {
+          [Operand::Number(n)] => *n,
+          _ => return Err(err(String::from("ORG expects a single number"))),
+        };
+        if target < address {
+          return Err(err(format!("ORG {:#05x} would move backwards from {:#05x}", target, address)));
+        }
+        if target > MEMORY_LEN {
+          return Err(err(format!("ORG {:#05x} is past the end of memory", target)));
+        }
+        address = target;
+        continue;
+      },
+      "DB" => {
+        let len = operands.len() as u32;
+        (Item::Bytes(operands), len)
+      },
+      "DW" => {
+        let len = operands.len() as u32 * 2;
+        (Item::Words(operands), len)
+      },
+      _ => {
+        let len = match (mnemonic.as_str(), operands.as_slice()) {
+          ("LD", [Operand::I, Operand::Long(_)]) => 4,
+          _ => 2,
+        };
+        (Item::Instruction(mnemonic, operands), len)
+      },
+    };
+
+    statements.push(Statement { line, address, item });
+    address = match address.checked_add(len) {
+      Some(end) if end <= MEMORY_LEN => end,
+      _ => return Err(err(String::from("program does not fit in memory"))),
+    };
+  }
+
+  let mut image = vec![];
+
+  for statement in statements {
+    let err = |message: String| AsmError { line: statement.line, message };
+    image.resize((statement.address - origin) as usize, 0);
+
+    match &statement.item {
+      Item::Instruction(mnemonic, operands) => {
+        let instruction = encode(mnemonic, operands, &labels).map_err(err)?;
+        image.extend_from_slice(&instruction.to_bytes());
+      },
+      Item::Bytes(operands) => {
+        for operand in operands {
+          image.push(value(operand, 0xff, &labels).map_err(err)? as u8);
+        }
+      },
+      Item::Words(operands) => {
+        for operand in operands {
+          let word = value(operand, 0xffff, &labels).map_err(err)? as u16;
+          image.extend_from_slice(&word.to_be_bytes());
+        }
+      },
+    }
+  }
+
+  Ok(image)
+}
+
+fn is_label(name: &str) -> bool {
+  let mut chars = name.chars();
+  let starts_well = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+
+  starts_well && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Register names and the `I`, `DT`, `ST`, `K`, `F`, `B`, `HF` and `R`
+/// operands, in any case.
+fn is_reserved(name: &str) -> bool {
+  !matches!(parse_operand(name), Ok(Operand::Label(_)))
+}
+
+fn parse_operands(text: &str) -> Result<Vec<Operand>, String> {
+  text.split(',').map(|op| parse_operand(op.trim())).collect()
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+  let upper = text.to_ascii_uppercase();
+
+  let operand = match upper.as_str() {
+    "I" => Operand::I,
+    "[I]" => Operand::AtI,
+    "DT" => Operand::Dt,
+    "ST" => Operand::St,
+    "K" => Operand::K,
+    "F" => Operand::F,
+    "B" => Operand::B,
+    "HF" => Operand::Hf,
+    "R" => Operand::R,
+    "" => return Err(String::from("missing operand")),
+    _ => {
+      if let Some(address) = upper.strip_prefix("LONG ") {
+        let address = &text[text.len() - address.len()..];
+        return Ok(Operand::Long(Box::new(parse_operand(address.trim())?)));
+      }
+
+      if let Some(register) = upper.strip_prefix('V').filter(|r| r.len() == 1) {
+        if let Ok(x) = u8::from_str_radix(register, 16) {
+          return Ok(Operand::V(x));
+        }
+      }
+
+      if upper.starts_with(|c: char| c.is_ascii_digit()) {
+        let number = if let Some(hex) = upper.strip_prefix("0X") {
+          u32::from_str_radix(hex, 16)
+        } else if let Some(bin) = upper.strip_prefix("0B") {
+          u32::from_str_radix(bin, 2)
+        } else {
+          upper.parse()
+        };
+        return number.map(Operand::Number).map_err(|_| format!("invalid number {:?}", text));
+      }
+
+      if !text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid operand {:?}", text));
+      }
+      Operand::Label(text.to_string())
+    },
+  };
+
+  Ok(operand)
+}
+
+fn value(operand: &Operand, max: u32, labels: &HashMap<String, u32>) -> Result<u32, String> {
+  let n = match operand {
+    Operand::Number(n) => *n,
+    Operand::Label(name) => *labels.get(name).ok_or_else(|| format!("undefined label {:?}", name))?,
+    other => return Err(format!("expected a value, got {:?}", other)),
+  };
+
+  if n > max {
+    return Err(format!("{:#x} does not fit in {:#x}", n, max));
+  }
+  Ok(n)
+}
+
+fn encode(mnemonic: &str, operands: &[Operand], labels: &HashMap<String, u32>) -> Result<Instruction, String> {
+  use Instruction::*;
+  use Operand::V;
+
+  let addr = |op: &Operand| value(op, 0xfff, labels).map(|n| n as u16);
+  let byte = |op: &Operand| value(op, 0xff, labels).map(|n| n as u8);
+  let nibble = |op: &Operand| value(op, 0xf, labels).map(|n| n as u8);
+
+  let instruction = match (mnemonic, operands) {
+    ("HALT", []) => Halt,
+    ("CLS", []) => Cls,
+    ("RET", []) => Ret,
+    ("SYS", [a]) => Sys { addr: addr(a)? },
+    ("JP", [V(0), a]) => JpV0 { addr: addr(a)? },
+    ("JP", [a]) => Jp { addr: addr(a)? },
+    ("CALL", [a]) => Call { addr: addr(a)? },
+    ("SE", [V(x), V(y)]) => SeReg { x: *x, y: *y },
+    ("SE", [V(x), b]) => SeByte { x: *x, byte: byte(b)? },
+    ("SNE", [V(x), V(y)]) => SneReg { x: *x, y: *y },
+    ("SNE", [V(x), b]) => SneByte { x: *x, byte: byte(b)? },
+    ("LD", [V(x), V(y)]) => LdReg { x: *x, y: *y },
+    ("LD", [V(x), Operand::Dt]) => LdVxDt { x: *x },
+    ("LD", [V(x), Operand::K]) => LdVxK { x: *x },
+    ("LD", [V(x), Operand::AtI]) => LdVxI { x: *x },
+    ("LD", [V(x), Operand::R]) => LdVxR { x: *x },
+    ("LD", [V(x), b]) => LdByte { x: *x, byte: byte(b)? },
+    ("LD", [Operand::I, Operand::Long(a)]) => LdILong { addr: value(a, 0xffff, labels)? as u16 },
+    ("LD", [Operand::I, a]) => LdI { addr: addr(a)? },
+    ("LD", [Operand::Dt, V(x)]) => LdDtVx { x: *x },
+    ("LD", [Operand::St, V(x)]) => LdStVx { x: *x },
+    ("LD", [Operand::F, V(x)]) => LdF { x: *x },
+    ("LD", [Operand::B, V(x)]) => LdB { x: *x },
+    ("LD", [Operand::AtI, V(x)]) => LdIVx { x: *x },
+    ("LD", [Operand::Hf, V(x)]) => LdHf { x: *x },
+    ("LD", [Operand::R, V(x)]) => LdRVx { x: *x },
+    ("ADD", [V(x), V(y)]) => AddReg { x: *x, y: *y },
+    ("ADD", [V(x), b]) => AddByte { x: *x, byte: byte(b)? },
+    ("ADD", [Operand::I, V(x)]) => AddI { x: *x },
+    ("OR", [V(x), V(y)]) => Or { x: *x, y: *y },
+    ("AND", [V(x), V(y)]) => And { x: *x, y: *y },
+    ("XOR", [V(x), V(y)]) => Xor { x: *x, y: *y },
+    ("SUB", [V(x), V(y)]) => Sub { x: *x, y: *y },
+    ("SUBN", [V(x), V(y)]) => Subn { x: *x, y: *y },
+    ("SHR", [V(x)]) => Shr { x: *x, y: *x },
+    ("SHR", [V(x), V(y)]) => Shr { x: *x, y: *y },
+    ("SHL", [V(x)]) => Shl { x: *x, y: *x },
+    ("SHL", [V(x), V(y)]) => Shl { x: *x, y: *y },
+    ("RND", [V(x), b]) => Rnd { x: *x, byte: byte(b)? },
+    ("DRW", [V(x), V(y), n]) => Drw { x: *x, y: *y, n: nibble(n)? },
+    ("SKP", [V(x)]) => Skp { x: *x },
+    ("SKNP", [V(x)]) => Sknp { x: *x },
+    ("SCD", [n]) => ScrollDown { n: nibble(n)? },
+    ("SCU", [n]) => ScrollUp { n: nibble(n)? },
+    ("SCR", []) => ScrollRight,
+    ("SCL", []) => ScrollLeft,
+    ("EXIT", []) => Exit,
+    ("LOW", []) => Lores,
+    ("HIGH", []) => Hires,
+    ("SAVE", [V(x), V(y)]) => SaveRange { x: *x, y: *y },
+    ("LOAD", [V(x), V(y)]) => LoadRange { x: *x, y: *y },
+    ("PLANE", [n]) => Plane { n: nibble(n)? },
+    ("AUDIO", []) => Audio,
+    ("PITCH", [V(x)]) => Pitch { x: *x },
+    (
+      "HALT" | "CLS" | "RET" | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND"
+      | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP" | "SCD" | "SCU"
+      | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "SAVE" | "LOAD" | "PLANE" | "AUDIO" | "PITCH",
+      _,
+    ) => return Err(format!("invalid operands for {}", mnemonic)),
+    _ => return Err(format!("unknown mnemonic {}", mnemonic)),
+  };
+
+  Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::CPU;
+
+  #[test]
+  fn assembles_add_twice_with_labels() {
+    let source = "
+      ; main
+              LD   V0, 5
+              LD   V1, 10
+              CALL add_twice
+              CALL add_twice
+              HALT
+
+      add_twice:
+              ADD  V0, V1
+              ADD  V0, V1
+              RET
+    ";
+
+    let rom = assemble(source).unwrap();
+    assert_eq!(&rom[4..8], &[0x22, 0x0a, 0x22, 0x0a]);
+
+    let mut cpu = CPU::with_seed(1);
+    cpu.load_rom(&rom).unwrap();
+    cpu.run().unwrap();
+    assert_eq!(cpu.registers[0], 45);
+  }
+
+  #[test]
+  fn data_directives_and_org() {
+    let source = "
+              LD   I, sprite
+              ORG  0x208
+      sprite: DB   0xf0, 0b10010000, 144
+              DW   sprite, 0x1234
+    ";
+
+    let rom = assemble(source).unwrap();
+    assert_eq!(
+      rom,
+      [0xa2, 0x08, 0, 0, 0, 0, 0, 0, 0xf0, 0x90, 0x90, 0x02, 0x08, 0x12, 0x34],
+    );
+  }
+
+  #[test]
+  fn encodes_every_form() {
+    let source = "
+      CLS
+      JP V0, 0x300
+      SE V3, VA
+      SNE V3, 0x10
+      LD VF, DT
+      LD V2, K
+      LD [I], V7
+      LD V7, [I]
+      LD ST, V1
+      ADD I, VE
+      SHR V4
+      SHL V4, V5
+      DRW V1, V2, 15
+      SKNP V9
+    ";
+
+    let rom = assemble(source).unwrap();
+    let words: Vec<u16> = rom.chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect();
+
+    assert_eq!(words, [
+      0x00e0, 0xb300, 0x53a0, 0x4310, 0xff07, 0xf20a, 0xf755,
+      0xf765, 0xf118, 0xfe1e, 0x8446, 0x845e, 0xd12f, 0xe9a1,
+    ]);
+  }
+
+  #[test]
+  fn encodes_extensions() {
+    let source = "
+      SCD 3
+      SCU 0xf
+      SCR
+      SCL
+      EXIT
+      LOW
+      HIGH
+      LD HF, V2
+      LD R, V7
+      LD V7, R
+      SAVE V2, V5
+      LOAD V5, V2
+      LD I, LONG data
+      PLANE 2
+      AUDIO
+      PITCH V3
+      data: DB 1
+    ";
+
+    let rom = assemble(source).unwrap();
+    let words: Vec<u16> = rom[..32].chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect();
+
+    assert_eq!(words, [
+      0x00c3, 0x00df, 0x00fb, 0x00fc, 0x00fd, 0x00fe, 0x00ff, 0xf230,
+      0xf775, 0xf785, 0x5252, 0x5523, 0xf000, 0x0222, 0xf201, 0xf002,
+    ]);
+    assert_eq!(rom[32..], [0xf3, 0x3a, 0x01]);
+  }
+
+  #[test]
+  fn errors_carry_line_numbers() {
+    let cases = [
+      ("CLS\nJP nowhere", 2, "undefined label \"nowhere\""),
+      ("\n\nLD V0, 256", 3, "0x100 does not fit in 0xff"),
+      ("FOO V1", 1, "unknown mnemonic FOO"),
+      ("CLS\nADD V1", 2, "invalid operands for ADD"),
+      ("a: CLS\na: RET", 2, "label \"a\" is defined twice"),
+      ("f: CLS", 1, "\"f\" is a reserved name and cannot be a label"),
+      ("1st: CLS", 1, "invalid label \"1st\""),
+      ("LD I, LONG 0x10000", 1, "0x10000 does not fit in 0xffff"),
+      ("ORG 0xffffffff\nDB 1", 1, "ORG 0xffffffff is past the end of memory"),
+      ("ORG 0x10000\nDB 1", 2, "program does not fit in memory"),
+      ("ORG 0xffff\nDW 1", 2, "program does not fit in memory"),
+    ];
+
+    for (source, line, message) in cases {
+      let err = assemble(source).unwrap_err();
+      assert_eq!((err.line, err.message.as_str()), (line, message), "{:?}", source);
+    }
+  }
+}
@@ -0,0 +1,451 @@
+//! Memory-mapped I/O. A `MappedBus` splits an address space into ranges,
+//! each answered by a `Device`: RAM, ROM, a console port or a timer, so
+//! programs can talk to the outside world with ordinary loads and stores.
+//!
+//! A device sees offsets from the start of its range, which keeps it usable,
+//! and testable, on its own.
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+use crate::isa::Bus;
+
+/// Something that can be mapped onto a `MappedBus`. The `Bus` methods take
+/// offsets into the device rather than bus addresses.
+pub trait Device: Bus + DeviceClone + Any {
+  /// A short name for the device, shown in memory maps and errors.
+  fn name(&self) -> &'static str;
+
+  /// Advances the device by one 60 Hz tick.
+  fn tick(&mut self) {}
+}
+
+/// Lets a `MappedBus` be cloned along with the machine holding it.
+pub trait DeviceClone {
+  fn clone_box(&self) -> Box<dyn Device>;
+}
+
+impl<T: Device + Clone> DeviceClone for T {
+  fn clone_box(&self) -> Box<dyn Device> {
+    Box::new(self.clone())
+  }
+}
+
+/// Plain read/write memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ram(Vec<u8>);
+
+impl Ram {
+  /// `len` bytes, all zero.
+  pub fn new(len: usize) -> Self {
+    Ram(vec![0; len])
+  }
+}
+
+impl From<Vec<u8>> for Ram {
+  fn from(bytes: Vec<u8>) -> Self {
+    Ram(bytes)
+  }
+}
+
+impl Bus for Ram {
+  fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  fn peek(&self, offset: usize) -> Option<u8> {
+    self.0.peek(offset)
+  }
+
+  fn write(&mut self, offset: usize, value: u8) -> bool {
+    self.0.write(offset, value)
+  }
+}
+
+impl Device for Ram {
+  fn name(&self) -> &'static str {
+    "ram"
+  }
+}
+
+/// Read-only memory. Writes are refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom(Vec<u8>);
+
+impl From<Vec<u8>> for Rom {
+  fn from(bytes: Vec<u8>) -> Self {
+    Rom(bytes)
+  }
+}
+
+impl Bus for Rom {
+  fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  fn peek(&self, offset: usize) -> Option<u8> {
+    self.0.peek(offset)
+  }
+
+  fn write(&mut self, _offset: usize, _value: u8) -> bool {
+    false
+  }
+}
+
+impl Device for Rom {
+  fn name(&self) -> &'static str {
+    "rom"
+  }
+}
+
+/// A serial console, two bytes wide.
+///
+/// | offset | read                           | write            |
+/// |--------|--------------------------------|------------------|
+/// | 0      | next input byte, 0 if none     | outputs the byte |
+/// | 1      | input bytes waiting, up to 255 | refused          |
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Console {
+  /// Everything the program has written so far.
+  pub output: Vec<u8>,
+  input: VecDeque<u8>,
+}
+
+impl Console {
+  pub const DATA: usize = 0;
+  pub const STATUS: usize = 1;
+
+  pub fn new() -> Self {
+    Console::default()
+  }
+
+  /// Queues bytes for the program to read.
+  pub fn feed(&mut self, bytes: &[u8]) {
+    self.input.extend(bytes);
+  }
+}
+
+impl Bus for Console {
+  fn len(&self) -> usize {
+    2
+  }
+
+  fn peek(&self, offset: usize) -> Option<u8> {
+    match offset {
+      Console::DATA => Some(self.input.front().copied().unwrap_or(0)),
+      Console::STATUS => Some(self.input.len().min(255) as u8),
+      _ => None,
+    }
+  }
+
+  /// Reading the data port consumes the byte.
+  fn read(&mut self, offset: usize) -> Option<u8> {
+    let value = self.peek(offset)?;
+    if offset == Console::DATA {
+      self.input.pop_front();
+    }
+    Some(value)
+  }
+
+  fn write(&mut self, offset: usize, value: u8) -> bool {
+    if offset != Console::DATA {
+      return false;
+    }
+    self.output.push(value);
+    true
+  }
+}
+
+impl Device for Console {
+  fn name(&self) -> &'static str {
+    "console"
+  }
+}
+
+/// A 60 Hz timer, two bytes wide.
+///
+/// | offset | read                   | write              |
+/// |--------|------------------------|--------------------|
+/// | 0      | countdown, stops at 0  | sets the countdown |
+/// | 1      | ticks so far, wrapping | sets the count     |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timer {
+  pub countdown: u8,
+  pub ticks: u8,
+}
+
+impl Timer {
+  pub const COUNTDOWN: usize = 0;
+  pub const TICKS: usize = 1;
+
+  pub fn new() -> Self {
+    Timer::default()
+  }
+}
+
+impl Bus for Timer {
+  fn len(&self) -> usize {
+    2
+  }
+
+  fn peek(&self, offset: usize) -> Option<u8> {
+    match offset {
+      Timer::COUNTDOWN => Some(self.countdown),
+      Timer::TICKS => Some(self.ticks),
+      _ => None,
+    }
+  }
+
+  fn write(&mut self, offset: usize, value: u8) -> bool {
+    match offset {
+      Timer::COUNTDOWN => self.countdown = value,
+      Timer::TICKS => self.ticks = value,
+      _ => return false,
+    }
+    true
+  }
+}
+
+impl Device for Timer {
+  fn name(&self) -> &'static str {
+    "timer"
+  }
+
+  fn tick(&mut self) {
+    self.countdown = self.countdown.saturating_sub(1);
+    self.ticks = self.ticks.wrapping_add(1);
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapError {
+  /// The device would reach past the end of the address space.
+  OutOfRange { name: &'static str, start: usize, end: usize, len: usize },
+  /// The device would overlap one already mapped.
+  Overlap { name: &'static str, start: usize, other: &'static str, other_start: usize },
+}
+
+impl fmt::Display for MapError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      MapError::OutOfRange { name, start, end, len } => write!(
+        f, "{} at 0x{:03x}..0x{:03x} does not fit in 0x{:03x} bytes", name, start, end, len,
+      ),
+      MapError::Overlap { name, start, other, other_start } => write!(
+        f, "{} at 0x{:03x} overlaps the {} at 0x{:03x}", name, start, other, other_start,
+      ),
+    }
+  }
+}
+
+impl Error for MapError {}
+
+struct Mapping {
+  start: usize,
+  device: Box<dyn Device>,
+}
+
+impl Mapping {
+  fn end(&self) -> usize {
+    self.start + self.device.len()
+  }
+}
+
+impl Clone for Mapping {
+  fn clone(&self) -> Self {
+    Mapping { start: self.start, device: self.device.clone_box() }
+  }
+}
+
+/// An address space of `len` bytes with devices mapped onto parts of it.
+/// Nothing answers at unmapped addresses.
+#[derive(Clone)]
+pub struct MappedBus {
+  len: usize,
+  /// Sorted by start address, and never overlapping.
+  mappings: Vec<Mapping>,
+}
+
+impl MappedBus {
+  pub fn new(len: usize) -> Self {
+    MappedBus { len, mappings: vec![] }
+  }
+
+  /// Maps `device` to the addresses from `start`.
+  pub fn map(&mut self, start: usize, device: impl Device) -> Result<(), MapError> {
+    let name = device.name();
+    let end = match start.checked_add(device.len()) {
+      Some(end) if end <= self.len => end,
+      _ => {
+        let end = start.saturating_add(device.len());
+        return Err(MapError::OutOfRange { name, start, end, len: self.len });
+      },
+    };
+
+    let index = self.mappings.partition_point(|m| m.start < start);
+    let neighbours = self.mappings[index.saturating_sub(1)..].iter().take(2);
+    for other in neighbours {
+      if other.start < end && start < other.end() {
+        return Err(MapError::Overlap { name, start, other: other.device.name(), other_start: other.start });
+      }
+    }
+
+    self.mappings.insert(index, Mapping { start, device: Box::new(device) });
+    Ok(())
+  }
+
+  /// Maps `device` to the addresses from `start`, for building a bus.
+  ///
+  /// # Panics
+  ///
+  /// If the device does not fit, like `map` returning an error.
+  pub fn with(mut self, start: usize, device: impl Device) -> Self {
+    if let Err(err) = self.map(start, device) {
+      panic!("{}", err);
+    }
+    self
+  }
+
+  pub fn is_mapped(&self, address: usize) -> bool {
+    self.find(address).is_some()
+  }
+
+  /// The device of type `T` mapped at `start`.
+  pub fn device<T: Device>(&self, start: usize) -> Option<&T> {
+    let mapping = self.mappings.iter().find(|m| m.start == start)?;
+    (&*mapping.device as &dyn Any).downcast_ref()
+  }
+
+  pub fn device_mut<T: Device>(&mut self, start: usize) -> Option<&mut T> {
+    let mapping = self.mappings.iter_mut().find(|m| m.start == start)?;
+    (&mut *mapping.device as &mut dyn Any).downcast_mut()
+  }
+
+  /// Ticks every device.
+  pub fn tick(&mut self) {
+    for mapping in &mut self.mappings {
+      mapping.device.tick();
+    }
+  }
+
+  /// The index of the mapping holding `address`.
+  fn find(&self, address: usize) -> Option<usize> {
+    let index = self.mappings.partition_point(|m| m.start <= address).checked_sub(1)?;
+    (address < self.mappings[index].end()).then_some(index)
+  }
+}
+
+impl Bus for MappedBus {
+  fn len(&self) -> usize {
+    self.len
+  }
+
+  fn peek(&self, address: usize) -> Option<u8> {
+    let mapping = &self.mappings[self.find(address)?];
+    mapping.device.peek(address - mapping.start)
+  }
+
+  fn read(&mut self, address: usize) -> Option<u8> {
+    let index = self.find(address)?;
+    let mapping = &mut self.mappings[index];
+    mapping.device.read(address - mapping.start)
+  }
+
+  fn write(&mut self, address: usize, value: u8) -> bool {
+    match self.find(address) {
+      Some(index) => {
+        let mapping = &mut self.mappings[index];
+        mapping.device.write(address - mapping.start, value)
+      },
+      None => false,
+    }
+  }
+}
+
+/// The memory map, one device per line.
+impl fmt::Debug for MappedBus {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut map = f.debug_map();
+    for mapping in &self.mappings {
+      map.entry(&format_args!("0x{:03x}..0x{:03x}", mapping.start, mapping.end()), &mapping.device.name());
+    }
+    map.finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rom_refuses_writes() {
+    let mut rom = Rom::from(vec![1, 2]);
+    assert!(!rom.write(0, 9));
+    assert_eq!((rom.peek(0), rom.peek(2)), (Some(1), None));
+
+    let mut ram = Ram::new(2);
+    assert!(ram.write(1, 9));
+    assert_eq!(ram.peek(1), Some(9));
+  }
+
+  #[test]
+  fn console_reads_consume_input() {
+    let mut console = Console::new();
+    console.feed(b"hi");
+    assert_eq!(console.peek(Console::STATUS), Some(2));
+    assert_eq!(console.peek(Console::DATA), Some(b'h'));
+    assert_eq!(console.read(Console::DATA), Some(b'h'));
+    assert_eq!(console.read(Console::DATA), Some(b'i'));
+    assert_eq!(console.read(Console::DATA), Some(0));
+
+    assert!(console.write(Console::DATA, b'!'));
+    assert!(!console.write(Console::STATUS, 1));
+    assert_eq!(console.output, b"!");
+  }
+
+  #[test]
+  fn timer_counts_down_and_up() {
+    let mut timer = Timer::new();
+    timer.write(Timer::COUNTDOWN, 2);
+    for _ in 0..3 {
+      timer.tick();
+    }
+    assert_eq!((timer.peek(Timer::COUNTDOWN), timer.peek(Timer::TICKS)), (Some(0), Some(3)));
+  }
+
+  #[test]
+  fn addresses_go_to_the_device_mapped_there() {
+    let mut bus = MappedBus::new(0x100)
+      .with(0x00, Rom::from(vec![0xaa; 0x10]))
+      .with(0x80, Ram::new(0x10))
+      .with(0xf0, Console::new());
+
+    assert_eq!(bus.peek(0x0f), Some(0xaa));
+    assert_eq!(bus.peek(0x10), None);
+    assert!(bus.write(0x81, 5));
+    assert_eq!(bus.device::<Ram>(0x80).unwrap().peek(1), Some(5));
+    assert!(!bus.write(0x00, 5));
+    assert!(!bus.write(0x40, 5));
+
+    bus.device_mut::<Console>(0xf0).unwrap().feed(b"x");
+    assert_eq!(bus.read(0xf0), Some(b'x'));
+    assert!(bus.write(0xf0, b'y'));
+    assert_eq!(bus.device::<Console>(0xf0).unwrap().output, b"y");
+    assert!(bus.device::<Ram>(0xf0).is_none());
+    assert_eq!(format!("{:?}", bus), r#"{0x000..0x010: "rom", 0x080..0x090: "ram", 0x0f0..0x0f2: "console"}"#);
+  }
+
+  #[test]
+  fn mappings_must_fit_and_not_overlap() {
+    let mut bus = MappedBus::new(0x100).with(0x10, Ram::new(0x10));
+
+    assert_eq!(bus.map(0xff, Timer::new()).unwrap_err().to_string(), "timer at 0x0ff..0x101 does not fit in 0x100 bytes");
+    assert_eq!(bus.map(0x1f, Timer::new()).unwrap_err().to_string(), "timer at 0x01f overlaps the ram at 0x010");
+    assert!(bus.map(0x0f, Console::new()).is_err());
+    assert!(bus.map(0x0e, Console::new()).is_ok());
+    assert!(bus.map(0x20, Timer::new()).is_ok());
+    assert!(matches!(bus.map(usize::MAX, Timer::new()), Err(MapError::OutOfRange { .. })));
+  }
+}
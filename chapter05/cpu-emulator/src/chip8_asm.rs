@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::Path;
+
+use clap::{App, Arg};
+use libcpu::assemble;
+
+fn main() {
+  let app = App::new("chip8-asm")
+    .about("Assembles CHIP-8 mnemonics into a ROM image")
+    .arg(
+      Arg::new("source")
+        .required(true)
+        .help("Assembly source file")
+    )
+    .arg(
+      Arg::new("output")
+        .short('o')
+        .long("output")
+        .takes_value(true)
+        .help("Where to write the ROM (default: the source with a .ch8 extension)")
+    )
+    .arg(
+      Arg::new("bytes")
+        .long("bytes")
+        .help("Print the image as a Rust byte array instead of writing a ROM")
+    );
+
+  let args = app.get_matches();
+
+  let source_path = Path::new(args.value_of("source").unwrap());
+  let source = fs::read_to_string(source_path).unwrap_or_else(|err| {
+    eprintln!("unable to read {}: {}", source_path.display(), err);
+    std::process::exit(1);
+  });
+
+  let rom = assemble(&source).unwrap_or_else(|err| {
+    eprintln!("{}: {}", source_path.display(), err);
+    std::process::exit(1);
+  });
+
+  if args.is_present("bytes") {
+    let bytes: Vec<String> = rom.iter().map(|b| format!("0x{:02X}", b)).collect();
+    println!("[{}]", bytes.join(", "));
+    return;
+  }
+
+  let output = args.value_of("output")
+    .map(Path::new)
+    .map(Path::to_path_buf)
+    .unwrap_or_else(|| source_path.with_extension("ch8"));
+
+  if let Err(err) = fs::write(&output, &rom) {
+    eprintln!("unable to write {}: {}", output.display(), err);
+    std::process::exit(1);
+  }
+  println!("wrote {} bytes to {}", rom.len(), output.display());
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::Path;
+
+use clap::{App, Arg};
+use libcpu::conformance::{read_rom, Golden};
+use libcpu::Profile;
+
+fn main() {
+  let app = App::new("chip8-conform")
+    .about("Records or checks the golden outcome of a headless CHIP-8 run")
+    .arg(
+      Arg::new("rom")
+        .required(true)
+        .help("ROM file, or assembler source ending in .asm")
+    )
+    .arg(
+      Arg::new("check")
+        .long("check")
+        .takes_value(true)
+        .conflicts_with_all(&["profile", "cycles"])
+        .help("Compare the run against this golden file instead of printing one")
+    )
+    .arg(
+      Arg::new("profile")
+        .long("profile")
+        .takes_value(true)
+        .help("Machine to emulate: chip8 (default), schip or xochip")
+    )
+    .arg(
+      Arg::new("cycles")
+        .short('c')
+        .long("cycles")
+        .takes_value(true)
+        .help("Instructions to run before recording the outcome (default: 1000)")
+    );
+
+  let args = app.get_matches();
+
+  let fail = |message: String| -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+  };
+
+  let rom = read_rom(Path::new(args.value_of("rom").unwrap())).unwrap_or_else(|err| fail(err));
+
+  match args.value_of("check") {
+    Some(path) => {
+      let golden: Golden = fs::read_to_string(path)
+        .map_err(|err| format!("unable to read {}: {}", path, err))
+        .and_then(|text| text.parse().map_err(|err| format!("{}: {}", path, err)))
+        .unwrap_or_else(|err| fail(err));
+
+      match golden.check(&rom) {
+        Ok(()) => println!("ok"),
+        Err(differences) => {
+          for difference in differences {
+            println!("{}", difference);
+          }
+          std::process::exit(3);
+        },
+      }
+    },
+    None => {
+      let profile: Profile = args.value_of("profile")
+        .map_or(Ok(Profile::Chip8), str::parse)
+        .unwrap_or_else(|err| fail(err));
+      let cycles = args.value_of("cycles").map_or(1000, |cycles| {
+        cycles.parse().unwrap_or_else(|_| {
+          eprintln!("--cycles must be a whole number, got {:?}", cycles);
+          std::process::exit(2);
+        })
+      });
+
+      print!("{}", Golden::record(&rom, profile, cycles).unwrap_or_else(|err| fail(err)));
+    },
+  }
+}
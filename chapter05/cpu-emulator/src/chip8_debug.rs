@@ -0,0 +1,66 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{App, Arg};
+use libcpu::debugger::Debugger;
+use libcpu::{Profile, CPU};
+
+fn main() -> io::Result<()> {
+  let app = App::new("chip8-debug")
+    .about("Steps through a CHIP-8 ROM with commands read from stdin (try `help`)")
+    .arg(
+      Arg::new("rom")
+        .required(true)
+        .help("ROM file, loaded at 0x200")
+    )
+    .arg(
+      Arg::new("seed")
+        .long("seed")
+        .takes_value(true)
+        .help("Seed for RND, so that sessions can be repeated")
+    )
+    .arg(
+      Arg::new("profile")
+        .long("profile")
+        .takes_value(true)
+        .help("Machine to emulate: chip8 (default), schip or xochip")
+    );
+
+  let args = app.get_matches();
+
+  let profile = args.value_of("profile").map_or(Ok(Profile::Chip8), str::parse).unwrap_or_else(|err| {
+    eprintln!("{}", err);
+    std::process::exit(2);
+  });
+  let seed = match args.value_of("seed") {
+    Some(seed) => seed.parse().unwrap_or_else(|_| {
+      eprintln!("--seed must be a whole number, got {:?}", seed);
+      std::process::exit(2);
+    }),
+    None => SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64),
+  };
+  let mut cpu = CPU::with_profile(profile, seed);
+  let rom = Path::new(args.value_of("rom").unwrap());
+  if let Err(err) = cpu.load_rom_file(rom) {
+    eprintln!("{}", err);
+    std::process::exit(1);
+  }
+
+  let mut debugger = Debugger::new(cpu);
+  let stdin = io::stdin();
+  let mut stdout = io::stdout();
+
+  debugger.command("list 0x200 1", &mut stdout)?;
+  loop {
+    write!(stdout, "(chip8) ")?;
+    stdout.flush()?;
+
+    let mut line = String::new();
+    if stdin.lock().read_line(&mut line)? == 0 || !debugger.command(&line, &mut stdout)? {
+      break;
+    }
+  }
+
+  Ok(())
+}
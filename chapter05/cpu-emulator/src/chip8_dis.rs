@@ -0,0 +1,30 @@
+use std::fs;
+use std::path::Path;
+
+use clap::{App, Arg};
+use libcpu::{disassemble, PROGRAM_START};
+
+fn main() {
+  let app = App::new("chip8-dis")
+    .about("Prints an annotated listing of a CHIP-8 ROM that chip8-asm can reassemble")
+    .arg(
+      Arg::new("rom")
+        .required(true)
+        .help("ROM file, assumed to be loaded at 0x200")
+    );
+
+  let args = app.get_matches();
+
+  let path = Path::new(args.value_of("rom").unwrap());
+  let rom = fs::read(path).unwrap_or_else(|err| {
+    eprintln!("unable to read {}: {}", path.display(), err);
+    std::process::exit(1);
+  });
+
+  if rom.len() > 0x10000 - PROGRAM_START {
+    eprintln!("{} is {} bytes, too large to load at {:#x}", path.display(), rom.len(), PROGRAM_START);
+    std::process::exit(1);
+  }
+
+  print!("{}", disassemble(&rom, PROGRAM_START as u16));
+}
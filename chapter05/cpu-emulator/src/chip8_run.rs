@@ -0,0 +1,349 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use clap::{App, Arg, ArgMatches};
+use libcpu::bus::Console;
+use libcpu::clock::{Clock, DEFAULT_HZ};
+use libcpu::profiler::{Profiler, Report};
+use libcpu::replay::{Recorder, Recording};
+use libcpu::{CpuFault, Profile, CPU, PROGRAM_START};
+
+/// Parses `V3=0x2a` style register assignments. Values may be decimal or
+/// `0x`-prefixed hex.
+fn parse_register(assignment: &str) -> Result<(usize, u8), String> {
+  let invalid = || format!("expected VX=VALUE, got {:?}", assignment);
+
+  let (name, value) = assignment.split_once('=').ok_or_else(invalid)?;
+  let index = name.strip_prefix('V')
+    .or_else(|| name.strip_prefix('v'))
+    .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+    .filter(|index| *index < 16)
+    .ok_or_else(invalid)?;
+
+  let value = match value.strip_prefix("0x") {
+    Some(hex) => u8::from_str_radix(hex, 16),
+    None => value.parse(),
+  }.map_err(|_| invalid())?;
+
+  Ok((index, value))
+}
+
+/// Parses `shift=off` style quirk settings.
+fn parse_quirk(setting: &str) -> Result<(&str, bool), String> {
+  match setting.split_once('=') {
+    Some((name, "on")) => Ok((name, true)),
+    Some((name, "off")) => Ok((name, false)),
+    _ => Err(format!("expected QUIRK=on or QUIRK=off, got {:?}", setting)),
+  }
+}
+
+/// Parses a `0x`-prefixed hex or decimal address.
+fn parse_address(address: &str) -> usize {
+  match address.strip_prefix("0x") {
+    Some(hex) => usize::from_str_radix(hex, 16),
+    None => address.parse(),
+  }.unwrap_or_else(|_| {
+    eprintln!("expected an address, got {:?}", address);
+    std::process::exit(2);
+  })
+}
+
+fn parse_whole_number<T: FromStr>(value: &str, flag: &str) -> T {
+  value.parse().unwrap_or_else(|_| {
+    eprintln!("{} must be a whole number, got {:?}", flag, value);
+    std::process::exit(2);
+  })
+}
+
+fn main() {
+  let app = App::new("chip8-run")
+    .about("Runs a CHIP-8 ROM and prints the final register state")
+    .arg(
+      Arg::new("rom")
+        .required_unless_present_any(["load-state", "replay"])
+        .help("ROM file, loaded at 0x200")
+    )
+    .arg(
+      Arg::new("seed")
+        .long("seed")
+        .takes_value(true)
+        .help("Seed for RND, so that runs can be repeated")
+    )
+    .arg(
+      Arg::new("profile")
+        .long("profile")
+        .takes_value(true)
+        .help("Machine to emulate: chip8 (default), schip or xochip")
+    )
+    .arg(
+      Arg::new("quirk")
+        .long("quirk")
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .help("Override one of the profile's quirks, e.g. --quirk shift=off (shift, load-store, jump, logic, wrap)")
+    )
+    .arg(
+      Arg::new("load-state")
+        .long("load-state")
+        .takes_value(true)
+        .conflicts_with_all(&["rom", "seed", "profile"])
+        .help("Resume from a snapshot instead of loading a ROM")
+    )
+    .arg(
+      Arg::new("save-state")
+        .long("save-state")
+        .takes_value(true)
+        .help("Write a snapshot of the final state to this file")
+    )
+    .arg(
+      Arg::new("record")
+        .long("record")
+        .takes_value(true)
+        .help("Record the run's inputs to this file for replay")
+    )
+    .arg(
+      Arg::new("replay")
+        .long("replay")
+        .takes_value(true)
+        .conflicts_with_all(&[
+          "rom", "seed", "profile", "quirk", "load-state", "record", "cycles", "ms", "hz", "turbo",
+          "register", "key", "console", "report",
+        ])
+        .help("Re-run a recording made with --record")
+    )
+    .arg(
+      Arg::new("console")
+        .long("console")
+        .takes_value(true)
+        .conflicts_with("record")
+        .help("Map a console port at this address, e.g. 0xf00, and print what the program writes to it")
+    )
+    .arg(
+      Arg::new("report")
+        .long("report")
+        .conflicts_with("record")
+        .help("Profile the run and print hot spots, call edges and code that never ran")
+    )
+    .arg(
+      Arg::new("cycles")
+        .short('c')
+        .long("cycles")
+        .takes_value(true)
+        .help("Stop after this many instructions")
+    )
+    .arg(
+      Arg::new("ms")
+        .long("ms")
+        .takes_value(true)
+        .help("Stop after this many milliseconds of emulated time")
+    )
+    .arg(
+      Arg::new("hz")
+        .long("hz")
+        .takes_value(true)
+        .help("Instructions per second of emulated time (default: 500)")
+    )
+    .arg(
+      Arg::new("turbo")
+        .long("turbo")
+        .help("Run as fast as possible instead of in real time")
+    )
+    .arg(
+      Arg::new("register")
+        .short('r')
+        .long("register")
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .help("Initial register value, e.g. -r V0=5 -r VA=0x2a")
+    )
+    .arg(
+      Arg::new("key")
+        .short('k')
+        .long("key")
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .help("Hex key (0-F) held down for the whole run")
+    )
+    .arg(
+      Arg::new("screen")
+        .long("screen")
+        .help("Print the final screen as text")
+    )
+    .arg(
+      Arg::new("pbm")
+        .long("pbm")
+        .takes_value(true)
+        .help("Write the final screen to this file as a PBM image")
+    );
+
+  let args = app.get_matches();
+
+  let start = Instant::now();
+  let (cpu, executed, outcome, report) = match args.value_of("replay") {
+    Some(path) => {
+      let recording = Recording::load(Path::new(path)).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+      });
+      match recording.replay() {
+        Ok(cpu) => (cpu, recording.instructions(), Ok(true), None),
+        Err(fault) => {
+          eprintln!("recording does not match its program: {}", fault);
+          std::process::exit(3);
+        },
+      }
+    },
+    None => run(&args),
+  };
+
+  let console = args.value_of("console").and_then(|address| cpu.devices.device::<Console>(parse_address(address)));
+  if let Some(console) = console {
+    println!("console: {}", String::from_utf8_lossy(&console.output));
+  }
+
+  let reason = match &outcome {
+    Ok(_) if args.is_present("replay") => String::from("replayed"),
+    Ok(false) => String::from("halted"),
+    Ok(true) => String::from("cycle limit reached"),
+    Err(fault) => format!("fault: {}", fault),
+  };
+  println!("{} after {} instructions in {:?}", reason, executed, start.elapsed());
+  println!("{}", cpu);
+
+  if let Some(report) = report {
+    print!("{}", report);
+  }
+
+  if args.is_present("screen") {
+    print!("{}", cpu.display.to_text());
+  }
+
+  if let Some(path) = args.value_of("pbm") {
+    if let Err(err) = fs::write(path, cpu.display.to_pbm()) {
+      eprintln!("unable to write {}: {}", path, err);
+      std::process::exit(1);
+    }
+  }
+
+  if let Some(path) = args.value_of("save-state") {
+    if let Err(err) = cpu.save_snapshot(Path::new(path)) {
+      eprintln!("{}", err);
+      std::process::exit(1);
+    }
+  }
+
+  if outcome.is_err() {
+    std::process::exit(3);
+  }
+}
+
+/// Sets up the CPU from the arguments and runs it. Returns the final state,
+/// the number of instructions executed, whether the program was still
+/// running, as `CPU::step` does, and the profile if one was asked for.
+fn run(args: &ArgMatches) -> (CPU, u64, Result<bool, CpuFault>, Option<Report>) {
+  let hz = args.value_of("hz")
+    .map_or(DEFAULT_HZ, |hz| parse_whole_number(hz, "--hz"));
+  if hz == 0 {
+    eprintln!("--hz must be at least 1");
+    std::process::exit(2);
+  }
+  let cycles: Option<u64> = args.value_of("cycles")
+    .map(|cycles| parse_whole_number(cycles, "--cycles"));
+  let ms: Option<u64> = args.value_of("ms")
+    .map(|ms| parse_whole_number(ms, "--ms"));
+  let budget = match (cycles, ms.map(|ms| ms.saturating_mul(hz as u64) / 1000)) {
+    (Some(a), Some(b)) => Some(a.min(b)),
+    (a, b) => a.or(b),
+  };
+
+  let mut cpu = match args.value_of("load-state") {
+    Some(path) => CPU::load_snapshot(Path::new(path)).unwrap_or_else(|err| {
+      eprintln!("{}", err);
+      std::process::exit(1);
+    }),
+    None => {
+      let profile = args.value_of("profile").map_or(Ok(Profile::Chip8), str::parse).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(2);
+      });
+      let seed = match args.value_of("seed") {
+        Some(seed) => parse_whole_number(seed, "--seed"),
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64),
+      };
+      let mut cpu = CPU::with_profile(profile, seed);
+      let rom = Path::new(args.value_of("rom").unwrap());
+      if let Err(err) = cpu.load_rom_file(rom) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+      }
+      cpu
+    },
+  };
+
+  if let Some(address) = args.value_of("console") {
+    if let Err(err) = cpu.devices.map(parse_address(address), Console::new()) {
+      eprintln!("{}", err);
+      std::process::exit(2);
+    }
+  }
+
+  for setting in args.values_of("quirk").into_iter().flatten() {
+    if let Err(err) = parse_quirk(setting).and_then(|(name, on)| cpu.quirks.set(name, on)) {
+      eprintln!("{}", err);
+      std::process::exit(2);
+    }
+  }
+
+  for assignment in args.values_of("register").into_iter().flatten() {
+    match parse_register(assignment) {
+      Ok((index, value)) => cpu.registers[index] = value,
+      Err(err) => {
+        eprintln!("{}", err);
+        std::process::exit(2);
+      },
+    }
+  }
+
+  for key in args.values_of("key").into_iter().flatten() {
+    match u8::from_str_radix(key, 16) {
+      Ok(key) if key < 16 => cpu.keypad.press(key),
+      _ => {
+        eprintln!("expected a hex key from 0 to F, got {:?}", key);
+        std::process::exit(2);
+      },
+    }
+  }
+
+  let mut clock = Clock::new(hz);
+  clock.set_turbo(args.is_present("turbo"));
+
+  if args.is_present("report") {
+    // A snapshot does not say how long the program is, so assume it ends
+    // at the last non-zero byte.
+    let end = match args.value_of("rom") {
+      Some(rom) => fs::metadata(rom).map_or(PROGRAM_START, |meta| PROGRAM_START + meta.len() as usize),
+      None => cpu.memory.iter().rposition(|byte| *byte != 0).map_or(PROGRAM_START, |last| last + 1),
+    };
+    let mut profiler = Profiler::new(cpu, PROGRAM_START..end);
+    let outcome = clock.run_paced(&mut profiler, budget);
+    let (cpu, report) = profiler.finish();
+    return (cpu, clock.cycles(), outcome, Some(report));
+  }
+
+  // Timer ticks go through the recorder too, as they are what makes a run
+  // depend on the wall clock.
+  let mut recorder = Recorder::new(cpu);
+  let outcome = clock.run_paced(&mut recorder, budget);
+
+  let (cpu, recording) = recorder.finish();
+  if let Some(path) = args.value_of("record") {
+    if let Err(err) = recording.save(Path::new(path)) {
+      eprintln!("{}", err);
+      std::process::exit(1);
+    }
+  }
+
+  (cpu, clock.cycles(), outcome, None)
+}
@@ -0,0 +1,235 @@
+//! Instruction pacing. A `Clock` runs a CPU at a configurable instruction
+//! rate and decrements the timers at 60 Hz of emulated time, however fast
+//! instructions go. Emulated time only advances as instructions execute, so
+//! a run behaves the same whether it is paced against the wall clock or
+//! runs flat out in turbo mode.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{CpuFault, CPU};
+
+/// A common rate for CHIP-8 programs; anything from 500 to 1000 Hz is
+/// typical.
+pub const DEFAULT_HZ: u32 = 500;
+pub const TIMER_HZ: u32 = 60;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// What a `Clock` can drive.
+pub trait Clocked {
+  type Fault;
+
+  /// Executes one instruction, as `CPU::step`.
+  fn step(&mut self) -> Result<bool, Self::Fault>;
+  fn tick_timers(&mut self);
+}
+
+impl Clocked for CPU {
+  type Fault = CpuFault;
+
+  fn step(&mut self) -> Result<bool, CpuFault> {
+    CPU::step(self)
+  }
+
+  fn tick_timers(&mut self) {
+    CPU::tick_timers(self)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clock {
+  hz: u32,
+  turbo: bool,
+  cycles: u64,
+  /// Emulated time, kept exact as whole nanoseconds plus a remainder in
+  /// units of 1 / `hz` nanoseconds.
+  nanos: u64,
+  nanos_remainder: u64,
+  /// Progress towards the next timer tick, in units of 1 / (60 * `hz`)
+  /// seconds.
+  timer_phase: u64,
+}
+
+impl Default for Clock {
+  fn default() -> Self {
+    Clock::new(DEFAULT_HZ)
+  }
+}
+
+impl Clock {
+  /// A clock running `hz` instructions per second. Rates below 1 Hz are
+  /// raised to 1 Hz.
+  pub fn new(hz: u32) -> Self {
+    Clock {
+      hz: hz.max(1),
+      turbo: false,
+      cycles: 0,
+      nanos: 0,
+      nanos_remainder: 0,
+      timer_phase: 0,
+    }
+  }
+
+  pub fn hz(&self) -> u32 {
+    self.hz
+  }
+
+  /// Changes the instruction rate from the next instruction on.
+  pub fn set_hz(&mut self, hz: u32) {
+    let hz = hz.max(1) as u64;
+    // Rescale the partial progress so that no time is gained or lost.
+    self.nanos_remainder = self.nanos_remainder * hz / self.hz as u64;
+    self.timer_phase = self.timer_phase * hz / self.hz as u64;
+    self.hz = hz as u32;
+  }
+
+  pub fn turbo(&self) -> bool {
+    self.turbo
+  }
+
+  /// In turbo mode `run_paced` stops sleeping and runs as fast as the host
+  /// allows. Emulated time, and so the timers, are unaffected.
+  pub fn set_turbo(&mut self, turbo: bool) {
+    self.turbo = turbo;
+  }
+
+  /// The number of instructions executed.
+  pub fn cycles(&self) -> u64 {
+    self.cycles
+  }
+
+  pub fn emulated_time(&self) -> Duration {
+    Duration::from_nanos(self.nanos)
+  }
+
+  /// Executes one instruction and ticks the timers if a 60 Hz boundary has
+  /// passed. A faulting instruction takes no time.
+  pub fn step<T: Clocked>(&mut self, target: &mut T) -> Result<bool, T::Fault> {
+    let running = target.step()?;
+    self.cycles += 1;
+
+    let hz = self.hz as u64;
+    self.nanos_remainder += NANOS_PER_SEC;
+    self.nanos += self.nanos_remainder / hz;
+    self.nanos_remainder %= hz;
+
+    self.timer_phase += TIMER_HZ as u64;
+    while self.timer_phase >= hz {
+      self.timer_phase -= hz;
+      target.tick_timers();
+    }
+
+    Ok(running)
+  }
+
+  /// Executes up to `cycles` instructions. Returns `Ok(false)` if the
+  /// program halted first.
+  pub fn run_cycles<T: Clocked>(&mut self, target: &mut T, cycles: u64) -> Result<bool, T::Fault> {
+    for _ in 0..cycles {
+      if !self.step(target)? {
+        return Ok(false);
+      }
+    }
+    Ok(true)
+  }
+
+  /// Executes instructions until `duration` of emulated time has passed,
+  /// without waiting in real time. Returns `Ok(false)` if the program
+  /// halted first.
+  pub fn run_for<T: Clocked>(&mut self, target: &mut T, duration: Duration) -> Result<bool, T::Fault> {
+    let end = self.emulated_time() + duration;
+    while self.emulated_time() < end {
+      if !self.step(target)? {
+        return Ok(false);
+      }
+    }
+    Ok(true)
+  }
+
+  /// Like `run_cycles`, but sleeps to keep emulated time in step with the
+  /// wall clock unless in turbo mode. `None` runs until the program halts.
+  pub fn run_paced<T: Clocked>(&mut self, target: &mut T, cycles: Option<u64>) -> Result<bool, T::Fault> {
+    let start = Instant::now();
+    let emulated_start = self.emulated_time();
+    let end = cycles.map(|cycles| self.cycles + cycles);
+
+    while end.is_none_or(|end| self.cycles < end) {
+      if !self.step(target)? {
+        return Ok(false);
+      }
+
+      if !self.turbo {
+        let due = start + (self.emulated_time() - emulated_start);
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+          thread::sleep(wait);
+        }
+      }
+    }
+    Ok(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assemble;
+
+  fn spinning_cpu() -> CPU {
+    let mut cpu = CPU::with_seed(1);
+    cpu.load_rom(&assemble("loop: JP loop").unwrap()).unwrap();
+    cpu.delay_timer = 255;
+    cpu
+  }
+
+  #[test]
+  fn timers_run_at_60_hz_whatever_the_rate() {
+    for hz in [500, 700, 1000, 60, 7] {
+      let mut cpu = spinning_cpu();
+      let mut clock = Clock::new(hz);
+
+      assert!(clock.run_cycles(&mut cpu, hz as u64 * 2).unwrap());
+      assert_eq!(clock.emulated_time(), Duration::from_secs(2), "{} Hz", hz);
+      assert_eq!(cpu.delay_timer, 255 - 120, "{} Hz", hz);
+    }
+  }
+
+  #[test]
+  fn run_for_counts_emulated_time() {
+    let mut cpu = spinning_cpu();
+    let mut clock = Clock::new(1000);
+
+    clock.run_for(&mut cpu, Duration::from_millis(250)).unwrap();
+    assert_eq!(clock.cycles(), 250);
+    assert_eq!(cpu.delay_timer, 255 - 15);
+
+    clock.set_hz(500);
+    clock.run_for(&mut cpu, Duration::from_millis(250)).unwrap();
+    assert_eq!(clock.cycles(), 375);
+    assert_eq!(cpu.delay_timer, 255 - 30);
+  }
+
+  #[test]
+  fn halting_stops_early() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.load_rom(&assemble("CLS\nHALT").unwrap()).unwrap();
+    let mut clock = Clock::default();
+
+    assert!(!clock.run_cycles(&mut cpu, 10).unwrap());
+    assert_eq!(clock.cycles(), 2);
+  }
+
+  #[test]
+  fn paced_runs_wait_unless_turbo() {
+    let mut cpu = spinning_cpu();
+    let mut clock = Clock::new(1000);
+
+    let start = Instant::now();
+    clock.run_paced(&mut cpu, Some(30)).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(30));
+
+    clock.set_turbo(true);
+    clock.run_paced(&mut cpu, Some(100_000)).unwrap();
+    assert_eq!(clock.emulated_time(), Duration::from_millis(100_030));
+  }
+}
@@ -0,0 +1,232 @@
+//! Headless conformance runs. A ROM is run for a fixed number of
+//! instructions with a fixed seed and no input, and the final state is
+//! compared to a `Golden` recorded from a run that is known to be right.
+//!
+//! Goldens are stored as text, one field per line:
+//!
+//! ```text
+//! profile chip8
+//! cycles 500
+//! halted yes
+//! pc 0x21e
+//! i 0x05f
+//! v 05 00 00 00 00 00 00 00 00 00 00 00 00 00 00 01
+//! screen 64x32 0x1f0e3d2c4b5a6978
+//! ```
+//!
+//! The screen is compared by `Display::fingerprint`, so a golden stays a few
+//! lines long whatever the resolution.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::clock::Clock;
+use crate::{assemble, CpuFault, Profile, CPU};
+
+/// The seed every conformance run uses for `CXNN`.
+pub const SEED: u64 = 1;
+
+/// Reads a ROM, assembling it first if it is a `.asm` source.
+pub fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+  let bytes = fs::read(path).map_err(|err| format!("unable to read {}: {}", path.display(), err))?;
+  if path.extension().is_some_and(|ext| ext == "asm") {
+    let source = String::from_utf8(bytes).map_err(|_| format!("{} is not UTF-8", path.display()))?;
+    return assemble(&source).map_err(|err| format!("{}: {}", path.display(), err));
+  }
+  Ok(bytes)
+}
+
+/// Runs `rom` on a fresh `profile` machine for up to `cycles` instructions,
+/// at the default clock rate so that the timers tick as they would in real
+/// time. Returns the final state and whether the program halted.
+pub fn run_headless(rom: &[u8], profile: Profile, cycles: u64) -> Result<(CPU, bool), String> {
+  let mut cpu = CPU::with_profile(profile, SEED);
+  cpu.load_rom(rom).map_err(|err| err.to_string())?;
+
+  let mut clock = Clock::default();
+  match clock.run_cycles(&mut cpu, cycles) {
+    Ok(running) => Ok((cpu, !running)),
+    Err(fault) => Err(fault_message(fault, clock.cycles())),
+  }
+}
+
+fn fault_message(fault: CpuFault, executed: u64) -> String {
+  format!("{} after {} instructions", fault, executed)
+}
+
+/// The expected outcome of a headless run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Golden {
+  pub profile: Profile,
+  pub cycles: u64,
+  pub halted: bool,
+  pub program_counter: usize,
+  pub index_register: u16,
+  pub registers: [u8; 16],
+  pub width: usize,
+  pub height: usize,
+  pub fingerprint: u64,
+}
+
+impl Golden {
+  /// Runs `rom` and records how it ends.
+  pub fn record(rom: &[u8], profile: Profile, cycles: u64) -> Result<Golden, String> {
+    let (cpu, halted) = run_headless(rom, profile, cycles)?;
+    Ok(Golden::of(&cpu, profile, cycles, halted))
+  }
+
+  fn of(cpu: &CPU, profile: Profile, cycles: u64, halted: bool) -> Golden {
+    Golden {
+      profile,
+      cycles,
+      halted,
+      program_counter: cpu.program_counter,
+      index_register: cpu.index_register,
+      registers: cpu.registers,
+      width: cpu.display.width(),
+      height: cpu.display.height(),
+      fingerprint: cpu.display.fingerprint(),
+    }
+  }
+
+  /// Runs `rom` as the golden says and lists every way the outcome
+  /// differs, with the final screen appended if it is one of them.
+  pub fn check(&self, rom: &[u8]) -> Result<(), Vec<String>> {
+    let (cpu, halted) = run_headless(rom, self.profile, self.cycles).map_err(|err| vec![err])?;
+    let actual = Golden::of(&cpu, self.profile, self.cycles, halted);
+
+    let mut differences = vec![];
+    let mut compare = |field: &str, expected: String, actual: String| {
+      if expected != actual {
+        differences.push(format!("{}: expected {}, got {}", field, expected, actual));
+      }
+    };
+
+    compare("halted", yes_no(self.halted).to_string(), yes_no(actual.halted).to_string());
+    compare("pc", format!("{:#05x}", self.program_counter), format!("{:#05x}", actual.program_counter));
+    compare("i", format!("{:#05x}", self.index_register), format!("{:#05x}", actual.index_register));
+    for (x, (expected, actual)) in self.registers.iter().zip(&actual.registers).enumerate() {
+      compare(&format!("V{:X}", x), format!("{:#04x}", expected), format!("{:#04x}", actual));
+    }
+    let screen = |golden: &Golden| format!("{}x{} {:#018x}", golden.width, golden.height, golden.fingerprint);
+    let screen_differs = screen(self) != screen(&actual);
+    compare("screen", screen(self), screen(&actual));
+
+    if screen_differs {
+      differences.push(format!("final screen:\n{}", cpu.display.to_text()));
+    }
+
+    if differences.is_empty() { Ok(()) } else { Err(differences) }
+  }
+}
+
+fn yes_no(flag: bool) -> &'static str {
+  if flag { "yes" } else { "no" }
+}
+
+impl fmt::Display for Golden {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "profile {}", self.profile)?;
+    writeln!(f, "cycles {}", self.cycles)?;
+    writeln!(f, "halted {}", yes_no(self.halted))?;
+    writeln!(f, "pc {:#05x}", self.program_counter)?;
+    writeln!(f, "i {:#05x}", self.index_register)?;
+    let registers: Vec<String> = self.registers.iter().map(|v| format!("{:02x}", v)).collect();
+    writeln!(f, "v {}", registers.join(" "))?;
+    writeln!(f, "screen {}x{} {:#018x}", self.width, self.height, self.fingerprint)
+  }
+}
+
+fn number(text: &str) -> Result<u64, String> {
+  match text.strip_prefix("0x") {
+    Some(hex) => u64::from_str_radix(hex, 16),
+    None => text.parse(),
+  }.map_err(|_| format!("invalid number {:?}", text))
+}
+
+impl FromStr for Golden {
+  type Err = String;
+
+  fn from_str(text: &str) -> Result<Self, Self::Err> {
+    let mut fields = HashMap::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+      let (key, value) = line.split_once(' ').ok_or_else(|| format!("expected KEY VALUE, got {:?}", line))?;
+      if fields.insert(key, value.trim()).is_some() {
+        return Err(format!("{} is given twice", key));
+      }
+    }
+    let field = |key: &str| fields.get(key).copied().ok_or_else(|| format!("missing {}", key));
+
+    let registers: Vec<u8> = field("v")?
+      .split_whitespace()
+      .map(|v| u8::from_str_radix(v, 16).map_err(|_| format!("invalid register value {:?}", v)))
+      .collect::<Result<_, _>>()?;
+    let registers = registers.try_into().map_err(|_| String::from("v needs 16 values"))?;
+
+    let (size, fingerprint) = field("screen")?.split_once(' ').ok_or("screen needs a size and a hash")?;
+    let (width, height) = size.split_once('x').ok_or("screen size should be WIDTHxHEIGHT")?;
+
+    Ok(Golden {
+      profile: field("profile")?.parse()?,
+      cycles: number(field("cycles")?)?,
+      halted: match field("halted")? {
+        "yes" => true,
+        "no" => false,
+        other => return Err(format!("halted should be yes or no, got {:?}", other)),
+      },
+      program_counter: number(field("pc")?)? as usize,
+      index_register: number(field("i")?)? as u16,
+      registers,
+      width: number(width)? as usize,
+      height: number(height)? as usize,
+      fingerprint: number(fingerprint)?,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assemble;
+
+  #[test]
+  fn goldens_round_trip_through_text() {
+    let rom = assemble("LD V3, 7\nLD F, V3\nDRW V0, V0, 5\nHALT").unwrap();
+    let golden = Golden::record(&rom, Profile::Chip8, 100).unwrap();
+
+    assert!(golden.halted);
+    assert_eq!(golden.registers[3], 7);
+    assert_eq!(golden.to_string().parse(), Ok(golden.clone()));
+    assert_eq!(golden.check(&rom), Ok(()));
+  }
+
+  #[test]
+  fn mismatches_are_listed() {
+    let rom = assemble("LD V3, 7\nLD F, V3\nDRW V0, V0, 5\nHALT").unwrap();
+    let mut golden = Golden::record(&rom, Profile::Chip8, 100).unwrap();
+    golden.registers[3] = 8;
+    golden.fingerprint ^= 1;
+
+    let differences = golden.check(&rom).unwrap_err();
+    assert_eq!(differences[0], "V3: expected 0x08, got 0x07");
+    assert!(differences[1].starts_with("screen: expected 64x32 "));
+    assert!(differences[2].starts_with("final screen:\n####"));
+
+    let faulting = assemble("RET").unwrap();
+    assert_eq!(
+      golden.check(&faulting),
+      Err(vec![String::from("stack underflow at 0x200 (00ee) after 0 instructions")]),
+    );
+  }
+
+  #[test]
+  fn damaged_goldens_are_rejected() {
+    let text = Golden::record(&[0, 0], Profile::Chip8, 1).unwrap().to_string();
+
+    assert_eq!(text.replace("cycles", "cycle").parse::<Golden>(), Err(String::from("missing cycles")));
+    assert_eq!(text.replace(" 00\n", "\n").parse::<Golden>(), Err(String::from("v needs 16 values")));
+  }
+}
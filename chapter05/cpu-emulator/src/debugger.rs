@@ -0,0 +1,473 @@
+//! A command-driven step debugger. `Debugger::command` takes one line of
+//! input, so the same commands work from the `chip8-debug` prompt and from
+//! tests. It drives any `isa::Executor`, CHIP-8 or otherwise.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::clock::{Clock, Clocked};
+use crate::isa::{self, Decoder, Executor};
+use crate::{CpuFault, CPU};
+
+/// How many instructions `continue` runs at most without a count, so that a
+/// program that loops forever, as most do, hands control back.
+const CONTINUE_LIMIT: u64 = 1_000_000;
+
+const HELP: &str = "\
+commands:
+  s, step [N]           execute N instructions (default 1)
+  c, continue [N]       run until a breakpoint, watchpoint, fault or HALT,
+                        or for at most N instructions (default 1000000)
+  b, break [ADDR]       set a breakpoint, or list them
+  d, delete ADDR        remove a breakpoint
+  w, watch REG|ADDR     stop when a register or memory byte changes
+  unwatch REG|ADDR      remove a watchpoint
+  r, regs               dump registers and the stack
+  m, mem ADDR [LEN]     dump LEN bytes of memory (default 64)
+  l, list [ADDR [N]]    disassemble N instructions (default: 8 from PC)
+  trace on|off          log every executed instruction
+  press K, release K    hold or let go of key K
+  q, quit               leave the debugger
+An empty line repeats the previous command. Numbers are decimal or 0x hex.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Watch {
+  /// A register, by one of the names `Executor::registers` gives.
+  Register(&'static str),
+  Memory(usize),
+}
+
+impl fmt::Display for Watch {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Watch::Register(name) => write!(f, "{}", name),
+      Watch::Memory(address) => write!(f, "[{:03x}]", address),
+    }
+  }
+}
+
+/// Why execution stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop<F = CpuFault> {
+  /// The requested number of instructions ran.
+  Stepped,
+  Breakpoint(usize),
+  Watchpoint { watch: Watch, old: u64, new: u64 },
+  Halted,
+  /// The instruction at the program counter faulted and was not executed.
+  Fault(F),
+}
+
+impl<F: fmt::Display> fmt::Display for Stop<F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Stop::Stepped => write!(f, "stepped"),
+      Stop::Breakpoint(address) => write!(f, "breakpoint at {:03x}", address),
+      Stop::Watchpoint { watch, old, new } => {
+        write!(f, "watchpoint {} changed from {:02x} to {:02x}", watch, old, new)
+      },
+      Stop::Halted => write!(f, "program halted"),
+      Stop::Fault(fault) => write!(f, "{}", fault),
+    }
+  }
+}
+
+pub struct Debugger<M = CPU> {
+  pub cpu: M,
+  breakpoints: BTreeSet<usize>,
+  /// Each watchpoint with the value it had after the last instruction.
+  watches: Vec<(Watch, u64)>,
+  trace: bool,
+  halted: bool,
+  /// Runs the timers at 60 Hz of emulated time; the debugger never waits.
+  pub clock: Clock,
+  last_command: String,
+}
+
+/// Registers are listed with the machine's `Display`, and the clock ticks
+/// its timers.
+impl<M> Debugger<M>
+where
+  M: Executor + Clocked<Fault = <M as Executor>::Fault> + fmt::Display,
+{
+  pub fn new(cpu: M) -> Self {
+    Debugger {
+      cpu,
+      breakpoints: BTreeSet::new(),
+      watches: vec![],
+      trace: false,
+      halted: false,
+      clock: Clock::default(),
+      last_command: String::new(),
+    }
+  }
+
+  pub fn executed(&self) -> u64 {
+    self.clock.cycles()
+  }
+
+  pub fn add_breakpoint(&mut self, address: usize) {
+    self.breakpoints.insert(address);
+  }
+
+  pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+    self.breakpoints.remove(&address)
+  }
+
+  pub fn add_watch(&mut self, watch: Watch) {
+    if !self.watches.iter().any(|(w, _)| *w == watch) {
+      let value = self.watched_value(watch);
+      self.watches.push((watch, value));
+    }
+  }
+
+  pub fn remove_watch(&mut self, watch: Watch) -> bool {
+    let len = self.watches.len();
+    self.watches.retain(|(w, _)| *w != watch);
+    self.watches.len() != len
+  }
+
+  fn watched_value(&self, watch: Watch) -> u64 {
+    match watch {
+      Watch::Register(name) => {
+        self.cpu.registers().into_iter().find(|(n, _)| *n == name).map_or(0, |(_, value)| value)
+      },
+      Watch::Memory(address) => self.cpu.bus().peek(address).unwrap_or(0) as u64,
+    }
+  }
+
+  /// Executes a single instruction, writing it to `trace` if tracing is on.
+  fn execute_one(&mut self, trace: &mut dyn Write) -> io::Result<Option<Stop<<M as Executor>::Fault>>> {
+    if self.halted {
+      return Ok(Some(Stop::Halted));
+    }
+
+    if self.trace {
+      writeln!(trace, "{}", self.describe(self.cpu.pc()))?;
+    }
+
+    let running = match self.clock.step(&mut self.cpu) {
+      Ok(running) => running,
+      Err(fault) => return Ok(Some(Stop::Fault(fault))),
+    };
+
+    if !running {
+      self.halted = true;
+      return Ok(Some(Stop::Halted));
+    }
+
+    for i in 0..self.watches.len() {
+      let (watch, old) = self.watches[i];
+      let new = self.watched_value(watch);
+      if new != old {
+        self.watches[i].1 = new;
+        return Ok(Some(Stop::Watchpoint { watch, old, new }));
+      }
+    }
+
+    if self.breakpoints.contains(&self.cpu.pc()) {
+      return Ok(Some(Stop::Breakpoint(self.cpu.pc())));
+    }
+
+    Ok(None)
+  }
+
+  /// Executes up to `count` instructions, stopping early at breakpoints and
+  /// watchpoints.
+  pub fn step(&mut self, count: u64, trace: &mut dyn Write) -> io::Result<Stop<<M as Executor>::Fault>> {
+    for _ in 0..count {
+      if let Some(stop) = self.execute_one(trace)? {
+        return Ok(stop);
+      }
+    }
+    Ok(Stop::Stepped)
+  }
+
+  /// Runs until a breakpoint, watchpoint, fault or `HALT`, or until `limit`
+  /// instructions have run, which returns `Stop::Stepped`. A breakpoint at
+  /// the current address does not stop the first instruction.
+  pub fn resume(&mut self, limit: u64, trace: &mut dyn Write) -> io::Result<Stop<<M as Executor>::Fault>> {
+    self.step(limit, trace)
+  }
+
+  /// `addr: opcode  mnemonic`, marking breakpoints with `*`.
+  fn describe(&self, address: usize) -> String {
+    let marker = if self.breakpoints.contains(&address) { '*' } else { ' ' };
+    let cursor = if address == self.cpu.pc() { '>' } else { ' ' };
+
+    format!("{}{}{}", marker, cursor, isa::describe(&self.cpu, address))
+  }
+
+  fn dump_memory(&self, start: usize, len: usize, out: &mut dyn Write) -> Result<(), CommandError> {
+    let bus = self.cpu.bus();
+    if start >= bus.len() {
+      return Err(format!("{:#x} is past the end of memory", start).into());
+    }
+    let end = start.saturating_add(len).min(bus.len());
+
+    for row in (start..end).step_by(16) {
+      let hex: Vec<String> = (row..(row + 16).min(end))
+        .map(|address| format!("{:02x}", bus.peek(address).unwrap_or(0)))
+        .collect();
+      writeln!(out, "{:03x}: {}", row, hex.join(" "))?;
+    }
+    Ok(())
+  }
+
+  /// Runs one line of debugger input. Returns `false` once the user quits.
+  pub fn command(&mut self, line: &str, out: &mut dyn Write) -> io::Result<bool> {
+    let line = match line.trim() {
+      "" => self.last_command.clone(),
+      line => line.to_string(),
+    };
+    self.last_command = line.clone();
+
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, args) = match words.split_first() {
+      Some((name, args)) => (*name, args),
+      None => return Ok(true),
+    };
+
+    match self.run_command(name, args, out) {
+      Ok(()) => {},
+      Err(CommandError::Usage(message)) => writeln!(out, "{}", message)?,
+      Err(CommandError::Io(err)) => return Err(err),
+    }
+    Ok(!matches!(name, "q" | "quit"))
+  }
+
+  fn run_command(&mut self, name: &str, args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let number = |i: usize| -> Result<usize, String> {
+      let arg = args.get(i).ok_or_else(|| format!("{} needs more arguments, see help", name))?;
+      parse_number(arg)
+    };
+
+    match (name, args.len()) {
+      ("s" | "step", 0 | 1) => {
+        let count = if args.is_empty() { 1 } else { number(0)? as u64 };
+        let stop = self.step(count, out)?;
+        if !matches!(stop, Stop::Stepped) {
+          writeln!(out, "{}", stop)?;
+        }
+        writeln!(out, "{}", self.describe(self.cpu.pc()))?;
+      },
+      ("c" | "continue", 0 | 1) => {
+        let limit = if args.is_empty() { CONTINUE_LIMIT } else { number(0)? as u64 };
+        match self.resume(limit, out)? {
+          Stop::Stepped => writeln!(out, "still running after {} instructions", self.executed())?,
+          stop => writeln!(out, "{} after {} instructions", stop, self.executed())?,
+        }
+        writeln!(out, "{}", self.describe(self.cpu.pc()))?;
+      },
+      ("b" | "break", 0) => {
+        for address in &self.breakpoints {
+          writeln!(out, "{}", self.describe(*address))?;
+        }
+      },
+      ("b" | "break", 1) => self.add_breakpoint(number(0)?),
+      ("d" | "delete", 1) => {
+        if !self.remove_breakpoint(number(0)?) {
+          return Err(CommandError::from("no breakpoint there"));
+        }
+      },
+      ("w" | "watch", 1) => self.add_watch(self.parse_watch(args[0])?),
+      ("unwatch", 1) => {
+        if !self.remove_watch(self.parse_watch(args[0])?) {
+          return Err(CommandError::from("no such watchpoint"));
+        }
+      },
+      ("r" | "regs", 0) => writeln!(out, "{}", self.cpu)?,
+      ("m" | "mem", 1 | 2) => {
+        let len = if args.len() == 2 { number(1)? } else { 64 };
+        self.dump_memory(number(0)?, len, out)?;
+      },
+      ("l" | "list", 0..=2) => {
+        let start = if args.is_empty() { self.cpu.pc() } else { number(0)? };
+        let count = if args.len() == 2 { number(1)? } else { 8 };
+        let mut address = start;
+        for _ in 0..count {
+          writeln!(out, "{}", self.describe(address))?;
+          let len = isa::instruction_at(&self.cpu, address).map_or(M::Decoder::UNIT, |(_, len)| len);
+          address = address.saturating_add(len);
+        }
+      },
+      ("trace", 1) => match args[0] {
+        "on" => self.trace = true,
+        "off" => self.trace = false,
+        _ => return Err(CommandError::from("trace takes on or off")),
+      },
+      ("press" | "release", 1) => self.cpu.set_key(number(0)?, name == "press")?,
+      ("h" | "help", 0) => writeln!(out, "{}", HELP)?,
+      ("q" | "quit", 0) => {},
+      _ => return Err(format!("unknown command {:?}, try help", args_line(name, args)).into()),
+    }
+
+    Ok(())
+  }
+
+  /// A register name, in any case, or a memory address.
+  fn parse_watch(&self, text: &str) -> Result<Watch, String> {
+    let registers = self.cpu.registers();
+    if let Some((name, _)) = registers.iter().find(|(name, _)| name.eq_ignore_ascii_case(text)) {
+      return Ok(Watch::Register(name));
+    }
+
+    match parse_number(text)? {
+      address if address < self.cpu.bus().len() => Ok(Watch::Memory(address)),
+      _ => Err(format!("{} is outside memory", text)),
+    }
+  }
+}
+
+/// A command either fails to write its output or is given bad input, which
+/// is reported back at the prompt.
+enum CommandError {
+  Io(io::Error),
+  Usage(String),
+}
+
+impl From<io::Error> for CommandError {
+  fn from(err: io::Error) -> Self {
+    CommandError::Io(err)
+  }
+}
+
+impl From<String> for CommandError {
+  fn from(message: String) -> Self {
+    CommandError::Usage(message)
+  }
+}
+
+impl From<&str> for CommandError {
+  fn from(message: &str) -> Self {
+    CommandError::Usage(message.to_string())
+  }
+}
+
+fn args_line(name: &str, args: &[&str]) -> String {
+  std::iter::once(name).chain(args.iter().copied()).collect::<Vec<_>>().join(" ")
+}
+
+fn parse_number(text: &str) -> Result<usize, String> {
+  match text.strip_prefix("0x") {
+    Some(hex) => usize::from_str_radix(hex, 16),
+    None => text.parse(),
+  }.map_err(|_| format!("invalid number {:?}", text))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assemble;
+
+  fn debugger(source: &str) -> Debugger {
+    let mut cpu = CPU::with_seed(1);
+    cpu.load_rom(&assemble(source).unwrap()).unwrap();
+    Debugger::new(cpu)
+  }
+
+  fn run(debugger: &mut Debugger, commands: &str) -> String {
+    let mut out = vec![];
+    for line in commands.lines() {
+      debugger.command(line, &mut out).unwrap();
+    }
+    String::from_utf8(out).unwrap()
+  }
+
+  const COUNTER: &str = "
+          LD  V0, 0
+          LD  I, 0x300
+    loop: ADD V0, 1
+          LD  B, V0
+          SE  V0, 3
+          JP  loop
+          HALT
+  ";
+
+  #[test]
+  fn breakpoints_stop_execution() {
+    let mut dbg = debugger(COUNTER);
+    let out = run(&mut dbg, "b 0x206\nc\nc\n");
+
+    assert_eq!(dbg.cpu.program_counter, 0x206);
+    assert_eq!(dbg.cpu.registers[0], 2);
+    assert!(out.contains("breakpoint at 206 after 7 instructions"), "{}", out);
+
+    let out = run(&mut dbg, "d 0x206\nc");
+    assert!(out.contains("program halted"), "{}", out);
+    assert_eq!(dbg.cpu.registers[0], 3);
+
+    let out = run(&mut dbg, "s");
+    assert!(out.starts_with("program halted"), "{}", out);
+  }
+
+  #[test]
+  fn continuing_stops_after_a_limit() {
+    let mut dbg = debugger("loop: JP loop");
+    let out = run(&mut dbg, "c 100\nc");
+    let lines: Vec<&str> = out.lines().collect();
+
+    assert_eq!(lines[0], "still running after 100 instructions");
+    assert_eq!(lines[1], " >200: 1200  JP 0x200");
+    assert_eq!(lines[2], format!("still running after {} instructions", 100 + CONTINUE_LIMIT));
+  }
+
+  #[test]
+  fn faults_stop_before_the_instruction() {
+    let mut dbg = debugger("LD V0, 1\nRET");
+    let out = run(&mut dbg, "c\ns");
+
+    assert!(out.contains("stack underflow at 0x202 (00ee) after 1 instructions"), "{}", out);
+    assert!(out.contains("stack underflow at 0x202 (00ee)\n >202: 00ee  RET"), "{}", out);
+    assert_eq!(dbg.executed(), 1);
+  }
+
+  #[test]
+  fn watchpoints_report_changes() {
+    let mut dbg = debugger(COUNTER);
+    let out = run(&mut dbg, "w 0x302\nc\nunwatch 0x302\nw V0\nc");
+
+    assert!(out.contains("watchpoint [302] changed from 00 to 01"), "{}", out);
+    assert!(out.contains("watchpoint V0 changed from 01 to 02"), "{}", out);
+  }
+
+  #[test]
+  fn step_trace_and_dumps() {
+    let mut dbg = debugger(COUNTER);
+    let out = run(&mut dbg, "trace on\ns 2\n\nm 0x200 4\nl 0x204 1\nbogus");
+    let lines: Vec<&str> = out.lines().collect();
+
+    assert_eq!(lines[0], " >200: 6000  LD V0, 0x00");
+    assert_eq!(lines[1], " >202: a300  LD I, 0x300");
+    assert_eq!(lines[2], " >204: 7001  ADD V0, 0x01");
+    assert_eq!(lines[5], " >208: 3003  SE V0, 0x03");
+    assert_eq!(lines[6], "200: 60 00 a3 00");
+    assert_eq!(lines[7], "  204: 7001  ADD V0, 0x01");
+    assert_eq!(lines[8], "unknown command \"bogus\", try help");
+    assert_eq!(dbg.executed(), 4);
+  }
+
+  #[test]
+  fn dumps_stop_at_the_end_of_memory() {
+    let mut dbg = debugger(COUNTER);
+    let out = run(&mut dbg, "m 0xffe 0xffffffffffffffff\nm 0x1000\nm 0xffffffffffffffff 1");
+    let lines: Vec<&str> = out.lines().collect();
+
+    assert_eq!(lines, ["ffe: 00 00", "0x1000 is past the end of memory", "0xffffffffffffffff is past the end of memory"]);
+  }
+
+  #[test]
+  fn listings_stop_at_the_end_of_memory() {
+    let mut dbg = debugger(COUNTER);
+    let out = run(&mut dbg, "l 0xffe 2\nl 0xffffffffffffffff 2");
+    let lines: Vec<&str> = out.lines().collect();
+
+    assert_eq!(lines, [
+      "  ffe: 0000  HALT",
+      "  1000: end of memory",
+      "  ffffffffffffffff: end of memory",
+      "  ffffffffffffffff: end of memory",
+    ]);
+  }
+}
@@ -0,0 +1,124 @@
+//! Turns ROM images and memory back into assembler source.
+//!
+//! Each line carries its address and raw bytes as a comment, and jump, call
+//! and `LD I` targets inside the listing get labels, so feeding the listing
+//! to `assemble_at` with the same origin reproduces the input byte for byte.
+//! Words outside the instruction set come out as `DW` and a trailing odd
+//! byte as `DB`.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::instruction::Instruction;
+use crate::CPU;
+
+fn label(address: u16) -> String {
+  format!("L_{:03x}", address)
+}
+
+/// Disassembles `image`, whose first byte lives at `origin`. Bytes that
+/// would lie past the 64 KiB address space are left out.
+pub fn disassemble(image: &[u8], origin: u16) -> String {
+  let image = &image[..image.len().min(0x10000 - origin as usize)];
+
+  // A linear sweep: each line is an instruction, or a word or byte that is
+  // not one.
+  let mut lines: Vec<(u16, &[u8], Option<Instruction>)> = vec![];
+  let mut offset = 0;
+  while offset < image.len() {
+    let instruction = Instruction::decode_from(&image[offset..]);
+    let len = instruction.map_or(2, |instruction| instruction.len()).min(image.len() - offset);
+    lines.push((origin + offset as u16, &image[offset..offset + len], instruction));
+    offset += len;
+  }
+
+  // Only addresses that start a line can carry a label.
+  let targets: BTreeSet<u16> = lines.iter()
+    .filter_map(|(_, _, instruction)| instruction.as_ref()?.address())
+    .filter(|target| lines.iter().any(|(address, _, _)| address == target))
+    .collect();
+
+  let mut listing = String::new();
+
+  for (address, bytes, instruction) in &lines {
+    let mut text = String::new();
+    let _ = match (instruction, bytes) {
+      (Some(instruction), _) => {
+        let target = instruction.address().filter(|t| targets.contains(t)).map(label);
+        instruction.write_with_label(&mut text, target.as_deref())
+      },
+      (None, [hi, lo]) => write!(text, "DW 0x{:02x}{:02x}", hi, lo),
+      (None, [byte]) => write!(text, "DB 0x{:02x}", byte),
+      _ => unreachable!("non-instructions are one or two bytes"),
+    };
+
+    let name = if targets.contains(address) { label(*address) + ":" } else { String::new() };
+    let raw: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let _ = writeln!(listing, "{:<8}{:<20}; {:03x}: {}", name, text, address, raw);
+  }
+
+  listing
+}
+
+impl CPU {
+  /// Disassembles `range` of memory. Out-of-bounds ranges are clamped.
+  pub fn disassemble(&self, range: Range<usize>) -> String {
+    let end = range.end.min(self.memory.len());
+    let start = range.start.min(end);
+    disassemble(&self.memory[start..end], start as u16)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{assemble, assemble_at, PROGRAM_START};
+
+  #[test]
+  fn listing_is_annotated_and_labelled() {
+    let rom = assemble("
+            LD   V0, 5
+      loop: CALL double
+            SE   V0, 40
+            JP   loop
+            HALT
+      double:
+            ADD  V0, V0
+            RET
+            DB   0x51, 0x21, 0x07
+    ").unwrap();
+
+    let listing = disassemble(&rom, PROGRAM_START as u16);
+    let lines: Vec<&str> = listing.lines().collect();
+
+    assert_eq!(lines[0], "        LD V0, 0x05         ; 200: 6005");
+    assert_eq!(lines[1], "L_202:  CALL L_20a          ; 202: 220a");
+    assert_eq!(lines[3], "        JP L_202            ; 206: 1202");
+    assert_eq!(lines[7], "        DW 0x5121           ; 20e: 5121");
+    assert_eq!(lines[8], "        DB 0x07             ; 210: 07");
+
+    assert_eq!(assemble(&listing).unwrap(), rom);
+  }
+
+  #[test]
+  fn memory_round_trips() {
+    let mut cpu = CPU::with_seed(1);
+    for (i, byte) in cpu.memory.iter_mut().enumerate() {
+      *byte = (i * 37 % 251) as u8;
+    }
+
+    let listing = cpu.disassemble(0x300..0x400);
+    assert_eq!(assemble_at(&listing, 0x300).unwrap(), &cpu.memory[0x300..0x400]);
+
+    assert!(cpu.disassemble(0xffe..0x2000).starts_with("        "));
+  }
+
+  #[test]
+  fn images_past_the_address_space_are_cut_short() {
+    let listing = disassemble(&vec![0; 70_000], PROGRAM_START as u16);
+    assert_eq!(listing.lines().count(), (0x10000 - PROGRAM_START) / 2);
+    assert!(listing.ends_with("; fffe: 0000\n"));
+  }
+}
@@ -0,0 +1,314 @@
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+/// Where the built-in hex digit sprites live in memory. Programs find them
+/// through `FX29` rather than by address, so this is only a convention.
+pub const FONT_START: usize = 0x050;
+pub const FONT_SPRITE_LEN: usize = 5;
+
+pub const FONT: [u8; 16 * FONT_SPRITE_LEN] = [
+  0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+  0x20, 0x60, 0x20, 0x20, 0x70, // 1
+  0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+  0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
+  0x90, 0x90, 0xf0, 0x10, 0x10, // 4
+  0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+  0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+  0xf0, 0x10, 0x20, 0x40, 0x40, // 7
+  0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+  0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+  0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+  0xe0, 0x90, 0xe0, 0x90, 0xe0, // B
+  0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+  0xe0, 0x90, 0x90, 0x90, 0xe0, // D
+  0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+  0xf0, 0x80, 0xf0, 0x80, 0x80, // F
+];
+
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// The SUPER-CHIP 8x10 digit sprites, found through `FX30`. XO-CHIP adds
+/// A to F.
+pub const BIG_FONT_START: usize = FONT_START + FONT.len();
+pub const BIG_FONT_SPRITE_LEN: usize = 10;
+
+pub const BIG_FONT: [u8; 16 * BIG_FONT_SPRITE_LEN] = [
+  0xff, 0xff, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff, // 0
+  0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xff, 0xff, // 1
+  0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, // 2
+  0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff, // 3
+  0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0x03, 0x03, // 4
+  0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff, // 5
+  0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, // 6
+  0xff, 0xff, 0x03, 0x03, 0x06, 0x0c, 0x18, 0x18, 0x18, 0x18, // 7
+  0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, // 8
+  0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff, // 9
+  0x7e, 0xff, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xc3, // A
+  0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc, // B
+  0x3c, 0xff, 0xc3, 0xc0, 0xc0, 0xc0, 0xc0, 0xc3, 0xff, 0x3c, // C
+  0xfc, 0xfe, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xfe, 0xfc, // D
+  0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, // E
+  0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xc0, 0xc0, // F
+];
+
+/// XO-CHIP draws on two bit planes, giving four colours. Plain CHIP-8 and
+/// SUPER-CHIP programs only ever touch the first.
+pub const PLANES: u8 = 2;
+
+/// A framebuffer whose pixels are only ever toggled, by XOR-ing sprites onto
+/// the screen. Each pixel holds one bit per plane.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Display {
+  pub(crate) width: usize,
+  pub(crate) height: usize,
+  pub(crate) pixels: Vec<u8>,
+}
+
+impl Default for Display {
+  fn default() -> Self {
+    Display::new(WIDTH, HEIGHT)
+  }
+}
+
+impl Display {
+  /// A blank screen of `width` by `height` pixels.
+  ///
+  /// # Panics
+  ///
+  /// If either side is zero, as nothing could be drawn or shown.
+  pub fn new(width: usize, height: usize) -> Self {
+    assert!(width > 0 && height > 0, "a {}x{} screen has no pixels", width, height);
+    Display { width, height, pixels: vec![0; width * height] }
+  }
+
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  pub fn is_hires(&self) -> bool {
+    self.width == HIRES_WIDTH
+  }
+
+  /// Switches between the 64x32 and 128x64 modes, clearing the screen.
+  pub fn set_hires(&mut self, hires: bool) {
+    *self = if hires { Display::new(HIRES_WIDTH, HIRES_HEIGHT) } else { Display::default() };
+  }
+
+  /// Whether the pixel is lit on any plane.
+  pub fn pixel(&self, x: usize, y: usize) -> bool {
+    self.planes(x, y) != 0
+  }
+
+  /// The pixel's plane bits: bit 0 for the first plane, bit 1 for the
+  /// second.
+  pub fn planes(&self, x: usize, y: usize) -> u8 {
+    self.pixels[y * self.width + x]
+  }
+
+  pub fn clear(&mut self) {
+    self.pixels.fill(0);
+  }
+
+  /// Clears only the planes in the `planes` mask.
+  pub fn clear_planes(&mut self, planes: u8) {
+    for pixel in &mut self.pixels {
+      *pixel &= !planes;
+    }
+  }
+
+  /// Moves the planes in `planes` by (`dx`, `dy`), filling the space left
+  /// behind with dark pixels.
+  pub fn scroll(&mut self, dx: isize, dy: isize, planes: u8) {
+    let old = self.pixels.clone();
+    let (width, height) = (self.width as isize, self.height as isize);
+
+    for y in 0..height {
+      for x in 0..width {
+        let (sx, sy) = (x - dx, y - dy);
+        let moved = if (0..width).contains(&sx) && (0..height).contains(&sy) {
+          old[(sy * width + sx) as usize] & planes
+        } else {
+          0
+        };
+        let pixel = &mut self.pixels[(y * width + x) as usize];
+        *pixel = *pixel & !planes | moved;
+      }
+    }
+  }
+
+  /// XORs an 8-pixel-wide sprite onto the first plane. The origin wraps
+  /// around the edges but the sprite itself is clipped. Returns whether any
+  /// lit pixel was turned off, which programs use for collision detection.
+  pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+    self.draw(x, y, sprite, 8, 1, false)
+  }
+
+  /// XORs a sprite `width` pixels wide (8 or 16, with rows of one or two
+  /// bytes) onto `plane`. With `wrap` the parts of the sprite that run off
+  /// an edge reappear on the opposite one.
+  pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8], width: usize, plane: u8, wrap: bool) -> bool {
+    let x = x % self.width;
+    let y = y % self.height;
+    let row_len = width / 8;
+    let mut collision = false;
+
+    for (row, bytes) in sprite.chunks(row_len).enumerate() {
+      let mut py = y + row;
+      if py >= self.height {
+        if !wrap {
+          break;
+        }
+        py %= self.height;
+      }
+
+      let bits = bytes.iter().fold(0u32, |bits, byte| bits << 8 | *byte as u32);
+      for column in 0..width {
+        let mut px = x + column;
+        if px >= self.width {
+          if !wrap {
+            break;
+          }
+          px %= self.width;
+        }
+
+        if bits & 1 << (width - 1 - column) != 0 {
+          let pixel = &mut self.pixels[py * self.width + px];
+          collision |= *pixel & plane != 0;
+          *pixel ^= plane;
+        }
+      }
+    }
+
+    collision
+  }
+
+  /// A 64-bit FNV-1a hash of the size and every pixel's planes, for
+  /// comparing screens against golden values without storing them.
+  pub fn fingerprint(&self) -> u64 {
+    let size = [(self.width as u16).to_be_bytes(), (self.height as u16).to_be_bytes()].concat();
+    size.iter().chain(&self.pixels).fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+      (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+  }
+
+  /// Renders the screen one line per row, with `.` for dark pixels and `#`
+  /// for lit ones. Pixels lit on the second plane only are `+`, and on both
+  /// `@`.
+  pub fn to_text(&self) -> String {
+    let mut text = String::with_capacity((self.width + 1) * self.height);
+
+    for row in self.pixels.chunks(self.width) {
+      for planes in row {
+        text.push(['.', '#', '+', '@'][*planes as usize & 3]);
+      }
+      text.push('\n');
+    }
+
+    text
+  }
+
+  /// Renders the screen as a plain (ASCII) PBM image, where 1 is black and
+  /// a pixel lit on any plane counts as black.
+  pub fn to_pbm(&self) -> String {
+    let mut pbm = format!("P1\n{} {}\n", self.width, self.height);
+
+    for row in self.pixels.chunks(self.width) {
+      let line: Vec<&str> = row.iter()
+        .map(|planes| if *planes != 0 { "1" } else { "0" })
+        .collect();
+      pbm.push_str(&line.join(" "));
+      pbm.push('\n');
+    }
+
+    pbm
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  #[should_panic(expected = "a 0x32 screen has no pixels")]
+  fn screens_need_pixels() {
+    Display::new(0, 32);
+  }
+
+  #[test]
+  fn drawing_twice_erases_and_collides() {
+    let mut display = Display::default();
+
+    assert!(!display.draw_sprite(0, 0, &[0xff]));
+    assert!(display.pixel(7, 0));
+    assert!(display.draw_sprite(4, 0, &[0xf0]));
+    assert!(!display.pixel(4, 0));
+    assert!(display.pixel(3, 0));
+  }
+
+  #[test]
+  fn origin_wraps_but_sprite_clips() {
+    let mut display = Display::default();
+
+    display.draw_sprite(WIDTH + 60, HEIGHT + 31, &[0xff, 0xff]);
+    assert!(display.pixel(63, 31));
+    assert!(!display.pixel(0, 0));
+    assert!(!display.pixel(60, 0));
+  }
+
+  #[test]
+  fn renders_text_and_pbm() {
+    let mut display = Display::new(4, 2);
+    display.draw_sprite(1, 1, &[0x80]);
+
+    assert_eq!(display.to_text(), "....\n.#..\n");
+    assert_eq!(display.to_pbm(), "P1\n4 2\n0 0 0 0\n0 1 0 0\n");
+  }
+
+  #[test]
+  fn wide_sprites_wrap_and_planes_are_separate() {
+    let mut display = Display::new(HIRES_WIDTH, HIRES_HEIGHT);
+
+    assert!(!display.draw(120, 63, &[0x80, 0x01, 0xff, 0xff], 16, 2, true));
+    assert_eq!(display.planes(120, 63), 2);
+    assert_eq!(display.planes(7, 63), 2);
+    assert!(display.pixel(7, 0));
+    assert!(!display.pixel(8, 0));
+    assert!(!display.draw(120, 63, &[0x80, 0x00], 16, 1, true));
+    assert_eq!(display.planes(120, 63), 3);
+
+    display.clear_planes(2);
+    assert_eq!(display.planes(120, 63), 1);
+    assert!(!display.pixel(7, 63));
+  }
+
+  #[test]
+  fn fingerprints_tell_screens_apart() {
+    let blank = Display::default();
+    let mut lit = Display::default();
+    lit.draw_sprite(0, 0, &[0x80]);
+
+    assert_eq!(blank.fingerprint(), Display::default().fingerprint());
+    assert_ne!(blank.fingerprint(), lit.fingerprint());
+    assert_ne!(blank.fingerprint(), Display::new(HIRES_WIDTH, HIRES_HEIGHT).fingerprint());
+    lit.draw(0, 0, &[0x80], 8, 3, false);
+    assert_ne!(blank.fingerprint(), lit.fingerprint());
+  }
+
+  #[test]
+  fn scrolling_moves_selected_planes() {
+    let mut display = Display::new(8, 4);
+    display.draw(0, 0, &[0xc0], 8, 1, false);
+    display.draw(0, 0, &[0x80], 8, 2, false);
+
+    display.scroll(1, 2, 1);
+    assert_eq!(display.to_text(), "+.......\n........\n.##.....\n........\n");
+
+    display.scroll(-4, 0, 3);
+    assert_eq!(display.to_text(), "........\n........\n........\n........\n");
+  }
+}
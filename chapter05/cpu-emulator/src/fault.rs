@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fmt;
+
+/// Why `CPU::step` could not execute an instruction. The CPU is left as it
+/// was before the faulting instruction, with the program counter pointing at
+/// it, so a host can inspect the state, patch it up and step again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuFault {
+  /// `CALL` with all 16 stack slots in use.
+  StackOverflow { pc: usize, opcode: u16 },
+  /// `RET` with nothing on the stack.
+  StackUnderflow { pc: usize, opcode: u16 },
+  /// `CALL` from the last word of XO-CHIP memory, whose return address does
+  /// not fit in a 16-bit stack slot.
+  ReturnAddressOutOfBounds { pc: usize, opcode: u16 },
+  /// An opcode outside the instruction set.
+  InvalidOpcode { pc: usize, opcode: u16 },
+  /// The program counter leaves no room for a two-byte opcode, so there is
+  /// none to report.
+  PcOutOfBounds { pc: usize },
+  /// The instruction would read or write memory through `I` past the end of
+  /// memory. `address` is where the access starts.
+  MemoryOutOfBounds { pc: usize, opcode: u16, address: usize },
+}
+
+impl CpuFault {
+  pub fn pc(&self) -> usize {
+    match *self {
+      CpuFault::StackOverflow { pc, .. }
+      | CpuFault::StackUnderflow { pc, .. }
+      | CpuFault::ReturnAddressOutOfBounds { pc, .. }
+      | CpuFault::InvalidOpcode { pc, .. }
+      | CpuFault::PcOutOfBounds { pc }
+      | CpuFault::MemoryOutOfBounds { pc, .. } => pc,
+    }
+  }
+
+  pub fn opcode(&self) -> Option<u16> {
+    match *self {
+      CpuFault::StackOverflow { opcode, .. }
+      | CpuFault::StackUnderflow { opcode, .. }
+      | CpuFault::ReturnAddressOutOfBounds { opcode, .. }
+      | CpuFault::InvalidOpcode { opcode, .. }
+      | CpuFault::MemoryOutOfBounds { opcode, .. } => Some(opcode),
+      CpuFault::PcOutOfBounds { .. } => None,
+    }
+  }
+}
+
+impl fmt::Display for CpuFault {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CpuFault::StackOverflow { .. } => write!(f, "stack overflow")?,
+      CpuFault::StackUnderflow { .. } => write!(f, "stack underflow")?,
+      CpuFault::ReturnAddressOutOfBounds { .. } => write!(f, "return address past the end of memory")?,
+      CpuFault::InvalidOpcode { .. } => write!(f, "invalid opcode")?,
+      CpuFault::PcOutOfBounds { .. } => write!(f, "program counter out of bounds")?,
+      CpuFault::MemoryOutOfBounds { address, .. } => {
+        write!(f, "memory access at {:#05x} runs past the end of memory", address)?
+      },
+    }
+
+    match self.opcode() {
+      Some(opcode) => write!(f, " at {:#05x} ({:04x})", self.pc(), opcode),
+      None => write!(f, " at {:#05x}", self.pc()),
+    }
+  }
+}
+
+impl Error for CpuFault {}
@@ -0,0 +1,488 @@
+use std::fmt;
+
+/// A CHIP-8 instruction. `x` and `y` name registers `V0` to `VF`, `addr`
+/// is a 12-bit address, `byte` an 8-bit immediate and `n` a 4-bit one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+  /// `0000`: stops the CPU. Not part of the original instruction set.
+  Halt,
+  /// `00E0`
+  Cls,
+  /// `00EE`
+  Ret,
+  /// `0NNN`: calls a machine-code routine on the original hardware; ignored.
+  Sys { addr: u16 },
+  /// `1NNN`
+  Jp { addr: u16 },
+  /// `2NNN`
+  Call { addr: u16 },
+  /// `3XNN`: skips the next instruction if `Vx == byte`.
+  SeByte { x: u8, byte: u8 },
+  /// `4XNN`: skips the next instruction if `Vx != byte`.
+  SneByte { x: u8, byte: u8 },
+  /// `5XY0`
+  SeReg { x: u8, y: u8 },
+  /// `6XNN`
+  LdByte { x: u8, byte: u8 },
+  /// `7XNN`: adds without touching `VF`.
+  AddByte { x: u8, byte: u8 },
+  /// `8XY0`
+  LdReg { x: u8, y: u8 },
+  /// `8XY1`
+  Or { x: u8, y: u8 },
+  /// `8XY2`
+  And { x: u8, y: u8 },
+  /// `8XY3`
+  Xor { x: u8, y: u8 },
+  /// `8XY4`: `VF` is set on carry.
+  AddReg { x: u8, y: u8 },
+  /// `8XY5`: `Vx = Vx - Vy`, `VF` is set when there is no borrow.
+  Sub { x: u8, y: u8 },
+  /// `8XY6`
+  Shr { x: u8, y: u8 },
+  /// `8XY7`: `Vx = Vy - Vx`, `VF` is set when there is no borrow.
+  Subn { x: u8, y: u8 },
+  /// `8XYE`
+  Shl { x: u8, y: u8 },
+  /// `9XY0`
+  SneReg { x: u8, y: u8 },
+  /// `ANNN`
+  LdI { addr: u16 },
+  /// `BNNN`: jumps to `addr + V0`, or `addr + VX` with the SUPER-CHIP jump
+  /// quirk.
+  JpV0 { addr: u16 },
+  /// `CXNN`: `Vx = random & byte`.
+  Rnd { x: u8, byte: u8 },
+  /// `DXYN`: draws the `n`-byte sprite at `I` at (`Vx`, `Vy`). On SUPER-CHIP
+  /// and XO-CHIP `n = 0` draws a 16x16 sprite.
+  Drw { x: u8, y: u8, n: u8 },
+  /// `EX9E`
+  Skp { x: u8 },
+  /// `EXA1`
+  Sknp { x: u8 },
+  /// `FX07`
+  LdVxDt { x: u8 },
+  /// `FX0A`
+  LdVxK { x: u8 },
+  /// `FX15`
+  LdDtVx { x: u8 },
+  /// `FX18`
+  LdStVx { x: u8 },
+  /// `FX1E`
+  AddI { x: u8 },
+  /// `FX29`
+  LdF { x: u8 },
+  /// `FX33`
+  LdB { x: u8 },
+  /// `FX55`: stores `V0` to `Vx` at `I`.
+  LdIVx { x: u8 },
+  /// `FX65`: loads `V0` to `Vx` from `I`.
+  LdVxI { x: u8 },
+
+  // SUPER-CHIP
+
+  /// `00CN`: scrolls the screen down `n` rows.
+  ScrollDown { n: u8 },
+  /// `00FB`: scrolls the screen right 4 pixels.
+  ScrollRight,
+  /// `00FC`: scrolls the screen left 4 pixels.
+  ScrollLeft,
+  /// `00FD`: leaves the interpreter, which here halts like `0000`.
+  Exit,
+  /// `00FE`: switches to the 64x32 mode.
+  Lores,
+  /// `00FF`: switches to the 128x64 mode.
+  Hires,
+  /// `FX30`: points `I` at the large font sprite for `Vx`.
+  LdHf { x: u8 },
+  /// `FX75`: stores `V0` to `Vx` in the flag registers.
+  LdRVx { x: u8 },
+  /// `FX85`: loads `V0` to `Vx` from the flag registers.
+  LdVxR { x: u8 },
+
+  // XO-CHIP
+
+  /// `00DN`: scrolls the screen up `n` rows.
+  ScrollUp { n: u8 },
+  /// `5XY2`: stores `Vx` to `Vy`, in either order, at `I` without changing
+  /// `I`.
+  SaveRange { x: u8, y: u8 },
+  /// `5XY3`: loads `Vx` to `Vy`, in either order, from `I` without changing
+  /// `I`.
+  LoadRange { x: u8, y: u8 },
+  /// `F000 NNNN`: loads a 16-bit address into `I`. The only four-byte
+  /// instruction.
+  LdILong { addr: u16 },
+  /// `FN01`: selects the bit planes that drawing, clearing and scrolling
+  /// affect.
+  Plane { n: u8 },
+  /// `F002`: loads the 16-byte audio pattern from `I`.
+  Audio,
+  /// `FX3A`: sets the audio pitch from `Vx`.
+  Pitch { x: u8 },
+}
+
+impl Instruction {
+  /// Decodes the instruction at the start of `bytes`, which may be two or,
+  /// for `F000 NNNN`, four bytes long.
+  pub fn decode_from(bytes: &[u8]) -> Option<Instruction> {
+    match bytes {
+      [0xf0, 0x00, hi, lo, ..] => Some(Instruction::LdILong { addr: u16::from_be_bytes([*hi, *lo]) }),
+      [hi, lo, ..] => Instruction::decode(u16::from_be_bytes([*hi, *lo])),
+      _ => None,
+    }
+  }
+
+  /// Decodes a two-byte `opcode` from any of the supported instruction
+  /// sets, returning `None` for opcodes outside them and for `F000`, which
+  /// needs the word after it (see `decode_from`).
+  /// `decode(op).map(|i| i.encode())` is always `Some(op)` or `None`.
+  pub fn decode(opcode: u16) -> Option<Instruction> {
+    use Instruction::*;
+
+    let c = ((opcode & 0xf000) >> 12) as u8;
+    let x = ((opcode & 0x0f00) >> 8) as u8;
+    let y = ((opcode & 0x00f0) >> 4) as u8;
+    let d = (opcode & 0x000f) as u8;
+
+    let byte = (opcode & 0x00ff) as u8;
+    let addr = opcode & 0x0fff;
+
+    let instruction = match (c, x, y, d) {
+      (0x0, 0x0, 0x0, 0x0) => Halt,
+      (0x0, 0x0, 0xe, 0x0) => Cls,
+      (0x0, 0x0, 0xe, 0xe) => Ret,
+      (0x0, 0x0, 0xc, _) => ScrollDown { n: d },
+      (0x0, 0x0, 0xd, _) => ScrollUp { n: d },
+      (0x0, 0x0, 0xf, 0xb) => ScrollRight,
+      (0x0, 0x0, 0xf, 0xc) => ScrollLeft,
+      (0x0, 0x0, 0xf, 0xd) => Exit,
+      (0x0, 0x0, 0xf, 0xe) => Lores,
+      (0x0, 0x0, 0xf, 0xf) => Hires,
+      (0x0, _, _, _) => Sys { addr },
+      (0x1, _, _, _) => Jp { addr },
+      (0x2, _, _, _) => Call { addr },
+      (0x3, _, _, _) => SeByte { x, byte },
+      (0x4, _, _, _) => SneByte { x, byte },
+      (0x5, _, _, 0x0) => SeReg { x, y },
+      (0x5, _, _, 0x2) => SaveRange { x, y },
+      (0x5, _, _, 0x3) => LoadRange { x, y },
+      (0x6, _, _, _) => LdByte { x, byte },
+      (0x7, _, _, _) => AddByte { x, byte },
+      (0x8, _, _, 0x0) => LdReg { x, y },
+      (0x8, _, _, 0x1) => Or { x, y },
+      (0x8, _, _, 0x2) => And { x, y },
+      (0x8, _, _, 0x3) => Xor { x, y },
+      (0x8, _, _, 0x4) => AddReg { x, y },
+      (0x8, _, _, 0x5) => Sub { x, y },
+      (0x8, _, _, 0x6) => Shr { x, y },
+      (0x8, _, _, 0x7) => Subn { x, y },
+      (0x8, _, _, 0xe) => Shl { x, y },
+      (0x9, _, _, 0x0) => SneReg { x, y },
+      (0xa, _, _, _) => LdI { addr },
+      (0xb, _, _, _) => JpV0 { addr },
+      (0xc, _, _, _) => Rnd { x, byte },
+      (0xd, _, _, _) => Drw { x, y, n: d },
+      (0xe, _, 0x9, 0xe) => Skp { x },
+      (0xe, _, 0xa, 0x1) => Sknp { x },
+      (0xf, 0x0, 0x0, 0x2) => Audio,
+      (0xf, _, 0x0, 0x1) => Plane { n: x },
+      (0xf, _, 0x0, 0x7) => LdVxDt { x },
+      (0xf, _, 0x0, 0xa) => LdVxK { x },
+      (0xf, _, 0x1, 0x5) => LdDtVx { x },
+      (0xf, _, 0x1, 0x8) => LdStVx { x },
+      (0xf, _, 0x1, 0xe) => AddI { x },
+      (0xf, _, 0x2, 0x9) => LdF { x },
+      (0xf, _, 0x3, 0x3) => LdB { x },
+      (0xf, _, 0x5, 0x5) => LdIVx { x },
+      (0xf, _, 0x6, 0x5) => LdVxI { x },
+      (0xf, _, 0x3, 0x0) => LdHf { x },
+      (0xf, _, 0x3, 0xa) => Pitch { x },
+      (0xf, _, 0x7, 0x5) => LdRVx { x },
+      (0xf, _, 0x8, 0x5) => LdVxR { x },
+      _ => return None,
+    };
+
+    Some(instruction)
+  }
+
+  /// The instruction's first, and usually only, word.
+  pub fn encode(&self) -> u16 {
+    use Instruction::*;
+
+    let xy = |c: u16, x: u8, y: u8, d: u16| c << 12 | (x as u16) << 8 | (y as u16) << 4 | d;
+    let xkk = |c: u16, x: u8, kk: u8| c << 12 | (x as u16) << 8 | kk as u16;
+    let nnn = |c: u16, addr: u16| c << 12 | (addr & 0x0fff);
+
+    match *self {
+      Halt => 0x0000,
+      Cls => 0x00e0,
+      Ret => 0x00ee,
+      Sys { addr } => nnn(0x0, addr),
+      Jp { addr } => nnn(0x1, addr),
+      Call { addr } => nnn(0x2, addr),
+      SeByte { x, byte } => xkk(0x3, x, byte),
+      SneByte { x, byte } => xkk(0x4, x, byte),
+      SeReg { x, y } => xy(0x5, x, y, 0x0),
+      LdByte { x, byte } => xkk(0x6, x, byte),
+      AddByte { x, byte } => xkk(0x7, x, byte),
+      LdReg { x, y } => xy(0x8, x, y, 0x0),
+      Or { x, y } => xy(0x8, x, y, 0x1),
+      And { x, y } => xy(0x8, x, y, 0x2),
+      Xor { x, y } => xy(0x8, x, y, 0x3),
+      AddReg { x, y } => xy(0x8, x, y, 0x4),
+      Sub { x, y } => xy(0x8, x, y, 0x5),
+      Shr { x, y } => xy(0x8, x, y, 0x6),
+      Subn { x, y } => xy(0x8, x, y, 0x7),
+      Shl { x, y } => xy(0x8, x, y, 0xe),
+      SneReg { x, y } => xy(0x9, x, y, 0x0),
+      LdI { addr } => nnn(0xa, addr),
+      JpV0 { addr } => nnn(0xb, addr),
+      Rnd { x, byte } => xkk(0xc, x, byte),
+      Drw { x, y, n } => xy(0xd, x, y, n as u16 & 0xf),
+      Skp { x } => xkk(0xe, x, 0x9e),
+      Sknp { x } => xkk(0xe, x, 0xa1),
+      LdVxDt { x } => xkk(0xf, x, 0x07),
+      LdVxK { x } => xkk(0xf, x, 0x0a),
+      LdDtVx { x } => xkk(0xf, x, 0x15),
+      LdStVx { x } => xkk(0xf, x, 0x18),
+      AddI { x } => xkk(0xf, x, 0x1e),
+      LdF { x } => xkk(0xf, x, 0x29),
+      LdB { x } => xkk(0xf, x, 0x33),
+      LdIVx { x } => xkk(0xf, x, 0x55),
+      LdVxI { x } => xkk(0xf, x, 0x65),
+      ScrollDown { n } => 0x00c0 | (n as u16 & 0xf),
+      ScrollRight => 0x00fb,
+      ScrollLeft => 0x00fc,
+      Exit => 0x00fd,
+      Lores => 0x00fe,
+      Hires => 0x00ff,
+      LdHf { x } => xkk(0xf, x, 0x30),
+      LdRVx { x } => xkk(0xf, x, 0x75),
+      LdVxR { x } => xkk(0xf, x, 0x85),
+      ScrollUp { n } => 0x00d0 | (n as u16 & 0xf),
+      SaveRange { x, y } => xy(0x5, x, y, 0x2),
+      LoadRange { x, y } => xy(0x5, x, y, 0x3),
+      LdILong { .. } => 0xf000,
+      Plane { n } => xkk(0xf, n, 0x01),
+      Audio => 0xf002,
+      Pitch { x } => xkk(0xf, x, 0x3a),
+    }
+  }
+
+  /// The opcode pattern the instruction belongs to, such as `8XY4`, as in
+  /// the usual CHIP-8 references.
+  pub fn pattern(&self) -> &'static str {
+    use Instruction::*;
+
+    match self {
+      Halt => "0000",
+      Cls => "00E0",
+      Ret => "00EE",
+      Sys { .. } => "0NNN",
+      Jp { .. } => "1NNN",
+      Call { .. } => "2NNN",
+      SeByte { .. } => "3XNN",
+      SneByte { .. } => "4XNN",
+      SeReg { .. } => "5XY0",
+      LdByte { .. } => "6XNN",
+      AddByte { .. } => "7XNN",
+      LdReg { .. } => "8XY0",
+      Or { .. } => "8XY1",
+      And { .. } => "8XY2",
+      Xor { .. } => "8XY3",
+      AddReg { .. } => "8XY4",
+      Sub { .. } => "8XY5",
+      Shr { .. } => "8XY6",
+      Subn { .. } => "8XY7",
+      Shl { .. } => "8XYE",
+      SneReg { .. } => "9XY0",
+      LdI { .. } => "ANNN",
+      JpV0 { .. } => "BNNN",
+      Rnd { .. } => "CXNN",
+      Drw { .. } => "DXYN",
+      Skp { .. } => "EX9E",
+      Sknp { .. } => "EXA1",
+      LdVxDt { .. } => "FX07",
+      LdVxK { .. } => "FX0A",
+      LdDtVx { .. } => "FX15",
+      LdStVx { .. } => "FX18",
+      AddI { .. } => "FX1E",
+      LdF { .. } => "FX29",
+      LdB { .. } => "FX33",
+      LdIVx { .. } => "FX55",
+      LdVxI { .. } => "FX65",
+      ScrollDown { .. } => "00CN",
+      ScrollRight => "00FB",
+      ScrollLeft => "00FC",
+      Exit => "00FD",
+      Lores => "00FE",
+      Hires => "00FF",
+      LdHf { .. } => "FX30",
+      LdRVx { .. } => "FX75",
+      LdVxR { .. } => "FX85",
+      ScrollUp { .. } => "00DN",
+      SaveRange { .. } => "5XY2",
+      LoadRange { .. } => "5XY3",
+      LdILong { .. } => "F000",
+      Plane { .. } => "FN01",
+      Audio => "F002",
+      Pitch { .. } => "FX3A",
+    }
+  }
+
+  /// The instruction's length in bytes.
+  #[allow(clippy::len_without_is_empty)]
+  pub fn len(&self) -> usize {
+    match self {
+      Instruction::LdILong { .. } => 4,
+      _ => 2,
+    }
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = self.encode().to_be_bytes().to_vec();
+    if let Instruction::LdILong { addr } = self {
+      bytes.extend_from_slice(&addr.to_be_bytes());
+    }
+    bytes
+  }
+
+  /// The code or data address operand of jumps, calls and `I` loads.
+  pub fn address(&self) -> Option<u16> {
+    match *self {
+      Instruction::Jp { addr }
+      | Instruction::Call { addr }
+      | Instruction::LdI { addr }
+      | Instruction::JpV0 { addr }
+      | Instruction::LdILong { addr } => Some(addr),
+      _ => None,
+    }
+  }
+
+  /// Writes the instruction in assembler syntax, naming the address operand
+  /// `label` if one is given.
+  pub(crate) fn write_with_label(&self, f: &mut dyn fmt::Write, label: Option<&str>) -> fmt::Result {
+    use Instruction::*;
+
+    let a = |addr: u16| label.map_or_else(|| format!("0x{:03x}", addr), str::to_string);
+
+    match *self {
+      Halt => write!(f, "HALT"),
+      Cls => write!(f, "CLS"),
+      Ret => write!(f, "RET"),
+      Sys { addr } => write!(f, "SYS 0x{:03x}", addr),
+      Jp { addr } => write!(f, "JP {}", a(addr)),
+      Call { addr } => write!(f, "CALL {}", a(addr)),
+      SeByte { x, byte } => write!(f, "SE V{:X}, 0x{:02x}", x, byte),
+      SneByte { x, byte } => write!(f, "SNE V{:X}, 0x{:02x}", x, byte),
+      SeReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+      LdByte { x, byte } => write!(f, "LD V{:X}, 0x{:02x}", x, byte),
+      AddByte { x, byte } => write!(f, "ADD V{:X}, 0x{:02x}", x, byte),
+      LdReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+      Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+      And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+      Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+      AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+      Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+      Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+      Subn { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+      Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+      SneReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+      LdI { addr } => write!(f, "LD I, {}", a(addr)),
+      JpV0 { addr } => write!(f, "JP V0, {}", a(addr)),
+      Rnd { x, byte } => write!(f, "RND V{:X}, 0x{:02x}", x, byte),
+      Drw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+      Skp { x } => write!(f, "SKP V{:X}", x),
+      Sknp { x } => write!(f, "SKNP V{:X}", x),
+      LdVxDt { x } => write!(f, "LD V{:X}, DT", x),
+      LdVxK { x } => write!(f, "LD V{:X}, K", x),
+      LdDtVx { x } => write!(f, "LD DT, V{:X}", x),
+      LdStVx { x } => write!(f, "LD ST, V{:X}", x),
+      AddI { x } => write!(f, "ADD I, V{:X}", x),
+      LdF { x } => write!(f, "LD F, V{:X}", x),
+      LdB { x } => write!(f, "LD B, V{:X}", x),
+      LdIVx { x } => write!(f, "LD [I], V{:X}", x),
+      LdVxI { x } => write!(f, "LD V{:X}, [I]", x),
+      ScrollDown { n } => write!(f, "SCD {}", n),
+      ScrollRight => write!(f, "SCR"),
+      ScrollLeft => write!(f, "SCL"),
+      Exit => write!(f, "EXIT"),
+      Lores => write!(f, "LOW"),
+      Hires => write!(f, "HIGH"),
+      LdHf { x } => write!(f, "LD HF, V{:X}", x),
+      LdRVx { x } => write!(f, "LD R, V{:X}", x),
+      LdVxR { x } => write!(f, "LD V{:X}, R", x),
+      ScrollUp { n } => write!(f, "SCU {}", n),
+      SaveRange { x, y } => write!(f, "SAVE V{:X}, V{:X}", x, y),
+      LoadRange { x, y } => write!(f, "LOAD V{:X}, V{:X}", x, y),
+      LdILong { addr } => match label {
+        Some(label) => write!(f, "LD I, LONG {}", label),
+        None => write!(f, "LD I, LONG 0x{:04x}", addr),
+      },
+      Plane { n } => write!(f, "PLANE {}", n),
+      Audio => write!(f, "AUDIO"),
+      Pitch { x } => write!(f, "PITCH V{:X}", x),
+    }
+  }
+}
+
+/// Formats the instruction as the assembler accepts it, e.g. `LD V0, 0x05`.
+impl fmt::Display for Instruction {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.write_with_label(f, None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assemble;
+
+  #[test]
+  fn decode_inverts_encode() {
+    for opcode in 0..=u16::MAX {
+      if let Some(instruction) = Instruction::decode(opcode) {
+        assert_eq!(instruction.encode(), opcode, "{}", instruction);
+      }
+    }
+
+    assert_eq!(Instruction::decode(0x5121), None);
+    assert_eq!(Instruction::decode(0x8008), None);
+    assert_eq!(Instruction::decode(0xf0ff), None);
+    assert_eq!(Instruction::decode(0xf000), None);
+  }
+
+  #[test]
+  fn long_loads_take_four_bytes() {
+    let instruction = Instruction::decode_from(&[0xf0, 0x00, 0xbe, 0xef]).unwrap();
+
+    assert_eq!(instruction, Instruction::LdILong { addr: 0xbeef });
+    assert_eq!(instruction.to_bytes(), [0xf0, 0x00, 0xbe, 0xef]);
+    assert_eq!(instruction.to_string(), "LD I, LONG 0xbeef");
+    assert_eq!(assemble(&instruction.to_string()).unwrap(), instruction.to_bytes());
+    assert_eq!(Instruction::decode_from(&[0xf0, 0x00, 0xbe]), None);
+  }
+
+  #[test]
+  fn patterns_match_opcodes() {
+    for opcode in 0..=u16::MAX {
+      if let Some(instruction) = Instruction::decode(opcode) {
+        let pattern = instruction.pattern();
+        for (digit, nibble) in pattern.chars().zip(format!("{:04X}", opcode).chars()) {
+          assert!(digit == nibble || "NXY".contains(digit), "{} is not {:04x}", pattern, opcode);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn display_reassembles() {
+    for opcode in (0..=u16::MAX).step_by(7) {
+      if let Some(instruction) = Instruction::decode(opcode) {
+        let text = instruction.to_string();
+        assert_eq!(assemble(&text).unwrap(), opcode.to_be_bytes(), "{}", text);
+      }
+    }
+  }
+}
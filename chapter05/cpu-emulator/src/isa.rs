@@ -0,0 +1,159 @@
+//! The parts of an emulator that do not depend on the instruction set. A
+//! machine plugs in by implementing three traits:
+//!
+//! * `Bus`, the memory its instructions are fetched from,
+//! * `Decoder`, which turns the bytes at an address into an instruction,
+//! * `Executor`, which holds the registers and carries instructions out.
+//!
+//! `step` and `run` then provide the fetch/decode/execute loop, and the
+//! debugger and `clock::Clock` work with any machine built this way. `CPU`
+//! is the CHIP-8 machine, with `Profile` as its decoder; `toy::Toy` is a
+//! second, much smaller one.
+
+use std::fmt;
+
+/// Byte-addressed memory, as seen from the processor.
+#[allow(clippy::len_without_is_empty)]
+pub trait Bus {
+  /// The size of the address space.
+  fn len(&self) -> usize;
+
+  /// Reads a byte without side effects, for decoding and inspection.
+  /// `None` if nothing answers at `address`.
+  fn peek(&self, address: usize) -> Option<u8>;
+
+  /// Reads a byte on behalf of the program. Devices may react to being read.
+  fn read(&mut self, address: usize) -> Option<u8> {
+    self.peek(address)
+  }
+
+  /// Writes a byte, returning whether anything accepted it.
+  fn write(&mut self, address: usize, value: u8) -> bool;
+}
+
+impl Bus for [u8] {
+  fn len(&self) -> usize {
+    <[u8]>::len(self)
+  }
+
+  fn peek(&self, address: usize) -> Option<u8> {
+    self.get(address).copied()
+  }
+
+  fn write(&mut self, address: usize, value: u8) -> bool {
+    match self.get_mut(address) {
+      Some(byte) => {
+        *byte = value;
+        true
+      },
+      None => false,
+    }
+  }
+}
+
+impl Bus for Vec<u8> {
+  fn len(&self) -> usize {
+    self.as_slice().len()
+  }
+
+  fn peek(&self, address: usize) -> Option<u8> {
+    self.as_slice().peek(address)
+  }
+
+  fn write(&mut self, address: usize, value: u8) -> bool {
+    self.as_mut_slice().write(address, value)
+  }
+}
+
+pub trait Decoder {
+  type Instruction: Copy + fmt::Display;
+
+  /// The shortest instruction, in bytes. Listings step over bytes that do
+  /// not decode this many at a time.
+  const UNIT: usize;
+
+  /// Decodes the instruction at `address`, returning it with its length in
+  /// bytes, or `None` if the bytes there are not an instruction.
+  fn decode(&self, bus: &dyn Bus, address: usize) -> Option<(Self::Instruction, usize)>;
+}
+
+pub trait Executor {
+  type Decoder: Decoder;
+  /// Why an instruction could not be executed.
+  type Fault: Copy + fmt::Debug + fmt::Display;
+
+  fn decoder(&self) -> &Self::Decoder;
+  fn bus(&self) -> &dyn Bus;
+  fn pc(&self) -> usize;
+  fn set_pc(&mut self, pc: usize);
+
+  /// The fault for a program counter that points at no valid instruction.
+  fn undecodable(&self, pc: usize) -> Self::Fault;
+
+  /// Carries out `instruction`, with the program counter already moved past
+  /// it. Returns `Ok(false)` if the machine halted. On a fault the machine
+  /// must be left as it was, apart from the program counter, which `step`
+  /// puts back.
+  fn execute(&mut self, instruction: Instr<Self>) -> Result<bool, Self::Fault>;
+
+  /// Registers by name and value, for watchpoints and dumps.
+  fn registers(&self) -> Vec<(&'static str, u64)>;
+
+  /// Presses or releases input `key`, for machines that have any.
+  fn set_key(&mut self, key: usize, pressed: bool) -> Result<(), String> {
+    let _ = (key, pressed);
+    Err(String::from("this machine has no keys"))
+  }
+}
+
+/// The instruction type of an executor's decoder.
+pub type Instr<E> = <<E as Executor>::Decoder as Decoder>::Instruction;
+
+/// Fetches, decodes and executes one instruction. Returns `Ok(false)` once
+/// the machine halts.
+pub fn step<E: Executor + ?Sized>(machine: &mut E) -> Result<bool, E::Fault> {
+  let pc = machine.pc();
+  let (instruction, len) = match machine.decoder().decode(machine.bus(), pc) {
+    Some(decoded) => decoded,
+    None => return Err(machine.undecodable(pc)),
+  };
+
+  machine.set_pc(pc + len);
+  machine.execute(instruction).inspect_err(|_| machine.set_pc(pc))
+}
+
+/// Steps until the machine halts or faults.
+pub fn run<E: Executor + ?Sized>(machine: &mut E) -> Result<(), E::Fault> {
+  while step(machine)? {}
+  Ok(())
+}
+
+/// The instruction at `address` and its length, if there is one.
+pub fn instruction_at<E: Executor + ?Sized>(machine: &E, address: usize) -> Option<(Instr<E>, usize)> {
+  machine.decoder().decode(machine.bus(), address)
+}
+
+/// `addr: bytes  mnemonic`, with the bytes grouped into `Decoder::UNIT`s.
+/// Addresses that hold no instruction show a unit of raw bytes and `??`.
+pub fn describe<E: Executor + ?Sized>(machine: &E, address: usize) -> String {
+  if address >= machine.bus().len() {
+    return format!("{:03x}: end of memory", address);
+  }
+
+  let unit = <E::Decoder as Decoder>::UNIT;
+  let (text, len) = match instruction_at(machine, address) {
+    Some((instruction, len)) => (instruction.to_string(), len),
+    None => (String::from("??"), unit),
+  };
+
+  let bytes: Option<Vec<u8>> = (address..address + len).map(|a| machine.bus().peek(a)).collect();
+  match bytes {
+    Some(bytes) => {
+      let raw: Vec<String> = bytes.chunks(unit)
+        .map(|unit| unit.iter().map(|b| format!("{:02x}", b)).collect())
+        .collect();
+      format!("{:03x}: {}  {}", address, raw.join(" "), text)
+    },
+    None => format!("{:03x}: end of memory", address),
+  }
+}
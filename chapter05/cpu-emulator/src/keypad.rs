@@ -0,0 +1,90 @@
+/// The 16-key hexadecimal keypad, laid out as
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keypad {
+  pub(crate) pressed: [bool; 16],
+  pub(crate) released: Option<u8>,
+  /// Whether `FX0A` is waiting. Releases are only recorded while it is, so
+  /// a key let go before the wait began cannot end it.
+  pub(crate) waiting: bool,
+}
+
+impl Keypad {
+  pub fn new() -> Self {
+    Keypad::default()
+  }
+
+  pub fn press(&mut self, key: u8) {
+    self.pressed[key as usize & 0xf] = true;
+  }
+
+  pub fn release(&mut self, key: u8) {
+    let key = key & 0xf;
+    if self.pressed[key as usize] {
+      self.pressed[key as usize] = false;
+      if self.waiting {
+        self.released = Some(key);
+      }
+    }
+  }
+
+  pub fn is_pressed(&self, key: u8) -> bool {
+    self.pressed[key as usize & 0xf]
+  }
+
+  /// Returns the key most recently released while `FX0A` was waiting, if
+  /// any, and forgets it.
+  pub fn take_released(&mut self) -> Option<u8> {
+    self.released.take()
+  }
+
+  /// Called each time `FX0A` executes, as the original interpreter completed
+  /// the wait on key release rather than press. Returns the key released
+  /// since the wait began, ending it, or else begins or continues the wait.
+  pub(crate) fn wait_for_release(&mut self) -> Option<u8> {
+    let key = self.released.take();
+    self.waiting = key.is_none();
+    key
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn release_is_reported_once() {
+    let mut keypad = Keypad::new();
+    assert_eq!(keypad.wait_for_release(), None);
+    keypad.press(0xa);
+    assert!(keypad.is_pressed(0xa));
+    assert_eq!(keypad.take_released(), None);
+
+    keypad.release(0xa);
+    assert!(!keypad.is_pressed(0xa));
+    assert_eq!(keypad.take_released(), Some(0xa));
+    assert_eq!(keypad.take_released(), None);
+  }
+
+  #[test]
+  fn releases_before_a_wait_are_ignored() {
+    let mut keypad = Keypad::new();
+    keypad.press(0x3);
+    keypad.release(0x3);
+    assert_eq!(keypad.wait_for_release(), None);
+
+    keypad.press(0x4);
+    keypad.release(0x4);
+    assert_eq!(keypad.wait_for_release(), Some(0x4));
+
+    keypad.press(0x5);
+    keypad.release(0x5);
+    assert_eq!(keypad.wait_for_release(), None);
+  }
+}
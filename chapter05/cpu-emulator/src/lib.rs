@@ -0,0 +1,884 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod assembler;
+pub mod bus;
+pub mod clock;
+pub mod conformance;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+mod fault;
+pub mod instruction;
+pub mod isa;
+pub mod keypad;
+pub mod profile;
+pub mod profiler;
+pub mod replay;
+pub mod rom;
+pub mod snapshot;
+pub mod toy;
+
+pub use assembler::{assemble, assemble_at, AsmError};
+pub use disassembler::disassemble;
+pub use display::Display;
+pub use fault::CpuFault;
+pub use instruction::Instruction;
+pub use keypad::Keypad;
+pub use profile::{Profile, Quirks};
+pub use rom::{RomError, PROGRAM_START};
+pub use snapshot::SnapshotError;
+
+use bus::MappedBus;
+use isa::{Bus, Decoder, Executor};
+use display::{BIG_FONT, BIG_FONT_SPRITE_LEN, BIG_FONT_START, FONT, FONT_SPRITE_LEN, FONT_START, PLANES};
+
+/// A small xorshift generator backing the `CXNN` opcode. It is seedable so
+/// that runs can be reproduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rng(u64);
+
+impl Rng {
+  fn new(seed: u64) -> Self {
+    // xorshift gets stuck on zero.
+    Rng(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+  }
+
+  fn next_u8(&mut self) -> u8 {
+    let mut x = self.0;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    self.0 = x;
+    (x >> 56) as u8
+  }
+}
+
+/// A fault raised by an instruction helper, before `step` adds the program
+/// counter and opcode to make it a `CpuFault`.
+enum Trap {
+  StackOverflow,
+  StackUnderflow,
+  ReturnAddress,
+  Memory(usize),
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone)]
+pub struct CPU {
+  pub program_counter: usize,
+  pub registers: [u8; 16],
+  /// 4 KiB, or 64 KiB on XO-CHIP.
+  pub memory: Vec<u8>,
+  pub stack: [u16; 16],
+  pub stack_pointer: usize,
+  pub index_register: u16,
+  pub delay_timer: u8,
+  pub sound_timer: u8,
+  pub display: Display,
+  pub keypad: Keypad,
+  /// Starts as the profile's quirks, but can be changed freely.
+  pub quirks: Quirks,
+  /// The SUPER-CHIP flag registers, written by `FX75` and read by `FX85`.
+  pub flags: [u8; 16],
+  /// The XO-CHIP bit planes drawing, clearing and scrolling affect, as a
+  /// mask. Always the first plane on the other machines.
+  pub planes: u8,
+  /// The XO-CHIP audio pattern, 128 one-bit samples.
+  pub audio_pattern: [u8; 16],
+  /// The XO-CHIP playback rate: 4000 * 2 ^ ((pitch - 64) / 48) Hz.
+  pub pitch: u8,
+  /// Devices mapped over memory. Instructions that read or write through
+  /// `I` reach a device instead of memory where one is mapped, but code is
+  /// always fetched from memory. Empty to begin with, and not part of
+  /// snapshots.
+  pub devices: MappedBus,
+  profile: Profile,
+  rng: Rng,
+}
+
+impl Default for CPU {
+  fn default() -> Self {
+    CPU::new()
+  }
+}
+
+impl CPU {
+  pub fn new() -> Self {
+    let seed = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_nanos() as u64)
+      .unwrap_or(0);
+    CPU::with_seed(seed)
+  }
+
+  /// Creates a CPU whose `CXNN` results are fully determined by `seed`.
+  pub fn with_seed(seed: u64) -> Self {
+    CPU::with_profile(Profile::Chip8, seed)
+  }
+
+  /// Creates a CPU emulating `profile`, with its memory size, instructions
+  /// and quirks.
+  pub fn with_profile(profile: Profile, seed: u64) -> Self {
+    let mut cpu = CPU {
+      program_counter: 0x000,
+      registers: [0x00; 16],
+      memory: vec![0x00; profile.memory_len()],
+      stack: [0x00; 16],
+      stack_pointer: 0,
+      index_register: 0,
+      delay_timer: 0,
+      sound_timer: 0,
+      display: Display::default(),
+      keypad: Keypad::new(),
+      quirks: profile.quirks(),
+      flags: [0x00; 16],
+      planes: 1,
+      audio_pattern: [0x00; 16],
+      pitch: 64,
+      devices: MappedBus::new(profile.memory_len()),
+      profile,
+      rng: Rng::new(seed),
+    };
+
+    cpu.memory[FONT_START..FONT_START + FONT.len()].copy_from_slice(&FONT);
+    if profile != Profile::Chip8 {
+      cpu.memory[BIG_FONT_START..BIG_FONT_START + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
+    }
+    cpu
+  }
+
+  pub fn profile(&self) -> Profile {
+    self.profile
+  }
+
+  /// Counts both timers down by one and ticks the devices. Hosts call this
+  /// at 60 Hz, independently of how fast instructions are executed, which
+  /// `clock::Clock` takes care of.
+  pub fn tick_timers(&mut self) {
+    self.delay_timer = self.delay_timer.saturating_sub(1);
+    self.sound_timer = self.sound_timer.saturating_sub(1);
+    self.devices.tick();
+  }
+
+  /// The buzzer sounds for as long as the sound timer is non-zero.
+  pub fn is_sound_playing(&self) -> bool {
+    self.sound_timer > 0
+  }
+
+  fn read_opcode(&self, address: usize) -> Option<u16> {
+    match self.memory.get(address..address.checked_add(2)?) {
+      Some(&[hi, lo]) => Some(u16::from_be_bytes([hi, lo])),
+      _ => None,
+    }
+  }
+
+  /// The `len` bytes of memory starting at `I`.
+  fn memory_at_i(&self, len: usize) -> Result<std::ops::Range<usize>, Trap> {
+    let i = self.index_register as usize;
+    if i + len > self.memory.len() {
+      return Err(Trap::Memory(i));
+    }
+    Ok(i..i + len)
+  }
+
+  /// Reads `range` as the program sees it, through any devices mapped there.
+  fn load(&mut self, range: std::ops::Range<usize>) -> Vec<u8> {
+    range.map(|address| self.devices.read(address).unwrap_or(self.memory[address])).collect()
+  }
+
+  /// Writes `bytes` from `start`. Writes to a device that refuses them, such
+  /// as a ROM, are dropped.
+  fn store(&mut self, start: usize, bytes: &[u8]) {
+    for (address, value) in (start..).zip(bytes) {
+      if self.devices.is_mapped(address) {
+        self.devices.write(address, *value);
+      } else {
+        self.memory[address] = *value;
+      }
+    }
+  }
+
+  /// The instruction at `address`, if it exists on this machine.
+  fn instruction_at(&self, address: usize) -> Option<Instruction> {
+    self.profile.decode(&self.memory, address).map(|(instruction, _)| instruction)
+  }
+
+  /// Skips the next instruction, all four bytes of it for `F000 NNNN`.
+  fn skip_if(&mut self, condition: bool) {
+    if condition {
+      self.program_counter += self.instruction_at(self.program_counter).map_or(2, |next| next.len());
+    }
+  }
+
+  fn add_xy(&mut self, x: u8, y: u8) {
+    let arg1 = self.registers[x as usize];
+    let arg2 = self.registers[y as usize];
+
+    let (val, overflow) = arg1.overflowing_add(arg2);
+    self.registers[x as usize] = val;
+
+    if overflow {
+      self.registers[0xf] = 1;
+    } else {
+      self.registers[0xf] = 0;
+    }
+  }
+
+  /// `Vx = Vx - Vy`, with `VF` set to 1 when there is no borrow.
+  fn sub_xy(&mut self, x: u8, y: u8) {
+    let arg1 = self.registers[x as usize];
+    let arg2 = self.registers[y as usize];
+
+    let (val, borrow) = arg1.overflowing_sub(arg2);
+    self.registers[x as usize] = val;
+    self.registers[0xf] = !borrow as u8;
+  }
+
+  /// `Vx = Vy - Vx`, with `VF` set to 1 when there is no borrow.
+  fn subn_xy(&mut self, x: u8, y: u8) {
+    let arg1 = self.registers[x as usize];
+    let arg2 = self.registers[y as usize];
+
+    let (val, borrow) = arg2.overflowing_sub(arg1);
+    self.registers[x as usize] = val;
+    self.registers[0xf] = !borrow as u8;
+  }
+
+  /// The register a shift reads: `Vy`, or `Vx` without the shift quirk.
+  fn shift_source(&self, x: u8, y: u8) -> u8 {
+    self.registers[if self.quirks.shift_uses_vy { y } else { x } as usize]
+  }
+
+  /// `Vx = Vy >> 1`, with `VF` set to the bit shifted out.
+  fn shr_xy(&mut self, x: u8, y: u8) {
+    let arg = self.shift_source(x, y);
+
+    self.registers[x as usize] = arg >> 1;
+    self.registers[0xf] = arg & 1;
+  }
+
+  /// `Vx = Vy << 1`, with `VF` set to the bit shifted out.
+  fn shl_xy(&mut self, x: u8, y: u8) {
+    let arg = self.shift_source(x, y);
+
+    self.registers[x as usize] = arg << 1;
+    self.registers[0xf] = arg >> 7;
+  }
+
+  /// Stores the decimal digits of `Vx` at `I`, `I + 1` and `I + 2`.
+  fn store_bcd(&mut self, x: u8) -> Result<(), Trap> {
+    let value = self.registers[x as usize];
+    let range = self.memory_at_i(3)?;
+
+    self.store(range.start, &[value / 100, value / 10 % 10, value % 10]);
+    Ok(())
+  }
+
+  /// Copies `V0` to `Vx` inclusive into memory starting at `I`. With the
+  /// load/store quirk `I` is left pointing just past the last byte written.
+  fn store_registers(&mut self, x: u8) -> Result<(), Trap> {
+    let n = x as usize + 1;
+    let range = self.memory_at_i(n)?;
+
+    let registers = self.registers;
+    self.store(range.start, &registers[..n]);
+    if self.quirks.load_store_increments_i {
+      self.index_register = self.index_register.wrapping_add(n as u16);
+    }
+    Ok(())
+  }
+
+  /// Fills `V0` to `Vx` inclusive from memory starting at `I`. With the
+  /// load/store quirk `I` is left pointing just past the last byte read.
+  fn load_registers(&mut self, x: u8) -> Result<(), Trap> {
+    let n = x as usize + 1;
+    let range = self.memory_at_i(n)?;
+
+    let values = self.load(range);
+    self.registers[..n].copy_from_slice(&values);
+    if self.quirks.load_store_increments_i {
+      self.index_register = self.index_register.wrapping_add(n as u16);
+    }
+    Ok(())
+  }
+
+  /// The registers from `Vx` to `Vy`, counting down if `y < x`.
+  fn register_range(x: u8, y: u8) -> Vec<usize> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y { (x..=y).collect() } else { (y..=x).rev().collect() }
+  }
+
+  fn save_range(&mut self, x: u8, y: u8) -> Result<(), Trap> {
+    let registers = CPU::register_range(x, y);
+    let range = self.memory_at_i(registers.len())?;
+
+    let values: Vec<u8> = registers.iter().map(|&register| self.registers[register]).collect();
+    self.store(range.start, &values);
+    Ok(())
+  }
+
+  fn load_range(&mut self, x: u8, y: u8) -> Result<(), Trap> {
+    let registers = CPU::register_range(x, y);
+    let range = self.memory_at_i(registers.len())?;
+
+    let values = self.load(range);
+    for (register, value) in registers.into_iter().zip(values) {
+      self.registers[register] = value;
+    }
+    Ok(())
+  }
+
+  /// Draws an `n`-row sprite, or a 16x16 one for `n = 0` outside plain
+  /// CHIP-8. With several planes selected each takes its own sprite data,
+  /// one after the other from `I`. `VF` is set if any plane collided.
+  fn draw(&mut self, x: u8, y: u8, n: u8) -> Result<(), Trap> {
+    let (width, rows) = match n {
+      0 if self.profile != Profile::Chip8 => (16, 16),
+      n => (8, n as usize),
+    };
+    let sprite_len = width / 8 * rows;
+    let planes: Vec<u8> = (0..PLANES).map(|plane| 1 << plane).filter(|plane| self.planes & plane != 0).collect();
+    let range = self.memory_at_i(sprite_len * planes.len())?;
+    let sprites = self.load(range);
+    let x = self.registers[x as usize] as usize;
+    let y = self.registers[y as usize] as usize;
+
+    let mut collision = false;
+    for (plane, sprite) in planes.iter().zip(sprites.chunks(sprite_len.max(1))) {
+      collision |= self.display.draw(x, y, sprite, width, *plane, self.quirks.sprites_wrap);
+    }
+    self.registers[0xf] = collision as u8;
+    Ok(())
+  }
+
+  /// Blocks on `FX0A` by re-executing it until a key has been released.
+  fn wait_for_key(&mut self, x: u8) {
+    match self.keypad.wait_for_release() {
+      Some(key) => self.registers[x as usize] = key,
+      None => self.program_counter -= 2,
+    }
+  }
+
+  /// `Vx = value` for the logic instructions, which clear `VF` with the
+  /// logic quirk.
+  fn logic(&mut self, x: u8, value: u8) {
+    self.registers[x as usize] = value;
+    if self.quirks.logic_resets_vf {
+      self.registers[0xf] = 0;
+    }
+  }
+
+  fn call(&mut self, nnn: usize) -> Result<(), Trap> {
+    let sp = self.stack_pointer;
+    let stack = &mut self.stack;
+
+    if sp >= stack.len() {
+      return Err(Trap::StackOverflow);
+    }
+    let return_address = u16::try_from(self.program_counter).map_err(|_| Trap::ReturnAddress)?;
+
+    stack[sp] = return_address;
+    self.stack_pointer += 1;
+    self.program_counter = nnn;
+    Ok(())
+  }
+
+  fn ret(&mut self) -> Result<(), Trap> {
+    if self.stack_pointer == 0 {
+      return Err(Trap::StackUnderflow);
+    }
+
+    self.stack_pointer -= 1;
+    let call_address = self.stack[self.stack_pointer];
+    self.program_counter = call_address as usize;
+    Ok(())
+  }
+
+  /// Runs until the `0000` halt instruction or a fault.
+  pub fn run(&mut self) -> Result<(), CpuFault> {
+    while self.step()? {}
+    Ok(())
+  }
+
+  /// Executes a single instruction. Returns `Ok(false)` once the CPU has
+  /// executed the `0000` halt instruction, or SUPER-CHIP's `00FD`.
+  /// Instructions from extensions the profile lacks are invalid opcodes.
+  pub fn step(&mut self) -> Result<bool, CpuFault> {
+    isa::step(self)
+  }
+
+  /// Helpers that can trap leave the CPU untouched when they do.
+  fn apply(&mut self, instruction: Instruction) -> Result<bool, Trap> {
+    let reg = |x: u8| self.registers[x as usize];
+
+    use Instruction::*;
+    match instruction {
+      Halt | Exit => { return Ok(false); }
+      Cls => self.display.clear_planes(self.planes),
+      Ret => self.ret()?,
+      Sys { .. } => {},
+      Jp { addr } => self.program_counter = addr as usize,
+      Call { addr } => self.call(addr as usize)?,
+      SeByte { x, byte } => self.skip_if(reg(x) == byte),
+      SneByte { x, byte } => self.skip_if(reg(x) != byte),
+      SeReg { x, y } => self.skip_if(reg(x) == reg(y)),
+      LdByte { x, byte } => self.registers[x as usize] = byte,
+      AddByte { x, byte } => self.registers[x as usize] = reg(x).wrapping_add(byte),
+      LdReg { x, y } => self.registers[x as usize] = reg(y),
+      Or { x, y } => self.logic(x, reg(x) | reg(y)),
+      And { x, y } => self.logic(x, reg(x) & reg(y)),
+      Xor { x, y } => self.logic(x, reg(x) ^ reg(y)),
+      AddReg { x, y } => self.add_xy(x, y),
+      Sub { x, y } => self.sub_xy(x, y),
+      Shr { x, y } => self.shr_xy(x, y),
+      Subn { x, y } => self.subn_xy(x, y),
+      Shl { x, y } => self.shl_xy(x, y),
+      SneReg { x, y } => self.skip_if(reg(x) != reg(y)),
+      LdI { addr } => self.index_register = addr,
+      JpV0 { addr } => {
+        let offset = if self.quirks.jump_uses_vx { reg((addr >> 8) as u8 & 0xf) } else { reg(0) };
+        self.program_counter = addr as usize + offset as usize;
+      },
+      Rnd { x, byte } => self.registers[x as usize] = self.rng.next_u8() & byte,
+      Drw { x, y, n } => self.draw(x, y, n)?,
+      Skp { x } => self.skip_if(self.keypad.is_pressed(reg(x))),
+      Sknp { x } => self.skip_if(!self.keypad.is_pressed(reg(x))),
+      LdVxDt { x } => self.registers[x as usize] = self.delay_timer,
+      LdVxK { x } => self.wait_for_key(x),
+      LdDtVx { x } => self.delay_timer = reg(x),
+      LdStVx { x } => self.sound_timer = reg(x),
+      AddI { x } => self.index_register = self.index_register.wrapping_add(reg(x) as u16),
+      LdF { x } => self.index_register = (FONT_START + (reg(x) & 0xf) as usize * FONT_SPRITE_LEN) as u16,
+      LdB { x } => self.store_bcd(x)?,
+      LdIVx { x } => self.store_registers(x)?,
+      LdVxI { x } => self.load_registers(x)?,
+      ScrollDown { n } => self.display.scroll(0, n as isize, self.planes),
+      ScrollUp { n } => self.display.scroll(0, -(n as isize), self.planes),
+      ScrollRight => self.display.scroll(4, 0, self.planes),
+      ScrollLeft => self.display.scroll(-4, 0, self.planes),
+      Lores => self.display.set_hires(false),
+      Hires => self.display.set_hires(true),
+      LdHf { x } => {
+        self.index_register = (BIG_FONT_START + (reg(x) & 0xf) as usize * BIG_FONT_SPRITE_LEN) as u16;
+      },
+      LdRVx { x } => self.flags[..=x as usize].copy_from_slice(&self.registers[..=x as usize]),
+      LdVxR { x } => self.registers[..=x as usize].copy_from_slice(&self.flags[..=x as usize]),
+      SaveRange { x, y } => self.save_range(x, y)?,
+      LoadRange { x, y } => self.load_range(x, y)?,
+      LdILong { addr } => self.index_register = addr,
+      Plane { n } => self.planes = n & ((1 << PLANES) - 1),
+      Audio => {
+        let range = self.memory_at_i(self.audio_pattern.len())?;
+        let pattern = self.load(range);
+        self.audio_pattern.copy_from_slice(&pattern);
+      },
+      Pitch { x } => self.pitch = reg(x),
+    }
+
+    Ok(true)
+  }
+}
+
+const REGISTER_NAMES: [&str; 16] = [
+  "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+];
+
+impl Executor for CPU {
+  type Decoder = Profile;
+  type Fault = CpuFault;
+
+  fn decoder(&self) -> &Profile {
+    &self.profile
+  }
+
+  fn bus(&self) -> &dyn Bus {
+    &self.memory
+  }
+
+  fn pc(&self) -> usize {
+    self.program_counter
+  }
+
+  fn set_pc(&mut self, pc: usize) {
+    self.program_counter = pc;
+  }
+
+  fn undecodable(&self, pc: usize) -> CpuFault {
+    match self.read_opcode(pc) {
+      Some(opcode) => CpuFault::InvalidOpcode { pc, opcode },
+      None => CpuFault::PcOutOfBounds { pc },
+    }
+  }
+
+  fn execute(&mut self, instruction: Instruction) -> Result<bool, CpuFault> {
+    let pc = self.program_counter - instruction.len();
+    let opcode = instruction.encode();
+
+    self.apply(instruction).map_err(|trap| match trap {
+      Trap::StackOverflow => CpuFault::StackOverflow { pc, opcode },
+      Trap::StackUnderflow => CpuFault::StackUnderflow { pc, opcode },
+      Trap::ReturnAddress => CpuFault::ReturnAddressOutOfBounds { pc, opcode },
+      Trap::Memory(address) => CpuFault::MemoryOutOfBounds { pc, opcode, address },
+    })
+  }
+
+  fn registers(&self) -> Vec<(&'static str, u64)> {
+    let mut registers: Vec<(&'static str, u64)> = REGISTER_NAMES.iter()
+      .zip(self.registers)
+      .map(|(name, value)| (*name, value as u64))
+      .collect();
+    registers.extend([
+      ("I", self.index_register as u64),
+      ("DT", self.delay_timer as u64),
+      ("ST", self.sound_timer as u64),
+      ("SP", self.stack_pointer as u64),
+    ]);
+    registers
+  }
+
+  fn set_key(&mut self, key: usize, pressed: bool) -> Result<(), String> {
+    if key >= 16 {
+      return Err(String::from("keys go from 0x0 to 0xf"));
+    }
+    if pressed {
+      self.keypad.press(key as u8);
+    } else {
+      self.keypad.release(key as u8);
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Display for CPU {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(
+      f,
+      "PC={:04x} I={:04x} SP={:x} DT={:02x} ST={:02x}",
+      self.program_counter, self.index_register, self.stack_pointer,
+      self.delay_timer, self.sound_timer,
+    )?;
+
+    for (row, registers) in self.registers.chunks(8).enumerate() {
+      for (column, value) in registers.iter().enumerate() {
+        if column > 0 {
+          write!(f, " ")?;
+        }
+        write!(f, "V{:X}={:02x}", row * 8 + column, value)?;
+      }
+      writeln!(f)?;
+    }
+
+    write!(f, "stack:")?;
+    for address in &self.stack[..self.stack_pointer] {
+      write!(f, " {:04x}", address)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assemble;
+
+  fn run_program(cpu: &mut CPU, program: &[u8]) {
+    cpu.memory[..program.len()].copy_from_slice(program);
+    cpu.run().unwrap();
+  }
+
+  #[test]
+  fn sub_sets_vf_when_there_is_no_borrow() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.registers[0] = 5;
+    cpu.registers[1] = 3;
+    cpu.registers[2] = 7;
+    run_program(&mut cpu, &[0x80, 0x15, 0x83, 0xf0, 0x80, 0x25, 0x00, 0x00]);
+
+    assert_eq!(cpu.registers[3], 1);
+    assert_eq!(cpu.registers[0], 2u8.wrapping_sub(7));
+    assert_eq!(cpu.registers[0xf], 0);
+  }
+
+  #[test]
+  fn skips_and_jumps() {
+    let mut cpu = CPU::with_seed(1);
+    run_program(&mut cpu, &[
+      0x60, 0x2a, // V0 = 0x2a
+      0x30, 0x2a, // skip next if V0 == 0x2a
+      0x61, 0x01, // (skipped)
+      0x12, 0x0a, // jump to 0x00a
+      0x61, 0x02, // (jumped over)
+      0x00, 0x00,
+    ]);
+
+    assert_eq!(cpu.registers[1], 0);
+  }
+
+  #[test]
+  fn bcd_and_register_dump() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.registers[0] = 254;
+    run_program(&mut cpu, &[0xa1, 0x00, 0xf0, 0x33, 0xf2, 0x65, 0x00, 0x00]);
+
+    assert_eq!(&cpu.memory[0x100..0x103], &[2, 5, 4]);
+    assert_eq!(&cpu.registers[..3], &[2, 5, 4]);
+    assert_eq!(cpu.index_register, 0x103);
+  }
+
+  #[test]
+  fn draws_font_sprites() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.registers[0] = 0xa;
+    run_program(&mut cpu, &[0xf0, 0x29, 0xd1, 0x25, 0xd1, 0x25, 0x00, 0x00]);
+
+    assert_eq!(cpu.index_register as usize, FONT_START + 0xa * FONT_SPRITE_LEN);
+    assert_eq!(cpu.registers[0xf], 1);
+    assert!(!cpu.display.pixel(0, 0));
+  }
+
+  #[test]
+  fn waits_for_key_release() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.memory[..4].copy_from_slice(&[0xf3, 0x0a, 0x00, 0x00]);
+
+    assert_eq!(cpu.step(), Ok(true));
+    assert_eq!(cpu.program_counter, 0);
+
+    cpu.keypad.press(0x7);
+    assert_eq!(cpu.step(), Ok(true));
+    assert_eq!(cpu.program_counter, 0);
+
+    cpu.keypad.release(0x7);
+    assert_eq!(cpu.step(), Ok(true));
+    assert_eq!(cpu.program_counter, 2);
+    assert_eq!(cpu.registers[3], 0x7);
+  }
+
+  #[test]
+  fn key_released_before_the_wait_does_not_count() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.memory[..4].copy_from_slice(&[0xf3, 0x0a, 0x00, 0x00]);
+
+    cpu.keypad.press(0x7);
+    cpu.keypad.release(0x7);
+    assert_eq!(cpu.step(), Ok(true));
+    assert_eq!(cpu.program_counter, 0);
+
+    cpu.keypad.press(0x2);
+    cpu.keypad.release(0x2);
+    assert_eq!(cpu.step(), Ok(true));
+    assert_eq!(cpu.registers[3], 0x2);
+  }
+
+  #[test]
+  fn timers_count_down_to_zero() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.delay_timer = 2;
+    cpu.sound_timer = 1;
+
+    cpu.tick_timers();
+    assert!(!cpu.is_sound_playing());
+    cpu.tick_timers();
+    cpu.tick_timers();
+    assert_eq!(cpu.delay_timer, 0);
+  }
+
+  #[test]
+  fn random_is_masked_and_seeded() {
+    let program = [0xc0, 0x0f, 0xc1, 0xff, 0x00, 0x00];
+    let mut a = CPU::with_seed(42);
+    let mut b = CPU::with_seed(42);
+    run_program(&mut a, &program);
+    run_program(&mut b, &program);
+
+    assert!(a.registers[0] <= 0x0f);
+    assert_eq!(a.registers[..2], b.registers[..2]);
+  }
+
+  #[test]
+  fn stack_faults_leave_the_cpu_untouched() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.memory[..2].copy_from_slice(&[0x20, 0x00]); // CALL 0x000, forever
+
+    let fault = cpu.run().unwrap_err();
+    assert_eq!(fault, CpuFault::StackOverflow { pc: 0, opcode: 0x2000 });
+    assert_eq!(cpu.stack_pointer, 16);
+    assert_eq!(cpu.program_counter, 0);
+
+    let mut cpu = CPU::with_seed(1);
+    cpu.memory[..2].copy_from_slice(&[0x00, 0xee]);
+    assert_eq!(cpu.step(), Err(CpuFault::StackUnderflow { pc: 0, opcode: 0x00ee }));
+
+    let mut cpu = CPU::with_profile(Profile::XoChip, 1);
+    cpu.memory[0xfffe..].copy_from_slice(&[0x22, 0x00]);
+    cpu.program_counter = 0xfffe;
+    assert_eq!(cpu.step(), Err(CpuFault::ReturnAddressOutOfBounds { pc: 0xfffe, opcode: 0x2200 }));
+    assert_eq!((cpu.program_counter, cpu.stack_pointer), (0xfffe, 0));
+  }
+
+  #[test]
+  fn bad_opcodes_and_addresses_fault() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.memory[..2].copy_from_slice(&[0x51, 0x21]);
+    assert_eq!(cpu.step(), Err(CpuFault::InvalidOpcode { pc: 0, opcode: 0x5121 }));
+
+    cpu.memory[..2].copy_from_slice(&[0x1f, 0xff]); // JP 0xfff
+    assert_eq!(cpu.step(), Ok(true));
+    assert_eq!(cpu.step(), Err(CpuFault::PcOutOfBounds { pc: 0xfff }));
+
+    let mut cpu = CPU::with_seed(1);
+    cpu.memory[..4].copy_from_slice(&[0xaf, 0xfe, 0xf2, 0x55]); // LD I, 0xffe; LD [I], V2
+    let fault = cpu.run().unwrap_err();
+    assert_eq!(fault, CpuFault::MemoryOutOfBounds { pc: 2, opcode: 0xf255, address: 0xffe });
+    assert_eq!(fault.to_string(), "memory access at 0xffe runs past the end of memory at 0x002 (f255)");
+    assert_eq!(cpu.index_register, 0xffe);
+  }
+
+  #[test]
+  fn quirks_change_shifts_loads_and_jumps() {
+    let program = assemble("
+      LD  V1, 0x81
+      SHR V0, V1
+      LD  I, 0x300
+      LD  [I], V1
+      OR  VF, V1
+      LD  V2, 2
+      JP  V0, 0x200
+    ").unwrap();
+
+    let mut cosmac = CPU::with_seed(1);
+    cosmac.load_rom(&program).unwrap();
+    for _ in 0..7 {
+      cosmac.step().unwrap();
+    }
+    assert_eq!(cosmac.registers[0], 0x40);
+    assert_eq!(cosmac.index_register, 0x302);
+    assert_eq!(cosmac.registers[0xf], 0);
+    assert_eq!(cosmac.program_counter, 0x240);
+
+    let mut schip = CPU::with_profile(Profile::SuperChip, 1);
+    schip.load_rom(&program).unwrap();
+    for _ in 0..7 {
+      schip.step().unwrap();
+    }
+    assert_eq!(schip.registers[0], 0);
+    assert_eq!(schip.index_register, 0x300);
+    assert_eq!(schip.registers[0xf], 0x81);
+    assert_eq!(schip.program_counter, 0x202);
+  }
+
+  #[test]
+  fn extensions_need_their_profile() {
+    let program = assemble("HIGH\nSCR\nEXIT").unwrap();
+
+    let mut cpu = CPU::with_seed(1);
+    cpu.load_rom(&program).unwrap();
+    assert_eq!(cpu.step(), Err(CpuFault::InvalidOpcode { pc: 0x200, opcode: 0x00ff }));
+
+    let mut cpu = CPU::with_profile(Profile::SuperChip, 1);
+    cpu.load_rom(&program).unwrap();
+    cpu.run().unwrap();
+    assert!(cpu.display.is_hires());
+    assert_eq!(cpu.program_counter, 0x206);
+
+    let mut cpu = CPU::with_profile(Profile::SuperChip, 1);
+    cpu.load_rom(&assemble("LD I, LONG 0x300").unwrap()).unwrap();
+    assert_eq!(cpu.step(), Err(CpuFault::InvalidOpcode { pc: 0x200, opcode: 0xf000 }));
+  }
+
+  #[test]
+  fn super_chip_draws_big_sprites_and_scrolls() {
+    let mut cpu = CPU::with_profile(Profile::SuperChip, 1);
+    cpu.registers[0] = 3;
+    cpu.load_rom(&assemble("
+      HIGH
+      LD  HF, V0
+      DRW V1, V1, 10
+      LD  I, block
+      DRW V2, V2, 0
+      SCD 2
+      SCL
+      LD  R, V2
+      HALT
+      block: DW 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff
+             DW 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff
+    ").unwrap()).unwrap();
+    cpu.run().unwrap();
+
+    assert_eq!(cpu.registers[0xf], 1);
+    assert_eq!(cpu.display.width(), 128);
+    // The 3 and the block overlap in the top left, so the 3's solid rows
+    // come out dark after the scroll moves everything down 2 and left 4.
+    assert!(!cpu.display.pixel(0, 2));
+    assert!(cpu.display.pixel(4, 4));
+    assert!(cpu.display.pixel(11, 17));
+    assert!(!cpu.display.pixel(12, 17));
+    assert!(!cpu.display.pixel(0, 18));
+    assert_eq!(cpu.flags[..3], [3, 0, 0]);
+  }
+
+  #[test]
+  fn xo_chip_ranges_planes_and_long_loads() {
+    let mut cpu = CPU::with_profile(Profile::XoChip, 1);
+    cpu.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+    cpu.load_rom(&assemble("
+      LD   I, LONG 0x8000
+      SAVE V3, V1
+      LOAD V4, V6
+      SE   V0, 1
+      LD   I, LONG 0x9000
+      PLANE 2
+      LD   I, sprite
+      DRW  V0, V0, 1
+      HALT
+      sprite: DB 0x80
+    ").unwrap()).unwrap();
+    cpu.run().unwrap();
+
+    assert_eq!(cpu.memory[0x8000..0x8003], [4, 3, 2]);
+    assert_eq!(cpu.registers[4..7], [4, 3, 2]);
+    // SE skipped all four bytes of the second long load.
+    assert_eq!(cpu.index_register, 0x216);
+    assert_eq!(cpu.display.planes(1, 1), 2);
+  }
+
+  #[test]
+  fn loads_and_stores_through_i_reach_mapped_devices() {
+    use crate::bus::{Console, Rom, Timer};
+
+    let mut cpu = CPU::with_seed(1);
+    cpu.devices.map(0xe00, Rom::from(vec![7, 8])).unwrap();
+    cpu.devices.map(0xf00, Console::new()).unwrap();
+    cpu.devices.map(0xf02, Timer::new()).unwrap();
+    cpu.devices.device_mut::<Console>(0xf00).unwrap().feed(b"?");
+    cpu.load_rom(&assemble("
+      LD   V0, 0x48
+      LD   I, 0xf00
+      LD   [I], V0
+      LD   V0, 0x49
+      LD   I, 0xf00
+      LD   [I], V0
+      LD   I, 0xf00
+      LD   V0, [I]
+      LD   V5, V0
+      LD   V0, 3
+      LD   I, 0xf02
+      LD   [I], V0
+      LD   I, 0xe00
+      LD   [I], V0
+      LD   I, 0xe00
+      LD   V1, [I]
+      HALT
+    ").unwrap()).unwrap();
+    cpu.run().unwrap();
+    cpu.tick_timers();
+
+    assert_eq!(cpu.devices.device::<Console>(0xf00).unwrap().output, b"HI");
+    assert_eq!(cpu.registers[5], b'?');
+    assert_eq!(cpu.devices.device::<Timer>(0xf02).unwrap().countdown, 2);
+    assert_eq!(cpu.registers[..2], [7, 8]);
+    assert_eq!(cpu.memory[0xe00..0xe01], [0]);
+    assert_eq!(cpu.memory[0xf00..0xf03], [0, 0, 0]);
+  }
+}
@@ -0,0 +1,253 @@
+//! Machine profiles and interpreter quirks.
+//!
+//! CHIP-8 grew up on many interpreters that disagree on a handful of
+//! instructions. A `Profile` picks the instruction set extensions and the
+//! quirks a program was written for; `Quirks` can then be adjusted one by
+//! one for programs that expect an unusual mix.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::instruction::Instruction;
+use crate::isa::{Bus, Decoder};
+
+/// Behaviours that differ between interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+  /// `8XY6` and `8XYE` shift `Vy` into `Vx`, as on the COSMAC VIP, rather
+  /// than shifting `Vx` in place.
+  pub shift_uses_vy: bool,
+  /// `FX55` and `FX65` leave `I` pointing just past the last register
+  /// rather than unchanged.
+  pub load_store_increments_i: bool,
+  /// `BNNN` jumps to `NNN + VX`, where X is the top nibble of `NNN`, rather
+  /// than to `NNN + V0`.
+  pub jump_uses_vx: bool,
+  /// `8XY1`, `8XY2` and `8XY3` clear `VF`.
+  pub logic_resets_vf: bool,
+  /// Sprites that run off the edge of the screen wrap around rather than
+  /// being clipped.
+  pub sprites_wrap: bool,
+}
+
+impl Quirks {
+  pub const COSMAC: Quirks = Quirks {
+    shift_uses_vy: true,
+    load_store_increments_i: true,
+    jump_uses_vx: false,
+    logic_resets_vf: true,
+    sprites_wrap: false,
+  };
+
+  pub const SUPER_CHIP: Quirks = Quirks {
+    shift_uses_vy: false,
+    load_store_increments_i: false,
+    jump_uses_vx: true,
+    logic_resets_vf: false,
+    sprites_wrap: false,
+  };
+
+  pub const XO_CHIP: Quirks = Quirks {
+    shift_uses_vy: true,
+    load_store_increments_i: true,
+    jump_uses_vx: false,
+    logic_resets_vf: false,
+    sprites_wrap: true,
+  };
+
+  /// The short names `set` accepts, in field order.
+  pub const NAMES: [&'static str; 5] = ["shift", "load-store", "jump", "logic", "wrap"];
+
+  /// Turns the quirk called `name`, one of `NAMES`, on or off.
+  pub fn set(&mut self, name: &str, on: bool) -> Result<(), String> {
+    let quirk = match name {
+      "shift" => &mut self.shift_uses_vy,
+      "load-store" => &mut self.load_store_increments_i,
+      "jump" => &mut self.jump_uses_vx,
+      "logic" => &mut self.logic_resets_vf,
+      "wrap" => &mut self.sprites_wrap,
+      _ => return Err(format!("unknown quirk {:?}, expected one of {}", name, Quirks::NAMES.join(", "))),
+    };
+    *quirk = on;
+    Ok(())
+  }
+
+  /// Packs the flags into a byte, in field order from the lowest bit.
+  pub(crate) fn to_bits(self) -> u8 {
+    [
+      self.shift_uses_vy,
+      self.load_store_increments_i,
+      self.jump_uses_vx,
+      self.logic_resets_vf,
+      self.sprites_wrap,
+    ].iter().enumerate().fold(0, |bits, (i, on)| bits | (*on as u8) << i)
+  }
+
+  pub(crate) fn from_bits(bits: u8) -> Quirks {
+    let bit = |i: u8| bits & 1 << i != 0;
+    Quirks {
+      shift_uses_vy: bit(0),
+      load_store_increments_i: bit(1),
+      jump_uses_vx: bit(2),
+      logic_resets_vf: bit(3),
+      sprites_wrap: bit(4),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+  /// The original COSMAC VIP interpreter: 64x32, 4 KiB of memory.
+  Chip8,
+  /// SUPER-CHIP 1.1: adds the 128x64 hi-res mode, scrolling, 16x16
+  /// sprites, a large font and flag registers.
+  SuperChip,
+  /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bit planes, 16-bit
+  /// `I` loads, register range loads and stores, and an audio pattern.
+  XoChip,
+}
+
+impl Profile {
+  pub fn quirks(self) -> Quirks {
+    match self {
+      Profile::Chip8 => Quirks::COSMAC,
+      Profile::SuperChip => Quirks::SUPER_CHIP,
+      Profile::XoChip => Quirks::XO_CHIP,
+    }
+  }
+
+  pub fn memory_len(self) -> usize {
+    match self {
+      Profile::Chip8 | Profile::SuperChip => 0x1000,
+      Profile::XoChip => 0x10000,
+    }
+  }
+
+  /// Whether `instruction` exists on this machine. Anything else is an
+  /// invalid opcode.
+  pub fn supports(self, instruction: &Instruction) -> bool {
+    use Instruction::*;
+
+    match instruction {
+      ScrollDown { .. } | ScrollRight | ScrollLeft | Exit | Lores | Hires | LdHf { .. }
+      | LdRVx { .. } | LdVxR { .. } => self != Profile::Chip8,
+      ScrollUp { .. } | SaveRange { .. } | LoadRange { .. } | LdILong { .. } | Plane { .. }
+      | Audio | Pitch { .. } => self == Profile::XoChip,
+      _ => true,
+    }
+  }
+
+  pub(crate) fn to_byte(self) -> u8 {
+    match self {
+      Profile::Chip8 => 0,
+      Profile::SuperChip => 1,
+      Profile::XoChip => 2,
+    }
+  }
+
+  pub(crate) fn from_byte(byte: u8) -> Option<Profile> {
+    match byte {
+      0 => Some(Profile::Chip8),
+      1 => Some(Profile::SuperChip),
+      2 => Some(Profile::XoChip),
+      _ => None,
+    }
+  }
+}
+
+/// A profile decodes exactly the instructions it supports.
+impl Decoder for Profile {
+  type Instruction = Instruction;
+
+  const UNIT: usize = 2;
+
+  fn decode(&self, bus: &dyn Bus, address: usize) -> Option<(Instruction, usize)> {
+    let mut bytes = [0; 4];
+    let mut len = 0;
+    while len < bytes.len() {
+      match address.checked_add(len).and_then(|address| bus.peek(address)) {
+        Some(byte) => bytes[len] = byte,
+        None => break,
+      }
+      len += 1;
+    }
+
+    let instruction = Instruction::decode_from(&bytes[..len]).filter(|i| self.supports(i))?;
+    Some((instruction, instruction.len()))
+  }
+}
+
+impl fmt::Display for Profile {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Profile::Chip8 => write!(f, "chip8"),
+      Profile::SuperChip => write!(f, "schip"),
+      Profile::XoChip => write!(f, "xochip"),
+    }
+  }
+}
+
+impl FromStr for Profile {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name.to_ascii_lowercase().as_str() {
+      "chip8" | "chip-8" => Ok(Profile::Chip8),
+      "schip" | "superchip" | "super-chip" => Ok(Profile::SuperChip),
+      "xochip" | "xo-chip" => Ok(Profile::XoChip),
+      _ => Err(format!("unknown profile {:?}, expected chip8, schip or xochip", name)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn names_round_trip() {
+    for profile in [Profile::Chip8, Profile::SuperChip, Profile::XoChip] {
+      assert_eq!(profile.to_string().parse(), Ok(profile));
+      assert_eq!(Profile::from_byte(profile.to_byte()), Some(profile));
+      assert_eq!(Quirks::from_bits(profile.quirks().to_bits()), profile.quirks());
+    }
+    assert_eq!("SUPER-CHIP".parse(), Ok(Profile::SuperChip));
+    assert!("chip9".parse::<Profile>().is_err());
+  }
+
+  #[test]
+  fn quirks_are_set_by_name() {
+    let mut quirks = Quirks::COSMAC;
+    for name in Quirks::NAMES {
+      quirks.set(name, false).unwrap();
+    }
+    assert_eq!(quirks.to_bits(), 0);
+
+    quirks.set("jump", true).unwrap();
+    assert!(quirks.jump_uses_vx);
+    assert!(quirks.set("vf", true).is_err());
+  }
+
+  /// Answers with `CLS` everywhere, right up to `usize::MAX`.
+  struct Endless;
+
+  impl Bus for Endless {
+    fn len(&self) -> usize {
+      usize::MAX
+    }
+
+    fn peek(&self, address: usize) -> Option<u8> {
+      Some(if address.is_multiple_of(2) { 0x00 } else { 0xe0 })
+    }
+
+    fn write(&mut self, _: usize, _: u8) -> bool {
+      false
+    }
+  }
+
+  #[test]
+  fn decoding_stops_at_the_top_of_the_address_space() {
+    assert_eq!(Profile::Chip8.decode(&Endless, usize::MAX - 1), Some((Instruction::Cls, 2)));
+    assert_eq!(Profile::Chip8.decode(&Endless, usize::MAX), None);
+  }
+}
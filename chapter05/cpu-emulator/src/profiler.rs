@@ -0,0 +1,285 @@
+//! Execution profiling and code coverage.
+//!
+//! A `Profiler` wraps a `CPU`, as `replay::Recorder` does, and counts how
+//! often each address and each opcode pattern executes. It follows `CALL`
+//! and `RET` to record which routine calls which, and notes the deepest the
+//! stack got. `finish` turns all that into a `Report`, which also lists the
+//! parts of the program that never ran. Those include any data the program
+//! keeps among its code, such as sprites.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+use crate::clock::Clocked;
+use crate::isa;
+use crate::{CpuFault, Instruction, CPU};
+
+/// How many of the busiest addresses a report prints.
+pub const HOT_SPOTS: usize = 10;
+
+pub struct Profiler {
+  cpu: CPU,
+  code: Range<usize>,
+  /// Executions of the instruction starting at each address.
+  counts: Vec<u64>,
+  /// Whether each byte belongs to an instruction that executed.
+  covered: Vec<bool>,
+  opcodes: BTreeMap<&'static str, u64>,
+  calls: BTreeMap<(usize, usize), u64>,
+  /// The entry point of each routine on the stack, starting with wherever
+  /// the program counter was when profiling began.
+  routines: Vec<usize>,
+  max_stack_depth: usize,
+  instructions: u64,
+}
+
+impl Profiler {
+  /// Profiles `cpu` from its current state. `code` is the part of memory
+  /// holding the program, which coverage is reported for.
+  pub fn new(cpu: CPU, code: Range<usize>) -> Self {
+    let len = cpu.memory.len();
+    Profiler {
+      code: code.start.min(len)..code.end.min(len),
+      counts: vec![0; len],
+      covered: vec![false; len],
+      opcodes: BTreeMap::new(),
+      calls: BTreeMap::new(),
+      routines: vec![cpu.program_counter],
+      max_stack_depth: cpu.stack_pointer,
+      instructions: 0,
+      cpu,
+    }
+  }
+
+  pub fn cpu(&self) -> &CPU {
+    &self.cpu
+  }
+
+  pub fn tick_timers(&mut self) {
+    self.cpu.tick_timers();
+  }
+
+  /// Executes one instruction, as `CPU::step`. Faulting instructions are not
+  /// counted.
+  pub fn step(&mut self) -> Result<bool, CpuFault> {
+    let pc = self.cpu.program_counter;
+    let decoded = isa::instruction_at(&self.cpu, pc);
+    let running = self.cpu.step()?;
+
+    // The instruction executed, so it decoded.
+    let (instruction, len) = decoded.expect("executed an undecodable instruction");
+    self.counts[pc] += 1;
+    let end = (pc + len).min(self.covered.len());
+    self.covered[pc..end].fill(true);
+    *self.opcodes.entry(instruction.pattern()).or_default() += 1;
+
+    match instruction {
+      Instruction::Call { addr } => {
+        let caller = *self.routines.last().unwrap();
+        *self.calls.entry((caller, addr as usize)).or_default() += 1;
+        self.routines.push(addr as usize);
+      },
+      Instruction::Ret if self.routines.len() > 1 => {
+        self.routines.pop();
+      },
+      _ => {},
+    }
+
+    self.max_stack_depth = self.max_stack_depth.max(self.cpu.stack_pointer);
+    self.instructions += 1;
+    Ok(running)
+  }
+
+  pub fn finish(self) -> (CPU, Report) {
+    let mut hot_spots: Vec<HotSpot> = self.counts.iter()
+      .enumerate()
+      .filter(|(_, count)| **count > 0)
+      .map(|(address, count)| HotSpot {
+        address,
+        count: *count,
+        instruction: isa::instruction_at(&self.cpu, address)
+          .map_or(String::from("??"), |(instruction, _)| instruction.to_string()),
+      })
+      .collect();
+    hot_spots.sort_by(|a, b| b.count.cmp(&a.count).then(a.address.cmp(&b.address)));
+
+    let mut opcodes: Vec<(&'static str, u64)> = self.opcodes.into_iter().collect();
+    opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let calls = self.calls.into_iter()
+      .map(|((caller, callee), count)| CallEdge { caller, callee, count })
+      .collect();
+
+    let mut never_executed: Vec<Range<usize>> = vec![];
+    for address in self.code.clone().filter(|address| !self.covered[*address]) {
+      match never_executed.last_mut() {
+        Some(range) if range.end == address => range.end += 1,
+        _ => never_executed.push(address..address + 1),
+      }
+    }
+
+    let report = Report {
+      instructions: self.instructions,
+      max_stack_depth: self.max_stack_depth,
+      hot_spots,
+      opcodes,
+      calls,
+      never_executed,
+    };
+    (self.cpu, report)
+  }
+}
+
+impl Clocked for Profiler {
+  type Fault = CpuFault;
+
+  fn step(&mut self) -> Result<bool, CpuFault> {
+    Profiler::step(self)
+  }
+
+  fn tick_timers(&mut self) {
+    Profiler::tick_timers(self)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotSpot {
+  pub address: usize,
+  pub count: u64,
+  /// The instruction at `address` when the run ended.
+  pub instruction: String,
+}
+
+/// One routine calling another, `count` times. Routines are named by their
+/// entry point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallEdge {
+  pub caller: usize,
+  pub callee: usize,
+  pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+  pub instructions: u64,
+  pub max_stack_depth: usize,
+  /// Every address that executed, busiest first.
+  pub hot_spots: Vec<HotSpot>,
+  /// Executions per opcode pattern, busiest first.
+  pub opcodes: Vec<(&'static str, u64)>,
+  /// Sorted by caller, then callee.
+  pub calls: Vec<CallEdge>,
+  /// Parts of the program that no executed instruction covered.
+  pub never_executed: Vec<Range<usize>>,
+}
+
+impl Report {
+  fn percent(&self, count: u64) -> f64 {
+    100.0 * count as f64 / self.instructions.max(1) as f64
+  }
+}
+
+impl fmt::Display for Report {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "{} instructions, deepest stack {}", self.instructions, self.max_stack_depth)?;
+
+    writeln!(f, "hot spots:")?;
+    for spot in self.hot_spots.iter().take(HOT_SPOTS) {
+      writeln!(
+        f, "  {:03x}  {:>8}  {:5.1}%  {}",
+        spot.address, spot.count, self.percent(spot.count), spot.instruction,
+      )?;
+    }
+
+    writeln!(f, "opcodes:")?;
+    for (pattern, count) in &self.opcodes {
+      writeln!(f, "  {}  {:>8}  {:5.1}%", pattern, count, self.percent(*count))?;
+    }
+
+    writeln!(f, "calls:")?;
+    if self.calls.is_empty() {
+      writeln!(f, "  none")?;
+    }
+    for edge in &self.calls {
+      writeln!(f, "  {:03x} -> {:03x}  {:>8}", edge.caller, edge.callee, edge.count)?;
+    }
+
+    writeln!(f, "never executed:")?;
+    if self.never_executed.is_empty() {
+      writeln!(f, "  none")?;
+    }
+    for range in &self.never_executed {
+      writeln!(f, "  {:03x}..{:03x}  {} bytes", range.start, range.end, range.len())?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{assemble, PROGRAM_START};
+
+  const PROGRAM: &str = "
+            LD   V0, 3
+    loop:   CALL twice
+            ADD  V0, 0xff
+            SE   V0, 0
+            JP   loop
+            HALT
+            JP   loop
+    twice:  CALL once
+            CALL once
+            RET
+    once:   ADD  V1, 1
+            RET
+  ";
+
+  fn profile(source: &str) -> Report {
+    let rom = assemble(source).unwrap();
+    let mut cpu = CPU::with_seed(1);
+    cpu.load_rom(&rom).unwrap();
+
+    let mut profiler = Profiler::new(cpu, PROGRAM_START..PROGRAM_START + rom.len());
+    while profiler.step().unwrap() {}
+    profiler.finish().1
+  }
+
+  #[test]
+  fn counts_addresses_opcodes_and_calls() {
+    let report = profile(PROGRAM);
+
+    assert_eq!(report.instructions, 1 + 3 * 11);
+    assert_eq!(report.max_stack_depth, 2);
+    assert_eq!(report.hot_spots[0], HotSpot { address: 0x214, count: 6, instruction: String::from("ADD V1, 0x01") });
+    assert_eq!(report.hot_spots[1].address, 0x216);
+    assert_eq!(report.opcodes[..3], [("00EE", 9), ("2NNN", 9), ("7XNN", 9)]);
+    assert_eq!(report.calls, [
+      CallEdge { caller: 0x200, callee: 0x20e, count: 3 },
+      CallEdge { caller: 0x20e, callee: 0x214, count: 6 },
+    ]);
+    assert_eq!(report.never_executed, vec![0x20c..0x20e]);
+  }
+
+  #[test]
+  fn reports_read_as_text() {
+    let report = profile("CALL sub\nHALT\nDB 0xff, 0xff\nsub: RET");
+
+    assert_eq!(report.to_string(), "\
+3 instructions, deepest stack 1
+hot spots:
+  200         1   33.3%  CALL 0x206
+  202         1   33.3%  HALT
+  206         1   33.3%  RET
+opcodes:
+  0000         1   33.3%
+  00EE         1   33.3%
+  2NNN         1   33.3%
+calls:
+  200 -> 206         1
+never executed:
+  204..206  2 bytes
+");
+  }
+}
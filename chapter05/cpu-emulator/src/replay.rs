@@ -0,0 +1,262 @@
+//! Input recording and deterministic replay.
+//!
+//! A `Recorder` wraps a `CPU` and logs every input it is given against the
+//! number of instructions executed so far. Timer ticks count as inputs,
+//! since hosts drive them from a wall clock. Together with a snapshot of the
+//! starting state, which includes the random number generator, that is
+//! enough to reproduce the run exactly.
+//!
+//! Recordings are stored big-endian as:
+//!
+//! ```text
+//! "C8RC" | version u8 | snapshot length u32 | snapshot | instructions u64
+//! | event count u32 | events: (instruction u64 | kind u8 | key u8)
+//! ```
+//!
+//! where kind is 0 for a key press, 1 for a release and 2 for a timer tick.
+
+use std::fs;
+use std::path::Path;
+
+use crate::clock::Clocked;
+use crate::snapshot::{Fields, SnapshotError};
+use crate::{CpuFault, CPU};
+
+const MAGIC: &[u8; 4] = b"C8RC";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+  KeyDown(u8),
+  KeyUp(u8),
+  TimerTick,
+}
+
+impl InputEvent {
+  fn apply(self, cpu: &mut CPU) {
+    match self {
+      InputEvent::KeyDown(key) => cpu.keypad.press(key),
+      InputEvent::KeyUp(key) => cpu.keypad.release(key),
+      InputEvent::TimerTick => cpu.tick_timers(),
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct Recording {
+  start: CPU,
+  /// Each event with the number of instructions executed before it.
+  events: Vec<(u64, InputEvent)>,
+  instructions: u64,
+}
+
+impl Recording {
+  pub fn events(&self) -> &[(u64, InputEvent)] {
+    &self.events
+  }
+
+  pub fn instructions(&self) -> u64 {
+    self.instructions
+  }
+
+  /// Re-runs the recorded session from its starting state. A fault means
+  /// the recording does not match the program it was made with.
+  pub fn replay(&self) -> Result<CPU, CpuFault> {
+    let mut cpu = self.start.clone();
+    let mut events = self.events.iter().peekable();
+
+    for executed in 0..self.instructions {
+      while let Some((_, event)) = events.next_if(|(at, _)| *at == executed) {
+        event.apply(&mut cpu);
+      }
+      cpu.step()?;
+    }
+
+    for (_, event) in events {
+      event.apply(&mut cpu);
+    }
+    Ok(cpu)
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let snapshot = self.start.snapshot();
+    let mut out = Vec::with_capacity(snapshot.len() + 32 + 10 * self.events.len());
+
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&(snapshot.len() as u32).to_be_bytes());
+    out.extend_from_slice(&snapshot);
+    out.extend_from_slice(&self.instructions.to_be_bytes());
+    out.extend_from_slice(&(self.events.len() as u32).to_be_bytes());
+
+    for (at, event) in &self.events {
+      let (kind, key) = match *event {
+        InputEvent::KeyDown(key) => (0, key),
+        InputEvent::KeyUp(key) => (1, key),
+        InputEvent::TimerTick => (2, 0),
+      };
+      out.extend_from_slice(&at.to_be_bytes());
+      out.extend_from_slice(&[kind, key]);
+    }
+
+    out
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Recording, SnapshotError> {
+    let invalid = |detail: String| SnapshotError::Invalid(detail);
+    let mut fields = Fields::new(bytes);
+
+    if fields.take(4)? != MAGIC {
+      return Err(invalid(String::from("not a CHIP-8 recording")));
+    }
+    let version = fields.u8()?;
+    if version != VERSION {
+      return Err(invalid(format!("unsupported recording version {}", version)));
+    }
+
+    let snapshot_len = fields.u32()? as usize;
+    let start = CPU::from_snapshot(fields.take(snapshot_len)?)?;
+    let instructions = fields.u64()?;
+
+    let count = fields.u32()?;
+    let mut events = vec![];
+    let mut previous = 0;
+    for _ in 0..count {
+      let at = fields.u64()?;
+      let event = match (fields.u8()?, fields.u8()?) {
+        (0, key) if key < 16 => InputEvent::KeyDown(key),
+        (1, key) if key < 16 => InputEvent::KeyUp(key),
+        (2, _) => InputEvent::TimerTick,
+        (kind, key) => return Err(invalid(format!("invalid event {} for key {}", kind, key))),
+      };
+      if at < previous || at > instructions {
+        return Err(invalid(format!("event at instruction {} is out of order", at)));
+      }
+      previous = at;
+      events.push((at, event));
+    }
+    fields.finish()?;
+
+    Ok(Recording { start, events, instructions })
+  }
+
+  pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+    fs::write(path, self.to_bytes())?;
+    Ok(())
+  }
+
+  pub fn load(path: &Path) -> Result<Recording, SnapshotError> {
+    Recording::from_bytes(&fs::read(path)?)
+  }
+}
+
+/// Runs a `CPU`, logging its inputs. Inputs must go through the recorder
+/// for the recording to replay faithfully, so the CPU is only lent out
+/// read-only.
+pub struct Recorder {
+  cpu: CPU,
+  recording: Recording,
+}
+
+impl Recorder {
+  /// Starts recording from `cpu`'s current state.
+  pub fn new(cpu: CPU) -> Self {
+    let recording = Recording { start: cpu.clone(), events: vec![], instructions: 0 };
+    Recorder { cpu, recording }
+  }
+
+  pub fn cpu(&self) -> &CPU {
+    &self.cpu
+  }
+
+  pub fn input(&mut self, event: InputEvent) {
+    event.apply(&mut self.cpu);
+    self.recording.events.push((self.recording.instructions, event));
+  }
+
+  /// Executes one instruction, as `CPU::step`. Faulting instructions are not
+  /// executed and so are not recorded.
+  pub fn step(&mut self) -> Result<bool, CpuFault> {
+    let running = self.cpu.step()?;
+    self.recording.instructions += 1;
+    Ok(running)
+  }
+
+  pub fn finish(self) -> (CPU, Recording) {
+    (self.cpu, self.recording)
+  }
+}
+
+/// Timer ticks are inputs as far as a recording is concerned.
+impl Clocked for Recorder {
+  type Fault = CpuFault;
+
+  fn step(&mut self) -> Result<bool, CpuFault> {
+    Recorder::step(self)
+  }
+
+  fn tick_timers(&mut self) {
+    self.input(InputEvent::TimerTick)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assemble;
+
+  /// Waits for a key, then counts down from a random delay.
+  const GAME: &str = "
+            LD   V0, K
+            RND  V1, 0x3f
+            LD   DT, V1
+    wait:   LD   V2, DT
+            SE   V2, 0
+            JP   wait
+            LD   I, 0x300
+            LD   B, V1
+            HALT
+  ";
+
+  #[test]
+  fn replay_reproduces_the_run() {
+    let mut cpu = CPU::with_seed(99);
+    cpu.load_rom(&assemble(GAME).unwrap()).unwrap();
+
+    let mut recorder = Recorder::new(cpu);
+    let mut executed = 0;
+    loop {
+      match executed {
+        5 => recorder.input(InputEvent::KeyDown(0xb)),
+        9 => recorder.input(InputEvent::KeyUp(0xb)),
+        _ => {},
+      }
+      if executed % 4 == 0 {
+        recorder.input(InputEvent::TimerTick);
+      }
+      if !recorder.step().unwrap() {
+        break;
+      }
+      executed += 1;
+    }
+
+    let (live, recording) = recorder.finish();
+    assert_eq!(live.registers[0], 0xb);
+
+    let restored = Recording::from_bytes(&recording.to_bytes()).unwrap();
+    assert_eq!(restored.events(), recording.events());
+
+    let replayed = restored.replay().unwrap();
+    assert_eq!(replayed.snapshot(), live.snapshot());
+  }
+
+  #[test]
+  fn mismatched_recordings_are_rejected() {
+    let recording = Recorder::new(CPU::with_seed(1)).finish().1;
+    let mut bytes = recording.to_bytes();
+    bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 3, 0]);
+
+    assert!(Recording::from_bytes(&bytes).is_err());
+    assert!(Recording::from_bytes(&CPU::with_seed(1).snapshot()).is_err());
+  }
+}
@@ -0,0 +1,90 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::CPU;
+
+/// Where CHIP-8 programs conventionally start: the interpreter itself used
+/// to live in the first 512 bytes.
+pub const PROGRAM_START: usize = 0x200;
+/// The largest ROM a 4 KiB machine can load. XO-CHIP has room for more.
+pub const MAX_ROM_LEN: usize = 4096 - PROGRAM_START;
+
+#[derive(Debug)]
+pub enum RomError {
+  Io(io::Error),
+  TooLarge { len: usize, max: usize },
+}
+
+impl fmt::Display for RomError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RomError::Io(err) => write!(f, "unable to read ROM: {}", err),
+      RomError::TooLarge { len, max } => {
+        write!(f, "ROM is {} bytes, but at most {} fit in memory", len, max)
+      },
+    }
+  }
+}
+
+impl Error for RomError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      RomError::Io(err) => Some(err),
+      RomError::TooLarge { .. } => None,
+    }
+  }
+}
+
+impl From<io::Error> for RomError {
+  fn from(err: io::Error) -> Self {
+    RomError::Io(err)
+  }
+}
+
+impl CPU {
+  /// Copies `rom` to `PROGRAM_START` and points the program counter at it.
+  pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), RomError> {
+    let max = self.memory.len() - PROGRAM_START;
+    if rom.len() > max {
+      return Err(RomError::TooLarge { len: rom.len(), max });
+    }
+
+    self.memory[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+    self.program_counter = PROGRAM_START;
+    Ok(())
+  }
+
+  pub fn load_rom_file(&mut self, path: &Path) -> Result<(), RomError> {
+    let rom = fs::read(path)?;
+    self.load_rom(&rom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Profile;
+
+  #[test]
+  fn rom_runs_from_program_start() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.load_rom(&[0x60, 0x07, 0x00, 0x00]).unwrap();
+
+    assert_eq!(cpu.program_counter, PROGRAM_START);
+    cpu.run().unwrap();
+    assert_eq!(cpu.registers[0], 7);
+  }
+
+  #[test]
+  fn oversized_rom_is_rejected() {
+    let mut cpu = CPU::with_seed(1);
+    let rom = vec![0; MAX_ROM_LEN + 1];
+
+    assert!(matches!(cpu.load_rom(&rom), Err(RomError::TooLarge { .. })));
+    assert!(cpu.load_rom(&rom[1..]).is_ok());
+    assert!(CPU::with_profile(Profile::XoChip, 1).load_rom(&rom).is_ok());
+  }
+}
@@ -0,0 +1,413 @@
+//! Save states. A snapshot holds everything that affects how a program runs
+//! from here on: the machine profile and quirks, registers, memory, stack,
+//! timers, screen, keypad and the state of the `CXNN` random number
+//! generator.
+//!
+//! The format is big-endian:
+//!
+//! ```text
+//! "C8SS" | version u8 | profile u8 | quirks u8 | planes u8 | pitch u8
+//! | flags 16 x u8 | audio pattern 16 x u8 | PC u32 | I u16 | SP u8 | DT u8
+//! | ST u8 | V0-VF | stack 16 x u16 | RNG state u64
+//! | keys held u16 (bit n = key n)
+//! | key released while FX0A waits u8 (0xfe = none yet, 0xff = not waiting)
+//! | width u16 | height u16 | for each plane: pixels, 8 per byte, MSB first
+//! | memory, 4 KiB or 64 KiB depending on the profile
+//! ```
+//!
+//! Version 1 snapshots, which predate profiles, lack the fields between
+//! the version and PC and have a single plane. They restore as plain
+//! CHIP-8. Versions 1 and 2 store the PC as a u16, which cannot hold the
+//! end of XO-CHIP memory.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::display::{HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, PLANES, WIDTH};
+use crate::instruction::Instruction;
+use crate::{Display, Keypad, Profile, Quirks, Rng, CPU};
+
+const MAGIC: &[u8; 4] = b"C8SS";
+const VERSION: u8 = 3;
+const NO_KEY: u8 = 0xff;
+const WAITING: u8 = 0xfe;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+  Io(io::Error),
+  /// The bytes are not a snapshot this version can restore.
+  Invalid(String),
+}
+
+impl fmt::Display for SnapshotError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SnapshotError::Io(err) => write!(f, "unable to access snapshot: {}", err),
+      SnapshotError::Invalid(detail) => write!(f, "invalid snapshot: {}", detail),
+    }
+  }
+}
+
+impl Error for SnapshotError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      SnapshotError::Io(err) => Some(err),
+      SnapshotError::Invalid(_) => None,
+    }
+  }
+}
+
+impl From<io::Error> for SnapshotError {
+  fn from(err: io::Error) -> Self {
+    SnapshotError::Io(err)
+  }
+}
+
+/// Reads fields off the front of a byte slice.
+pub(crate) struct Fields<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+  pub(crate) fn new(bytes: &'a [u8]) -> Self {
+    Fields { bytes }
+  }
+
+  pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+    if self.bytes.len() < len {
+      return Err(SnapshotError::Invalid(String::from("data is cut short")));
+    }
+    let (head, rest) = self.bytes.split_at(len);
+    self.bytes = rest;
+    Ok(head)
+  }
+
+  pub(crate) fn u8(&mut self) -> Result<u8, SnapshotError> {
+    Ok(self.take(1)?[0])
+  }
+
+  pub(crate) fn u16(&mut self) -> Result<u16, SnapshotError> {
+    Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+  }
+
+  pub(crate) fn u32(&mut self) -> Result<u32, SnapshotError> {
+    Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  pub(crate) fn u64(&mut self) -> Result<u64, SnapshotError> {
+    Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  pub(crate) fn finish(self) -> Result<(), SnapshotError> {
+    match self.bytes.len() {
+      0 => Ok(()),
+      extra => Err(SnapshotError::Invalid(format!("{} unexpected trailing bytes", extra))),
+    }
+  }
+}
+
+impl CPU {
+  pub fn snapshot(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(self.memory.len() + 2048);
+
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&[self.profile.to_byte(), self.quirks.to_bits(), self.planes, self.pitch]);
+    out.extend_from_slice(&self.flags);
+    out.extend_from_slice(&self.audio_pattern);
+    out.extend_from_slice(&(self.program_counter as u32).to_be_bytes());
+    out.extend_from_slice(&self.index_register.to_be_bytes());
+    out.extend_from_slice(&[self.stack_pointer as u8, self.delay_timer, self.sound_timer]);
+    out.extend_from_slice(&self.registers);
+    for address in &self.stack {
+      out.extend_from_slice(&address.to_be_bytes());
+    }
+    out.extend_from_slice(&self.rng.0.to_be_bytes());
+
+    let held = (0..16).filter(|key| self.keypad.pressed[*key]).fold(0u16, |bits, key| bits | 1 << key);
+    out.extend_from_slice(&held.to_be_bytes());
+    out.push(match self.keypad.released {
+      Some(key) => key,
+      None if self.keypad.waiting => WAITING,
+      None => NO_KEY,
+    });
+
+    out.extend_from_slice(&(self.display.width as u16).to_be_bytes());
+    out.extend_from_slice(&(self.display.height as u16).to_be_bytes());
+    for plane in 0..PLANES {
+      for pixels in self.display.pixels.chunks(8) {
+        let byte = pixels.iter().enumerate()
+          .fold(0u8, |byte, (i, planes)| byte | (planes >> plane & 1) << (7 - i));
+        out.push(byte);
+      }
+    }
+
+    out.extend_from_slice(&self.memory);
+    out
+  }
+
+  pub fn from_snapshot(bytes: &[u8]) -> Result<CPU, SnapshotError> {
+    let invalid = |detail: &str| SnapshotError::Invalid(detail.to_string());
+    let mut fields = Fields::new(bytes);
+
+    if fields.take(4)? != MAGIC {
+      return Err(invalid("not a CHIP-8 snapshot"));
+    }
+    let version = fields.u8()?;
+    if !(1..=VERSION).contains(&version) {
+      return Err(SnapshotError::Invalid(format!("unsupported version {}", version)));
+    }
+
+    let mut cpu = match version {
+      1 => CPU::with_seed(1),
+      _ => {
+        let profile = Profile::from_byte(fields.u8()?).ok_or_else(|| invalid("unknown profile"))?;
+        let mut cpu = CPU::with_profile(profile, 1);
+        cpu.quirks = Quirks::from_bits(fields.u8()?);
+        cpu.planes = fields.u8()?;
+        cpu.pitch = fields.u8()?;
+        cpu.flags.copy_from_slice(fields.take(16)?);
+        cpu.audio_pattern.copy_from_slice(fields.take(16)?);
+        cpu
+      },
+    };
+    cpu.program_counter = if version < 3 { fields.u16()? as usize } else { fields.u32()? as usize };
+    cpu.index_register = fields.u16()?;
+    cpu.stack_pointer = fields.u8()? as usize;
+    cpu.delay_timer = fields.u8()?;
+    cpu.sound_timer = fields.u8()?;
+    if cpu.stack_pointer > cpu.stack.len() {
+      return Err(invalid("stack pointer is past the end of the stack"));
+    }
+
+    cpu.registers.copy_from_slice(fields.take(16)?);
+    for address in cpu.stack.iter_mut() {
+      *address = fields.u16()?;
+    }
+    cpu.rng = Rng(fields.u64()?);
+    if cpu.rng.0 == 0 {
+      return Err(invalid("random number generator state is zero"));
+    }
+
+    let held = fields.u16()?;
+    let mut keypad = Keypad::new();
+    for key in 0..16 {
+      keypad.pressed[key] = held & 1 << key != 0;
+    }
+    match fields.u8()? {
+      NO_KEY => {},
+      WAITING => keypad.waiting = true,
+      key if key < 16 => {
+        keypad.waiting = true;
+        keypad.released = Some(key);
+      },
+      _ => return Err(invalid("released key is out of range")),
+    }
+    cpu.keypad = keypad;
+
+    let width = fields.u16()? as usize;
+    let height = fields.u16()? as usize;
+    let hires = cpu.profile.supports(&Instruction::Hires) && (width, height) == (HIRES_WIDTH, HIRES_HEIGHT);
+    if (width, height) != (WIDTH, HEIGHT) && !hires {
+      return Err(SnapshotError::Invalid(format!("unsupported screen size {}x{}", width, height)));
+    }
+    let planes = if version == 1 { 1 } else { PLANES };
+    let mut display = Display::new(width, height);
+    for plane in 0..planes {
+      let packed = fields.take((width * height).div_ceil(8))?;
+      for (i, pixel) in display.pixels.iter_mut().enumerate() {
+        *pixel |= (packed[i / 8] >> (7 - i % 8) & 1) << plane;
+      }
+    }
+    cpu.display = display;
+
+    let memory = fields.take(cpu.memory.len())?;
+    cpu.memory.copy_from_slice(memory);
+    fields.finish()?;
+
+    Ok(cpu)
+  }
+
+  pub fn save_snapshot(&self, path: &Path) -> Result<(), SnapshotError> {
+    fs::write(path, self.snapshot())?;
+    Ok(())
+  }
+
+  pub fn load_snapshot(path: &Path) -> Result<CPU, SnapshotError> {
+    CPU::from_snapshot(&fs::read(path)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assemble;
+
+  #[test]
+  fn restored_cpu_carries_on_identically() {
+    let mut cpu = CPU::with_seed(7);
+    cpu.load_rom(&assemble("
+            LD   V1, 0
+      loop: RND  V0, 0xff
+            LD   F, V0
+            DRW  V1, V1, 5
+            ADD  V1, 3
+            CALL sub
+            JP   loop
+      sub:  RET
+    ").unwrap()).unwrap();
+    cpu.keypad.press(0x3);
+    cpu.keypad.press(0xc);
+    cpu.keypad.release(0xc);
+    cpu.delay_timer = 9;
+
+    for _ in 0..50 {
+      cpu.step().unwrap();
+    }
+
+    let bytes = cpu.snapshot();
+    let mut restored = CPU::from_snapshot(&bytes).unwrap();
+    assert_eq!(restored.snapshot(), bytes);
+    assert_eq!(restored.keypad, cpu.keypad);
+    assert_eq!(restored.display, cpu.display);
+
+    for _ in 0..50 {
+      cpu.step().unwrap();
+      restored.step().unwrap();
+    }
+    assert_eq!(restored.snapshot(), cpu.snapshot());
+  }
+
+  #[test]
+  fn pending_key_wait_survives_a_round_trip() {
+    let mut cpu = CPU::with_seed(1);
+    cpu.load_rom(&assemble("LD V2, K\nHALT").unwrap()).unwrap();
+    cpu.keypad.press(0x5);
+    cpu.keypad.release(0x5);
+    cpu.step().unwrap();
+
+    let mut restored = CPU::from_snapshot(&cpu.snapshot()).unwrap();
+    assert_eq!(restored.keypad, cpu.keypad);
+    restored.keypad.press(0x9);
+    restored.keypad.release(0x9);
+    restored.run().unwrap();
+    assert_eq!(restored.registers[2], 0x9);
+  }
+
+  #[test]
+  fn damaged_snapshots_are_rejected() {
+    let bytes = CPU::with_seed(1).snapshot();
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[4] = 9;
+
+    for (bytes, detail) in [
+      (&bytes[..bytes.len() - 1], "data is cut short"),
+      (&b"C8RC"[..], "not a CHIP-8 snapshot"),
+      (&wrong_version[..], "unsupported version 9"),
+    ] {
+      match CPU::from_snapshot(bytes) {
+        Err(SnapshotError::Invalid(found)) => assert_eq!(found, detail),
+        other => panic!("expected {:?}, got {:?}", detail, other.map(|_| ())),
+      }
+    }
+
+    let mut long = bytes;
+    long.push(0);
+    assert!(CPU::from_snapshot(&long).is_err());
+  }
+
+  /// Overwrites the screen size of a low-resolution snapshot.
+  fn with_screen_size(mut bytes: Vec<u8>, width: u16, height: u16) -> Vec<u8> {
+    let at = bytes.len() - 4096 - PLANES as usize * WIDTH * HEIGHT / 8 - 4;
+    bytes[at..at + 2].copy_from_slice(&width.to_be_bytes());
+    bytes[at + 2..at + 4].copy_from_slice(&height.to_be_bytes());
+    bytes
+  }
+
+  #[test]
+  fn odd_screen_sizes_are_rejected() {
+    let bytes = CPU::with_seed(1).snapshot();
+
+    for (width, height) in [(0, 0), (0xffff, 0xffff), (64, 0)] {
+      match CPU::from_snapshot(&with_screen_size(bytes.clone(), width, height)) {
+        Err(SnapshotError::Invalid(found)) => assert_eq!(found, format!("unsupported screen size {}x{}", width, height)),
+        other => panic!("{}x{} gave {:?}", width, height, other.map(|_| ())),
+      }
+    }
+  }
+
+  #[test]
+  fn hires_screens_need_a_hires_profile() {
+    let bytes = with_screen_size(CPU::with_seed(1).snapshot(), 128, 64);
+    match CPU::from_snapshot(&bytes) {
+      Err(SnapshotError::Invalid(found)) => assert_eq!(found, "unsupported screen size 128x64"),
+      other => panic!("expected a rejection, got {:?}", other.map(|_| ())),
+    }
+
+    let mut cpu = CPU::with_profile(Profile::SuperChip, 1);
+    cpu.display.set_hires(true);
+    cpu.display.draw_sprite(127, 63, &[0x80]);
+    let restored = CPU::from_snapshot(&cpu.snapshot()).unwrap();
+    assert!(restored.display.is_hires());
+    assert!(restored.display.pixel(127, 63));
+  }
+
+  #[test]
+  fn profiles_and_planes_survive_a_round_trip() {
+    let mut cpu = CPU::with_profile(Profile::XoChip, 3);
+    cpu.quirks.jump_uses_vx = true;
+    cpu.load_rom(&assemble("
+      HIGH
+      PLANE 3
+      LD   I, LONG sprite
+      LD   V0, 0x7f
+      LD   R, V0
+      DRW  V0, V0, 1
+      HALT
+      ORG  0x8000
+      sprite: DB 0xc0, 0x80
+    ").unwrap()).unwrap();
+    cpu.run().unwrap();
+
+    let restored = CPU::from_snapshot(&cpu.snapshot()).unwrap();
+    assert_eq!(restored.profile(), Profile::XoChip);
+    assert_eq!(restored.quirks, cpu.quirks);
+    assert_eq!(restored.flags, cpu.flags);
+    assert_eq!(restored.display.planes(0x7f, 0x3f), 3);
+    assert_eq!(restored.display.planes(0, 0x3f), 1);
+    assert_eq!(restored.memory, cpu.memory);
+  }
+
+  #[test]
+  fn version_1_snapshots_restore_as_chip8() {
+    let mut cpu = CPU::with_seed(5);
+    cpu.display.draw_sprite(0, 0, &[0xa0]);
+    cpu.registers[3] = 9;
+
+    // Version 1 is version 3 without the profile block, with a 16-bit PC
+    // and one plane.
+    let v3 = cpu.snapshot();
+    let pixels = 64 * 32 / 8;
+    let header = 5 + 4 + 32;
+    let display_end = v3.len() - 4096;
+    let mut v1 = [&MAGIC[..], &[1]].concat();
+    v1.extend_from_slice(&v3[header + 2..display_end - pixels]);
+    v1.extend_from_slice(&v3[display_end..]);
+
+    let restored = CPU::from_snapshot(&v1).unwrap();
+    assert_eq!(restored.snapshot(), v3);
+  }
+
+  #[test]
+  fn pc_at_the_end_of_xo_chip_memory_survives_a_round_trip() {
+    let mut cpu = CPU::with_profile(Profile::XoChip, 1);
+    cpu.program_counter = 0x10000;
+
+    let restored = CPU::from_snapshot(&cpu.snapshot()).unwrap();
+    assert_eq!(restored.program_counter, 0x10000);
+  }
+}
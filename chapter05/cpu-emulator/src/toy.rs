@@ -0,0 +1,341 @@
+//! A toy RISC machine, to show that `isa`, the debugger and the clock are
+//! not tied to CHIP-8. It has four 8-bit registers, a 256-byte address
+//! space on a `MappedBus`, and two-byte instructions: an opcode nibble, two
+//! 2-bit register fields and an immediate byte.
+//!
+//! ```text
+//! 0x0-   HALT
+//! 0x1a   LI   ra, imm        ra = imm
+//! 0x2a   ADD  ra, rb         ra += rb
+//! 0x3a   SUB  ra, rb         ra -= rb
+//! 0x4a   ADDI ra, imm        ra += imm
+//! 0x5a   LD   ra, [imm]      ra = memory[imm]
+//! 0x6a   ST   ra, [imm]      memory[imm] = ra
+//! 0x7a   BNZ  ra, imm        jump to imm if ra != 0
+//! 0x8a   DIV  ra, rb         ra /= rb, faulting on division by zero
+//! ```
+//!
+//! Arithmetic wraps. Loads from addresses where nothing answers, and stores
+//! a device refuses, are bus errors.
+
+use std::fmt;
+
+use crate::bus::{MappedBus, Ram};
+use crate::clock::Clocked;
+use crate::isa::{self, Bus, Decoder, Executor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToyInstruction {
+  Halt,
+  Li { a: u8, imm: u8 },
+  Add { a: u8, b: u8 },
+  Sub { a: u8, b: u8 },
+  Addi { a: u8, imm: u8 },
+  Ld { a: u8, address: u8 },
+  St { a: u8, address: u8 },
+  Bnz { a: u8, target: u8 },
+  Div { a: u8, b: u8 },
+}
+
+impl ToyInstruction {
+  pub fn encode(&self) -> [u8; 2] {
+    use ToyInstruction::*;
+
+    let op = |op: u8, a: u8, b: u8| op << 4 | (a & 3) << 2 | (b & 3);
+    match *self {
+      Halt => [0x00, 0x00],
+      Li { a, imm } => [op(0x1, a, 0), imm],
+      Add { a, b } => [op(0x2, a, b), 0],
+      Sub { a, b } => [op(0x3, a, b), 0],
+      Addi { a, imm } => [op(0x4, a, 0), imm],
+      Ld { a, address } => [op(0x5, a, 0), address],
+      St { a, address } => [op(0x6, a, 0), address],
+      Bnz { a, target } => [op(0x7, a, 0), target],
+      Div { a, b } => [op(0x8, a, b), 0],
+    }
+  }
+}
+
+impl fmt::Display for ToyInstruction {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    use ToyInstruction::*;
+
+    match *self {
+      Halt => write!(f, "HALT"),
+      Li { a, imm } => write!(f, "LI r{}, {}", a, imm),
+      Add { a, b } => write!(f, "ADD r{}, r{}", a, b),
+      Sub { a, b } => write!(f, "SUB r{}, r{}", a, b),
+      Addi { a, imm } => write!(f, "ADDI r{}, {}", a, imm as i8),
+      Ld { a, address } => write!(f, "LD r{}, [0x{:02x}]", a, address),
+      St { a, address } => write!(f, "ST r{}, [0x{:02x}]", a, address),
+      Bnz { a, target } => write!(f, "BNZ r{}, 0x{:02x}", a, target),
+      Div { a, b } => write!(f, "DIV r{}, r{}", a, b),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToyDecoder;
+
+impl Decoder for ToyDecoder {
+  type Instruction = ToyInstruction;
+
+  const UNIT: usize = 2;
+
+  fn decode(&self, bus: &dyn Bus, address: usize) -> Option<(ToyInstruction, usize)> {
+    use ToyInstruction::*;
+
+    let (op, imm) = (bus.peek(address)?, bus.peek(address + 1)?);
+    let (a, b) = (op >> 2 & 3, op & 3);
+    let instruction = match op >> 4 {
+      0x0 if op == 0 && imm == 0 => Halt,
+      0x1 if b == 0 => Li { a, imm },
+      0x2 if imm == 0 => Add { a, b },
+      0x3 if imm == 0 => Sub { a, b },
+      0x4 if b == 0 => Addi { a, imm },
+      0x5 if b == 0 => Ld { a, address: imm },
+      0x6 if b == 0 => St { a, address: imm },
+      0x7 if b == 0 => Bnz { a, target: imm },
+      0x8 if imm == 0 => Div { a, b },
+      _ => return None,
+    };
+    Some((instruction, 2))
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToyFault {
+  Undecodable { pc: usize },
+  DivideByZero { pc: usize },
+  BusError { pc: usize, address: u8 },
+}
+
+impl fmt::Display for ToyFault {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ToyFault::Undecodable { pc } => write!(f, "no instruction at 0x{:02x}", pc),
+      ToyFault::DivideByZero { pc } => write!(f, "division by zero at 0x{:02x}", pc),
+      ToyFault::BusError { pc, address } => write!(f, "bus error at 0x{:02x} accessing 0x{:02x}", pc, address),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Toy {
+  pub pc: usize,
+  pub registers: [u8; 4],
+  pub bus: MappedBus,
+}
+
+impl Toy {
+  /// A machine with 256 bytes of RAM, holding `program` from address 0.
+  pub fn new(program: &[ToyInstruction]) -> Self {
+    let mut ram = assemble(program);
+    ram.resize(256, 0);
+    Toy::with_bus(MappedBus::new(256).with(0, Ram::from(ram)))
+  }
+
+  /// A machine starting at address 0 of `bus`, which should be 256 bytes.
+  pub fn with_bus(bus: MappedBus) -> Self {
+    Toy { pc: 0, registers: [0; 4], bus }
+  }
+
+  pub fn step(&mut self) -> Result<bool, ToyFault> {
+    isa::step(self)
+  }
+}
+
+/// The bytes of `program`.
+pub fn assemble(program: &[ToyInstruction]) -> Vec<u8> {
+  program.iter().flat_map(ToyInstruction::encode).collect()
+}
+
+const REGISTER_NAMES: [&str; 4] = ["r0", "r1", "r2", "r3"];
+
+impl Executor for Toy {
+  type Decoder = ToyDecoder;
+  type Fault = ToyFault;
+
+  fn decoder(&self) -> &ToyDecoder {
+    &ToyDecoder
+  }
+
+  fn bus(&self) -> &dyn Bus {
+    &self.bus
+  }
+
+  fn pc(&self) -> usize {
+    self.pc
+  }
+
+  fn set_pc(&mut self, pc: usize) {
+    self.pc = pc;
+  }
+
+  fn undecodable(&self, pc: usize) -> ToyFault {
+    ToyFault::Undecodable { pc }
+  }
+
+  fn execute(&mut self, instruction: ToyInstruction) -> Result<bool, ToyFault> {
+    use ToyInstruction::*;
+
+    let r = self.registers;
+    let reg = |x: u8| r[x as usize];
+    let pc = self.pc - 2;
+
+    let (a, value) = match instruction {
+      Halt => return Ok(false),
+      Li { a, imm } => (a, imm),
+      Add { a, b } => (a, reg(a).wrapping_add(reg(b))),
+      Sub { a, b } => (a, reg(a).wrapping_sub(reg(b))),
+      Addi { a, imm } => (a, reg(a).wrapping_add(imm)),
+      Ld { a, address } => match self.bus.read(address as usize) {
+        Some(value) => (a, value),
+        None => return Err(ToyFault::BusError { pc, address }),
+      },
+      Div { a, b } => match reg(a).checked_div(reg(b)) {
+        Some(quotient) => (a, quotient),
+        None => return Err(ToyFault::DivideByZero { pc }),
+      },
+      St { a, address } => {
+        if !self.bus.write(address as usize, reg(a)) {
+          return Err(ToyFault::BusError { pc, address });
+        }
+        return Ok(true);
+      },
+      Bnz { a, target } => {
+        if reg(a) != 0 {
+          self.pc = target as usize;
+        }
+        return Ok(true);
+      },
+    };
+    self.registers[a as usize] = value;
+    Ok(true)
+  }
+
+  fn registers(&self) -> Vec<(&'static str, u64)> {
+    REGISTER_NAMES.iter().zip(self.registers).map(|(name, value)| (*name, value as u64)).collect()
+  }
+}
+
+/// Timer ticks go to the devices.
+impl Clocked for Toy {
+  type Fault = ToyFault;
+
+  fn step(&mut self) -> Result<bool, ToyFault> {
+    Toy::step(self)
+  }
+
+  fn tick_timers(&mut self) {
+    self.bus.tick();
+  }
+}
+
+impl fmt::Display for Toy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "pc={:02x}", self.pc)?;
+    for (name, value) in REGISTER_NAMES.iter().zip(self.registers) {
+      write!(f, " {}={:02x}", name, value)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::clock::Clock;
+  use crate::bus::{Console, Rom};
+  use crate::debugger::Debugger;
+  use ToyInstruction::*;
+
+  /// Adds up 5 + 4 + 3 + 2 + 1 and stores the total at 0x80.
+  const SUM: [ToyInstruction; 7] = [
+    Li { a: 0, imm: 0 },
+    Li { a: 1, imm: 5 },
+    Add { a: 0, b: 1 },
+    Addi { a: 1, imm: 0xff },
+    Bnz { a: 1, target: 4 },
+    St { a: 0, address: 0x80 },
+    Halt,
+  ];
+
+  #[test]
+  fn runs_through_the_generic_loop() {
+    let mut toy = Toy::new(&SUM);
+    isa::run(&mut toy).unwrap();
+
+    assert_eq!(toy.registers[..2], [15, 0]);
+    assert_eq!(toy.bus.peek(0x80), Some(15));
+    assert_eq!(isa::describe(&toy, 6), "006: 44ff  ADDI r1, -1");
+  }
+
+  #[test]
+  fn decoding_inverts_encoding() {
+    for op in 0..=255u8 {
+      for imm in [0, 1, 0x80, 0xff] {
+        if let Some((instruction, 2)) = ToyDecoder.decode(&vec![op, imm], 0) {
+          assert_eq!(instruction.encode(), [op, imm]);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn faults_leave_the_pc_on_the_instruction() {
+    let mut toy = Toy::new(&[Li { a: 0, imm: 6 }, Div { a: 0, b: 1 }]);
+    assert_eq!(isa::run(&mut toy), Err(ToyFault::DivideByZero { pc: 2 }));
+    assert_eq!((toy.pc, toy.registers[0]), (2, 6));
+
+    toy.bus.write(2, 0xf0);
+    assert_eq!(toy.step(), Err(ToyFault::Undecodable { pc: 2 }));
+  }
+
+  #[test]
+  fn the_debugger_and_clock_drive_it_too() {
+    let mut dbg = Debugger::new(Toy::new(&SUM));
+    let mut out = vec![];
+    for line in ["b 8", "w R0", "c", "c", "c", "r", "l 0 2"] {
+      dbg.command(line, &mut out).unwrap();
+    }
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.contains("watchpoint r0 changed from 00 to 05 after 3 instructions"), "{}", out);
+    assert!(out.contains("breakpoint at 008 after 4 instructions"), "{}", out);
+    assert!(out.contains("watchpoint r0 changed from 05 to 09"), "{}", out);
+    assert!(out.contains("pc=06 r0=09 r1=04 r2=00 r3=00"), "{}", out);
+    assert!(out.ends_with("  000: 1000  LI r0, 0\n  002: 1405  LI r1, 5\n"), "{}", out);
+
+    let mut toy = Toy::new(&SUM);
+    assert!(!Clock::default().run_cycles(&mut toy, 100).unwrap());
+  }
+
+  #[test]
+  fn loads_and_stores_do_io_on_the_bus() {
+    let echo = assemble(&[
+      Ld { a: 0, address: 0xf1 },
+      Bnz { a: 0, target: 6 },
+      Halt,
+      Ld { a: 0, address: 0xf0 },
+      St { a: 0, address: 0xf0 },
+      Li { a: 1, imm: 1 },
+      Bnz { a: 1, target: 0 },
+    ]);
+    let bus = MappedBus::new(256)
+      .with(0x00, Rom::from(echo))
+      .with(0x80, Ram::new(0x10))
+      .with(0xf0, Console::new());
+    let mut toy = Toy::with_bus(bus);
+    toy.bus.device_mut::<Console>(0xf0).unwrap().feed(b"echo");
+    isa::run(&mut toy).unwrap();
+    assert_eq!(toy.bus.device::<Console>(0xf0).unwrap().output, b"echo");
+
+
+    let faulty = assemble(&[St { a: 0, address: 0x00 }, Ld { a: 0, address: 0x40 }]);
+    let mut toy = Toy::with_bus(MappedBus::new(256).with(0x00, Rom::from(faulty)));
+    assert_eq!(toy.step(), Err(ToyFault::BusError { pc: 0, address: 0x00 }));
+    toy.pc = 2;
+    assert_eq!(toy.step(), Err(ToyFault::BusError { pc: 2, address: 0x40 }));
+  }
+}
@@ -0,0 +1,86 @@
+//! Runs every ROM in `tests/roms` against its goldens. A golden named
+//! `NAME.PROFILE.golden` belongs to `NAME.asm` or `NAME.ch8`, so third-party
+//! test ROMs can be dropped in next to the ones assembled from source.
+//!
+//! After a deliberate change in behaviour, `CHIP8_BLESS=1 cargo test` rewrites
+//! the goldens from the current emulator. Review the diff before committing.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use libcpu::conformance::{read_rom, run_headless, Golden};
+use libcpu::Profile;
+
+fn roms_dir() -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms")
+}
+
+fn rom_for(golden: &Path) -> PathBuf {
+  let file_name = golden.file_name().unwrap().to_str().unwrap();
+  let name = file_name.split('.').next().unwrap();
+
+  let source = golden.with_file_name(format!("{}.asm", name));
+  if source.exists() { source } else { golden.with_file_name(format!("{}.ch8", name)) }
+}
+
+#[test]
+fn roms_match_their_goldens() {
+  let bless = env::var_os("CHIP8_BLESS").is_some();
+  let mut goldens: Vec<PathBuf> = fs::read_dir(roms_dir())
+    .unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().is_some_and(|ext| ext == "golden"))
+    .collect();
+  goldens.sort();
+  assert!(!goldens.is_empty(), "no goldens in {}", roms_dir().display());
+
+  let mut failures = vec![];
+  for path in &goldens {
+    let golden: Golden = fs::read_to_string(path).unwrap().parse()
+      .unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    let rom = read_rom(&rom_for(path)).unwrap();
+
+    if bless {
+      let blessed = Golden::record(&rom, golden.profile, golden.cycles).unwrap();
+      fs::write(path, blessed.to_string()).unwrap();
+    } else if let Err(differences) = golden.check(&rom) {
+      failures.push(format!("{}:\n  {}", path.display(), differences.join("\n  ")));
+    }
+  }
+
+  assert!(failures.is_empty(), "{} of {} goldens failed\n{}", failures.len(), goldens.len(), failures.join("\n"));
+}
+
+/// `ibm_logo.ch8` is the widely circulated IBM logo demo, not written for
+/// this emulator, so besides its golden it is checked against the picture
+/// it is known to draw: the striped logo at (12, 8) on an otherwise blank
+/// screen.
+#[test]
+fn ibm_logo_draws_the_logo() {
+  let rom = read_rom(&roms_dir().join("ibm_logo.ch8")).unwrap();
+  let (cpu, _) = run_headless(&rom, Profile::Chip8, 100).unwrap();
+
+  let logo = [
+    "########.#########...#####.........#####",
+    "",
+    "########.###########.######.......######",
+    "",
+    "..####.....###...###...#####.....#####",
+    "",
+    "..####.....#######.....#######.#######",
+    "",
+    "..####.....#######.....###.#######.###",
+    "",
+    "..####.....###...###...###..#####..###",
+    "",
+    "########.###########.#####...###...#####",
+    "",
+    "########.#########...#####....#....#####",
+  ];
+  let expected: Vec<String> = (0..32usize)
+    .map(|y| format!("{:.<12}{:.<52}", "", y.checked_sub(8).and_then(|i| logo.get(i)).unwrap_or(&"")))
+    .collect();
+
+  assert_eq!(cpu.display.to_text().lines().collect::<Vec<_>>(), expected);
+}
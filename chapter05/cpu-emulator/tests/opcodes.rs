@@ -0,0 +1,200 @@
+//! One test per CHIP-8 opcode group, paying most attention to `VF`, which
+//! the arithmetic instructions set as a flag after writing their result.
+
+use libcpu::display::{FONT_SPRITE_LEN, FONT_START};
+use libcpu::{assemble, Profile, CPU, PROGRAM_START};
+
+/// Runs `source`, followed by `HALT`, with `registers` preset.
+fn run(source: &str, registers: &[(usize, u8)]) -> CPU {
+  let mut cpu = CPU::with_seed(1);
+  cpu.load_rom(&assemble(&format!("{}\nHALT", source)).unwrap()).unwrap();
+  for (x, value) in registers {
+    cpu.registers[*x] = *value;
+  }
+  cpu.run().unwrap();
+  cpu
+}
+
+#[test]
+fn add_sets_vf_on_carry() {
+  for (a, b, sum, carry) in [(0xff, 1, 0, 1), (0xfe, 1, 0xff, 0), (0x80, 0x80, 0, 1), (0, 0, 0, 0), (200, 100, 44, 1)] {
+    let cpu = run("ADD V0, V1", &[(0, a), (1, b), (0xf, 7)]);
+    assert_eq!((cpu.registers[0], cpu.registers[0xf]), (sum, carry), "{} + {}", a, b);
+  }
+}
+
+#[test]
+fn add_into_vf_keeps_the_flag() {
+  assert_eq!(run("ADD VF, V1", &[(0xf, 0xff), (1, 1)]).registers[0xf], 1);
+  assert_eq!(run("ADD VF, V1", &[(0xf, 1), (1, 1)]).registers[0xf], 0);
+}
+
+#[test]
+fn add_byte_wraps_without_touching_vf() {
+  let cpu = run("ADD V0, 1", &[(0, 0xff), (0xf, 7)]);
+  assert_eq!((cpu.registers[0], cpu.registers[0xf]), (0, 7));
+}
+
+#[test]
+fn sub_and_subn_clear_vf_on_borrow() {
+  for (a, b, difference, no_borrow) in [(5, 3, 2, 1), (3, 5, 0xfe, 0), (4, 4, 0, 1)] {
+    let cpu = run("SUB V0, V1", &[(0, a), (1, b)]);
+    assert_eq!((cpu.registers[0], cpu.registers[0xf]), (difference, no_borrow), "{} - {}", a, b);
+
+    let cpu = run("SUBN V0, V1", &[(0, b), (1, a)]);
+    assert_eq!((cpu.registers[0], cpu.registers[0xf]), (difference, no_borrow), "{} - {}", a, b);
+  }
+
+  assert_eq!(run("SUB VF, V1", &[(0xf, 1), (1, 2)]).registers[0xf], 0);
+}
+
+#[test]
+fn shifts_set_vf_to_the_bit_shifted_out() {
+  for (source, value, result, bit) in [
+    ("SHR V0", 0x01, 0x00, 1),
+    ("SHR V0", 0x02, 0x01, 0),
+    ("SHL V0", 0x80, 0x00, 1),
+    ("SHL V0", 0x7f, 0xfe, 0),
+  ] {
+    let cpu = run(source, &[(0, value), (0xf, 7)]);
+    assert_eq!((cpu.registers[0], cpu.registers[0xf]), (result, bit), "{} of {:#x}", source, value);
+  }
+
+  // The COSMAC VIP shifts Vy into Vx.
+  let cpu = run("SHR V0, V1", &[(0, 0xff), (1, 0x04)]);
+  assert_eq!((cpu.registers[0], cpu.registers[0xf]), (0x02, 0));
+  assert_eq!(run("SHL VF, V1", &[(1, 0x81)]).registers[0xf], 1);
+}
+
+#[test]
+fn logic_instructions_clear_vf() {
+  for (source, result) in [("OR V0, V1", 0b1110), ("AND V0, V1", 0b1000), ("XOR V0, V1", 0b0110)] {
+    let cpu = run(source, &[(0, 0b1100), (1, 0b1010), (0xf, 7)]);
+    assert_eq!((cpu.registers[0], cpu.registers[0xf]), (result, 0), "{}", source);
+  }
+}
+
+#[test]
+fn loads_copy_values_and_registers() {
+  let cpu = run("LD V0, 0x2a\nLD V1, V0\nLD I, 0x123", &[]);
+  assert_eq!(cpu.registers[..2], [0x2a, 0x2a]);
+  assert_eq!(cpu.index_register, 0x123);
+}
+
+#[test]
+fn skips_compare_bytes_and_registers() {
+  let source = "
+    SE   V0, 1
+    ADD  VE, 1
+    SNE  V0, 1
+    ADD  VE, 2
+    SE   V0, V1
+    ADD  VE, 4
+    SNE  V0, V1
+    ADD  VE, 8
+  ";
+
+  assert_eq!(run(source, &[(0, 1), (1, 1)]).registers[0xe], 2 | 8);
+  assert_eq!(run(source, &[(0, 1), (1, 2)]).registers[0xe], 2 | 4);
+  assert_eq!(run(source, &[(0, 0), (1, 0)]).registers[0xe], 1 | 8);
+}
+
+#[test]
+fn jumps_calls_and_returns() {
+  let cpu = run("
+          CALL sub
+          JP   V0, end
+          ADD  V1, 1
+    end:  HALT
+    sub:  ADD  V2, 1
+          RET
+  ", &[(0, 0)]);
+
+  assert_eq!(cpu.registers[1..3], [0, 1]);
+  assert_eq!(cpu.stack_pointer, 0);
+  assert_eq!(cpu.program_counter, 0x208);
+}
+
+#[test]
+fn add_i_wraps_and_leaves_vf_alone() {
+  let cpu = run("LD I, 0xff0\nADD I, V0", &[(0, 0x20), (0xf, 7)]);
+  assert_eq!((cpu.index_register, cpu.registers[0xf]), (0x1010, 7));
+}
+
+#[test]
+fn random_numbers_are_masked() {
+  for _ in 0..20 {
+    let cpu = run("RND V0, 0x0f\nRND V1, 0", &[]);
+    assert!(cpu.registers[0] <= 0x0f);
+    assert_eq!(cpu.registers[1], 0);
+  }
+}
+
+#[test]
+fn drawing_sets_vf_on_collision_only() {
+  let cpu = run("LD F, V0\nDRW V1, V1, 5", &[(0xf, 1)]);
+  assert_eq!(cpu.registers[0xf], 0);
+
+  let cpu = run("LD F, V0\nDRW V1, V1, 5\nDRW V1, V1, 5", &[]);
+  assert_eq!(cpu.registers[0xf], 1);
+  assert!(!cpu.display.pixel(0, 0));
+
+  let cpu = run("LD F, V0\nDRW V1, V1, 5\nLD V1, 8\nDRW V1, V1, 5", &[]);
+  assert_eq!(cpu.registers[0xf], 0);
+}
+
+#[test]
+fn timers_are_read_and_written() {
+  let cpu = run("LD DT, V0\nLD ST, V1\nLD V2, DT", &[(0, 9), (1, 3)]);
+  assert_eq!((cpu.delay_timer, cpu.sound_timer, cpu.registers[2]), (9, 3, 9));
+}
+
+#[test]
+fn key_skips_follow_the_keypad() {
+  let source = "SKP V0\nADD VE, 1\nSKNP V0\nADD VE, 2";
+
+  let mut cpu = CPU::with_seed(1);
+  cpu.load_rom(&assemble(source).unwrap()).unwrap();
+  cpu.registers[0] = 0xa;
+  cpu.keypad.press(0xa);
+  for _ in 0..3 {
+    cpu.step().unwrap();
+  }
+  assert_eq!(cpu.registers[0xe], 2);
+
+  assert_eq!(run(source, &[(0, 0xa)]).registers[0xe], 1);
+}
+
+#[test]
+fn font_and_bcd() {
+  let cpu = run("LD F, V0", &[(0, 0x1c)]);
+  assert_eq!(cpu.index_register as usize, FONT_START + 0xc * FONT_SPRITE_LEN);
+
+  for (value, digits) in [(0, [0, 0, 0]), (9, [0, 0, 9]), (100, [1, 0, 0]), (255, [2, 5, 5])] {
+    let cpu = run("LD I, 0x300\nLD B, V0", &[(0, value)]);
+    assert_eq!(cpu.memory[0x300..0x303], digits, "{}", value);
+  }
+}
+
+#[test]
+fn register_dumps_and_loads_move_i() {
+  let cpu = run("LD I, 0x300\nLD [I], V2\nLD I, 0x300\nLD V4, [I]", &[(0, 1), (1, 2), (2, 3), (3, 9), (4, 9)]);
+  assert_eq!(cpu.registers[..5], [1, 2, 3, 0, 0]);
+  assert_eq!(cpu.index_register, 0x305);
+}
+
+#[test]
+fn every_opcode_executes_or_faults() {
+  for profile in [Profile::Chip8, Profile::SuperChip, Profile::XoChip] {
+    // One CPU for the whole sweep, so later opcodes also run against
+    // whatever state earlier ones left behind.
+    let mut cpu = CPU::with_profile(profile, 1);
+    for opcode in 0..=u16::MAX {
+      cpu.memory[PROGRAM_START..PROGRAM_START + 2].copy_from_slice(&opcode.to_be_bytes());
+      cpu.program_counter = PROGRAM_START;
+      if let Err(fault) = cpu.step() {
+        assert_eq!(fault.pc(), PROGRAM_START, "{:?} {:04x}", profile, opcode);
+      }
+    }
+  }
+}
@@ -0,0 +1,37 @@
+; BCD, register dumps and loads, ADD I and the hex font: 234 is split into
+; digits, which are drawn, and then every font sprite is drawn in a row
+; underneath.
+        LD   V0, 234
+        LD   I, digits
+        LD   B, V0
+        LD   V2, [I]        ; V0-V2 = 2, 3, 4
+
+        LD   VC, 0
+        LD   VD, 0
+        LD   F, V0
+        DRW  VC, VD, 5
+        ADD  VC, 5
+        LD   F, V1
+        DRW  VC, VD, 5
+        ADD  VC, 5
+        LD   F, V2
+        DRW  VC, VD, 5
+
+        LD   V3, 0          ; digit
+        LD   VC, 0
+        LD   VD, 8
+font:   LD   F, V3
+        DRW  VC, VD, 5
+        ADD  VC, 4
+        ADD  V3, 1
+        SE   V3, 16
+        JP   font
+
+        LD   I, digits
+        LD   V4, 2
+        ADD  I, V4
+        LD   V5, [I]        ; V0-V5 from the digits and whatever follows
+        HALT
+
+digits: DB   0, 0, 0
+        DB   0xaa, 0xbb, 0xcc
@@ -0,0 +1,70 @@
+; VF after every instruction that sets it, with and without a carry, borrow
+; or shifted-out bit. The flags are collected in V0-V9 and VE and drawn as
+; digits along the top of the screen.
+        LD   VB, 1
+
+        LD   VA, 0xff
+        ADD  VA, VB         ; 0xff + 1 carries
+        LD   V0, VF
+        ADD  VA, VB         ; 0 + 1 does not
+        LD   V1, VF
+
+        LD   VA, 5
+        SUB  VA, VB         ; 5 - 1 does not borrow: VF = 1
+        LD   V2, VF
+        LD   VA, 0
+        SUB  VA, VB         ; 0 - 1 borrows: VF = 0
+        LD   V3, VF
+
+        LD   VA, 0x81
+        SHR  VA             ; shifts out a 1
+        LD   V4, VF
+        SHR  VA             ; 0x40 shifts out a 0
+        LD   V5, VF
+
+        LD   VA, 0x81
+        SHL  VA             ; shifts out a 1
+        LD   V6, VF
+        SHL  VA             ; 0x02 shifts out a 0
+        LD   V7, VF
+
+        LD   VA, 1
+        SUBN VA, VB         ; 1 - 1 does not borrow
+        LD   V8, VF
+        LD   VA, 2
+        SUBN VA, VB         ; 1 - 2 borrows
+        LD   V9, VF
+
+        LD   VF, 0xff
+        ADD  VF, VB         ; with VF as the destination the flag wins
+        LD   VE, VF
+
+        LD   VC, 0
+        LD   VD, 0
+        LD   F, V0
+        CALL digit
+        LD   F, V1
+        CALL digit
+        LD   F, V2
+        CALL digit
+        LD   F, V3
+        CALL digit
+        LD   F, V4
+        CALL digit
+        LD   F, V5
+        CALL digit
+        LD   F, V6
+        CALL digit
+        LD   F, V7
+        CALL digit
+        LD   F, V8
+        CALL digit
+        LD   F, V9
+        CALL digit
+        LD   F, VE
+        CALL digit
+        HALT
+
+digit:  DRW  VC, VD, 5
+        ADD  VC, 5
+        RET
@@ -0,0 +1,32 @@
+; SUPER-CHIP: the 128x64 mode, large digits, a 16x16 sprite, scrolling
+; and the flag registers.
+        HIGH
+        LD   V0, 0
+        LD   V1, 0
+        LD   V2, 0xa
+big:    LD   HF, V2         ; A to F in the large font
+        DRW  V0, V1, 10
+        ADD  V0, 10
+        ADD  V2, 1
+        SE   V2, 16
+        JP   big
+
+        LD   I, ball
+        LD   V0, 100
+        LD   V1, 40
+        DRW  V0, V1, 0      ; 16x16
+        SCD  4
+        SCR
+        SCR
+        SCL
+
+        LD   V0, 0x12
+        LD   V1, 0x34
+        LD   R, V1
+        LD   V0, 0
+        LD   V1, 0
+        LD   V1, R
+        EXIT
+
+ball:   DW   0x07e0, 0x1ff8, 0x3ffc, 0x7ffe, 0x7ffe, 0xffff, 0xffff, 0xffff
+        DW   0xffff, 0xffff, 0xffff, 0x7ffe, 0x7ffe, 0x3ffc, 0x1ff8, 0x07e0
@@ -0,0 +1,16 @@
+; OR, AND and XOR, and whether they clear VF, which only the COSMAC VIP did.
+        LD   V0, 0b1100
+        LD   V1, 0b1010
+        LD   V2, V0
+        LD   V3, V0
+        LD   V4, V0
+        LD   VF, 7
+        OR   V2, V1         ; 0b1110
+        LD   V5, VF
+        LD   VF, 7
+        AND  V3, V1         ; 0b1000
+        LD   V6, VF
+        LD   VF, 7
+        XOR  V4, V1         ; 0b0110
+        LD   V7, VF
+        HALT
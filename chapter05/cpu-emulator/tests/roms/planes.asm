@@ -0,0 +1,37 @@
+; XO-CHIP: both bit planes, scrolling up, register ranges and 16-bit I
+; loads into the upper 60 KiB. The SE skips a four-byte instruction.
+        LD   V0, 0x11
+        LD   V1, 0x22
+        LD   V2, 0x33
+        LD   I, LONG 0xfff0
+        SAVE V2, V0         ; reversed: 0x33, 0x22, 0x11
+        LOAD V5, V7
+        SE   V5, 0x33
+        LD   I, LONG 0x0000
+        LD   VA, V5
+
+        LD   V0, 8
+        LD   V1, 20
+        PLANE 3
+        LD   I, squares
+        DRW  V0, V1, 8      ; one sprite per plane
+        PLANE 2
+        SCU  6
+        PLANE 1
+        SCL
+        PLANE 3
+
+        LD   V3, 62
+        LD   V4, 30
+        LD   I, squares
+        DRW  V3, V4, 4      ; wraps around both edges
+        LD   I, LONG tune
+        AUDIO
+        LD   V9, 80
+        PITCH V9
+        HALT
+
+squares: DB  0xff, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xff
+         DB  0x00, 0x00, 0x3c, 0x3c, 0x3c, 0x3c, 0x00, 0x00
+tune:    DB  0xf0, 0xf0, 0x0f, 0x0f, 0xf0, 0xf0, 0x0f, 0x0f
+         DB  0xaa, 0xaa, 0x55, 0x55, 0xaa, 0xaa, 0x55, 0x55
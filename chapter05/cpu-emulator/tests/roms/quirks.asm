@@ -0,0 +1,28 @@
+; Shifts, register stores and loads, and BNNN are where interpreters
+; disagree. Run as plain CHIP-8 and as SUPER-CHIP the same program ends
+; differently.
+        LD   V0, 0x10
+        LD   V1, 0x81
+        SHR  V0, V1         ; V0 = 0x40 from V1, or 0x08 from V0
+        LD   V2, 0x41
+        SHL  V2, V1         ; V2 = 0x02 from V1, or 0x82 from V2
+
+        LD   I, buffer
+        LD   [I], V2        ; leaves I at buffer + 3, or at buffer
+        LD   V3, 0xff
+        LD   [I], V3        ; so this goes after the first store, or over it
+        LD   I, buffer
+        LD   V5, [I]
+
+        LD   I, buffer
+        DRW  V6, V6, 7
+        JP   V0, 0x300      ; to 0x300 + V0, or + V3 on SUPER-CHIP
+
+        ORG  0x340
+        LD   V8, 1          ; 0x300 + 0x40
+        HALT
+        ORG  0x3ff
+        LD   V8, 2          ; 0x300 + 0xff
+        HALT
+
+buffer: DB   0, 0, 0, 0, 0, 0, 0
@@ -0,0 +1,387 @@
+//! Takes IEEE 754 binary floating-point numbers apart.
+//!
+//! A `Format` describes a layout: one sign bit, then the exponent field,
+//! then the fraction field. `F16`, `BF16`, `F32` and `F64` are provided,
+//! and `Format::new` makes others.
+//! Bit patterns of any of them are passed around as `u64`s, since Rust has
+//! no stable `f16` type. For example, `F32.to_parts(n.to_bits().into())`.
+//!
+//! `Format::to_parts` splits a bit pattern into its fields, and
+//! `Format::decode` says what those fields mean, as a sign, a power of two
+//! and a mantissa for finite numbers, including zeros and subnormals, or as
+//! an infinity or a NaN with its payload. `Format::encode` goes the other
+//! way from an `f64`, rounding to nearest, ties to even, as hardware does.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+  name: &'static str,
+  exponent_bits: u32,
+  fraction_bits: u32,
+}
+
+/// IEEE 754 half precision.
+pub const F16: Format = Format::new("f16", 5, 10).unwrap();
+/// bfloat16: `f32` with the low 16 fraction bits dropped.
+pub const BF16: Format = Format::new("bf16", 8, 7).unwrap();
+pub const F32: Format = Format::new("f32", 8, 23).unwrap();
+pub const F64: Format = Format::new("f64", 11, 52).unwrap();
+
+/// The fields of a bit pattern, as unsigned integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parts {
+  pub sign: u64,
+  pub exponent: u64,
+  pub fraction: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+  Zero,
+  Subnormal,
+  Normal,
+  Infinite,
+  Nan,
+}
+
+/// What a bit pattern means. `sign` is 1.0 or -1.0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+  /// `sign * mantissa * 2^exponent`. The mantissa is in [1, 2) for normal
+  /// numbers and in [0, 1) for zeros and subnormals, whose exponent is the
+  /// smallest normal one.
+  Finite { sign: f64, exponent: i32, mantissa: f64 },
+  Infinite { sign: f64 },
+  /// `payload` is the fraction field without the quiet bit, its top bit.
+  Nan { sign: f64, quiet: bool, payload: u64 },
+}
+
+/// 2^exponent, exactly, for exponents with a normal `f64`.
+fn pow2(exponent: i32) -> f64 {
+  assert!((-1022..=1023).contains(&exponent), "2^{} is not a normal f64", exponent);
+  f64::from_bits(((exponent + 1023) as u64) << 52)
+}
+
+/// `n >> shift`, rounded to nearest with ties to even.
+fn shift_right_rounding(n: u64, shift: u32) -> u64 {
+  if shift == 0 {
+    return n;
+  }
+  if shift > 64 {
+    return 0;
+  }
+  let kept = n.checked_shr(shift).unwrap_or(0);
+  let dropped = n & (u64::MAX >> (64 - shift));
+  let half = 1 << (shift - 1);
+  if dropped > half || (dropped == half && kept & 1 == 1) {
+    kept + 1
+  } else {
+    kept
+  }
+}
+
+impl Format {
+  /// A format with 2 to 11 exponent bits and 1 to 52 fraction bits, or
+  /// `None` for anything else. Every such format converts to and from
+  /// `f64` exactly; with fewer bits there would be no normal numbers or
+  /// no room for NaNs.
+  pub const fn new(name: &'static str, exponent_bits: u32, fraction_bits: u32) -> Option<Format> {
+    if exponent_bits < 2 || exponent_bits > 11 || fraction_bits < 1 || fraction_bits > 52 {
+      return None;
+    }
+    Some(Format { name, exponent_bits, fraction_bits })
+  }
+
+  pub const fn name(&self) -> &'static str {
+    self.name
+  }
+
+  pub const fn exponent_bits(&self) -> u32 {
+    self.exponent_bits
+  }
+
+  pub const fn fraction_bits(&self) -> u32 {
+    self.fraction_bits
+  }
+
+  /// The width of the whole bit pattern.
+  pub const fn bits(&self) -> u32 {
+    1 + self.exponent_bits + self.fraction_bits
+  }
+
+  pub const fn bias(&self) -> i32 {
+    (1 << (self.exponent_bits - 1)) - 1
+  }
+
+  /// The exponent of the smallest normal number, also used by subnormals.
+  pub const fn min_exponent(&self) -> i32 {
+    1 - self.bias()
+  }
+
+  pub const fn max_exponent(&self) -> i32 {
+    self.bias()
+  }
+
+  const fn max_biased_exponent(&self) -> u64 {
+    (1 << self.exponent_bits) - 1
+  }
+
+  const fn fraction_mask(&self) -> u64 {
+    (1 << self.fraction_bits) - 1
+  }
+
+  const fn quiet_bit(&self) -> u64 {
+    1 << (self.fraction_bits - 1)
+  }
+
+  /// Splits `bits` into fields. Bits above `self.bits()` are ignored.
+  pub fn to_parts(&self, bits: u64) -> Parts {
+    Parts {
+      sign: (bits >> (self.bits() - 1)) & 1,
+      exponent: (bits >> self.fraction_bits) & self.max_biased_exponent(),
+      fraction: bits & self.fraction_mask(),
+    }
+  }
+
+  /// Reassembles fields split by `to_parts`. Panics if a field is too wide.
+  pub fn from_parts(&self, parts: Parts) -> u64 {
+    assert!(parts.sign <= 1, "sign {} is not a bit", parts.sign);
+    assert!(parts.exponent <= self.max_biased_exponent(), "exponent {:#x} does not fit {}", parts.exponent, self.name);
+    assert!(parts.fraction <= self.fraction_mask(), "fraction {:#x} does not fit {}", parts.fraction, self.name);
+    parts.sign << (self.bits() - 1) | parts.exponent << self.fraction_bits | parts.fraction
+  }
+
+  pub fn classify(&self, parts: Parts) -> Class {
+    match (parts.exponent, parts.fraction) {
+      (0, 0) => Class::Zero,
+      (0, _) => Class::Subnormal,
+      (e, 0) if e == self.max_biased_exponent() => Class::Infinite,
+      (e, _) if e == self.max_biased_exponent() => Class::Nan,
+      _ => Class::Normal,
+    }
+  }
+
+  pub fn decode(&self, parts: Parts) -> Value {
+    let sign = if parts.sign == 0 { 1.0 } else { -1.0 };
+    let fraction = parts.fraction as f64 / pow2(self.fraction_bits as i32);
+    match self.classify(parts) {
+      Class::Zero | Class::Subnormal => Value::Finite { sign, exponent: self.min_exponent(), mantissa: fraction },
+      Class::Normal => Value::Finite {
+        sign,
+        exponent: parts.exponent as i32 - self.bias(),
+        mantissa: 1.0 + fraction,
+      },
+      Class::Infinite => Value::Infinite { sign },
+      Class::Nan => Value::Nan {
+        sign,
+        quiet: parts.fraction & self.quiet_bit() != 0,
+        payload: parts.fraction & !self.quiet_bit(),
+      },
+    }
+  }
+
+  /// The value of `bits`. Every format here widens to `f64` exactly. NaN
+  /// fractions are copied into the top of the `f64` fraction, as hardware
+  /// does, so the quiet bit stays the quiet bit.
+  pub fn to_f64(&self, bits: u64) -> f64 {
+    let parts = self.to_parts(bits);
+    match self.decode(parts) {
+      Value::Finite { sign, exponent, mantissa } => {
+        // Splitting the scaling keeps f64 subnormals exact.
+        let (high, low) = if exponent < -1022 { (-1022, exponent + 1022) } else { (exponent, 0) };
+        sign * mantissa * pow2(high) * pow2(low)
+      },
+      Value::Infinite { sign } => sign * f64::INFINITY,
+      Value::Nan { .. } => f64::from_bits(F64.from_parts(Parts {
+        sign: parts.sign,
+        exponent: F64.max_biased_exponent(),
+        fraction: parts.fraction << (F64.fraction_bits - self.fraction_bits),
+      })),
+    }
+  }
+
+  /// The bit pattern nearest to `n`, with ties to even. Too large values
+  /// become infinities. NaNs keep their sign and the top of their fraction,
+  /// quiet bit included, except that a signalling NaN whose payload
+  /// would be lost entirely is quietened, so it stays a NaN.
+  pub fn encode(&self, n: f64) -> u64 {
+    let source = F64.to_parts(n.to_bits());
+    let sign = source.sign;
+
+    match F64.classify(source) {
+      Class::Zero => self.from_parts(Parts { sign, exponent: 0, fraction: 0 }),
+      Class::Infinite => self.infinity(sign),
+      Class::Nan => {
+        let mut fraction = source.fraction >> (F64.fraction_bits - self.fraction_bits);
+        if fraction == 0 {
+          fraction = self.quiet_bit();
+        }
+        self.from_parts(Parts { sign, exponent: self.max_biased_exponent(), fraction })
+      },
+      Class::Normal | Class::Subnormal => {
+        // n = significand * 2^scale, with an integer significand.
+        let (significand, scale) = if source.exponent == 0 {
+          (source.fraction, F64.min_exponent() - F64.fraction_bits as i32)
+        } else {
+          (source.fraction | 1 << F64.fraction_bits, source.exponent as i32 - F64.bias() - F64.fraction_bits as i32)
+        };
+        let top_bit = 63 - significand.leading_zeros() as i32;
+        let mut exponent = (scale + top_bit).max(self.min_exponent());
+
+        // Round to a multiple of the spacing of numbers with that exponent.
+        let shift = exponent - self.fraction_bits as i32 - scale;
+        let mut mantissa = if shift <= 0 {
+          significand << -shift
+        } else {
+          shift_right_rounding(significand, shift as u32)
+        };
+        if mantissa >> (self.fraction_bits + 1) != 0 {
+          // Rounding carried into a new bit. The dropped bit is a zero.
+          mantissa >>= 1;
+          exponent += 1;
+        }
+
+        if exponent > self.max_exponent() {
+          self.infinity(sign)
+        } else if mantissa >> self.fraction_bits == 0 {
+          self.from_parts(Parts { sign, exponent: 0, fraction: mantissa })
+        } else {
+          self.from_parts(Parts {
+            sign,
+            exponent: (exponent + self.bias()) as u64,
+            fraction: mantissa & self.fraction_mask(),
+          })
+        }
+      },
+    }
+  }
+
+  fn infinity(&self, sign: u64) -> u64 {
+    self.from_parts(Parts { sign, exponent: self.max_biased_exponent(), fraction: 0 })
+  }
+}
+
+impl fmt::Display for Class {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Class::Zero => "zero",
+      Class::Subnormal => "subnormal",
+      Class::Normal => "normal",
+      Class::Infinite => "infinite",
+      Class::Nan => "NaN",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FORMATS: [Format; 4] = [F16, BF16, F32, F64];
+
+  #[test]
+  fn splits_and_decodes_normal_numbers() {
+    let parts = F32.to_parts(42.42_f32.to_bits().into());
+    assert_eq!(parts, Parts { sign: 0, exponent: 132, fraction: 0x29ae14 });
+    assert_eq!(F32.from_parts(parts), 42.42_f32.to_bits() as u64);
+
+    let Value::Finite { sign, exponent, mantissa } = F32.decode(parts) else { panic!() };
+    assert_eq!((sign, exponent), (1.0, 5));
+    assert_eq!(mantissa * 32.0, 42.42_f32 as f64);
+
+    assert_eq!(F16.to_parts(0xc500), Parts { sign: 1, exponent: 17, fraction: 0x100 });
+    assert_eq!(F16.to_f64(0xc500), -5.0);
+    assert_eq!(BF16.to_f64(0x4049), 3.140625);
+    assert_eq!(F64.to_f64(1.5_f64.to_bits()), 1.5);
+  }
+
+  #[test]
+  fn handles_zeros_subnormals_and_infinities() {
+    for format in FORMATS {
+      let negative_zero = format.encode(-0.0);
+      assert_eq!(format.classify(format.to_parts(negative_zero)), Class::Zero);
+      assert!(format.to_f64(negative_zero).is_sign_negative());
+
+      let smallest = format.from_parts(Parts { sign: 0, exponent: 0, fraction: 1 });
+      let value = pow2(format.min_exponent()) / pow2(format.fraction_bits as i32);
+      assert_eq!(format.classify(format.to_parts(smallest)), Class::Subnormal, "{}", format.name);
+      assert_eq!(format.to_f64(smallest), value, "{}", format.name);
+      assert_eq!(format.encode(value), smallest, "{}", format.name);
+      // Half of it is a tie, which rounds to the even zero.
+      assert_eq!(format.encode(value / 2.0), 0, "{}", format.name);
+      assert_eq!(format.encode(value * 0.75), smallest, "{}", format.name);
+
+      let infinity = format.encode(f64::NEG_INFINITY);
+      assert_eq!(format.decode(format.to_parts(infinity)), Value::Infinite { sign: -1.0 });
+      assert_eq!(format.to_f64(infinity), f64::NEG_INFINITY);
+    }
+
+    assert_eq!(F16.to_f64(F16.encode(65504.0)), 65504.0);
+    assert_eq!(F16.to_f64(F16.encode(65520.0)), f64::INFINITY);
+    assert_eq!(F16.to_f64(F16.encode(65519.0)), 65504.0);
+  }
+
+  #[test]
+  fn keeps_nan_payloads() {
+    let signalling = F16.from_parts(Parts { sign: 1, exponent: 0x1f, fraction: 0x155 });
+    assert_eq!(F16.decode(F16.to_parts(signalling)), Value::Nan { sign: -1.0, quiet: false, payload: 0x155 });
+
+    let wide = F16.to_f64(signalling);
+    assert!(wide.is_nan() && wide.is_sign_negative());
+    assert_eq!(F64.decode(F64.to_parts(wide.to_bits())), Value::Nan { sign: -1.0, quiet: false, payload: 0x155 << 42 });
+    assert_eq!(F16.encode(wide), signalling);
+
+    // The payload's top bits don't fit bf16, so the NaN is quietened.
+    let narrow = BF16.encode(f64::from_bits(0x7ff0_0000_0000_0001));
+    assert_eq!(BF16.decode(BF16.to_parts(narrow)), Value::Nan { sign: 1.0, quiet: true, payload: 0 });
+  }
+
+  #[test]
+  fn only_formats_that_fit_f64_can_be_made() {
+    for (exponent_bits, fraction_bits) in [(0, 10), (1, 10), (12, 10), (5, 0), (5, 53), (0, 0)] {
+      assert_eq!(Format::new("odd", exponent_bits, fraction_bits), None, "{} {}", exponent_bits, fraction_bits);
+    }
+
+    for exponent_bits in 2..=11 {
+      for fraction_bits in 1..=52 {
+        let format = Format::new("any", exponent_bits, fraction_bits).unwrap();
+        for n in [0.0, -1.5, 1e-310, 1e300, f64::INFINITY, f64::NAN] {
+          let bits = format.encode(n);
+          assert_eq!(format.encode(format.to_f64(bits)), bits, "{} {} {:e}", exponent_bits, fraction_bits, n);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn every_f16_round_trips_through_f64() {
+    for bits in 0..=0xffff {
+      assert_eq!(F16.encode(F16.to_f64(bits)), bits, "{:#06x}", bits);
+    }
+  }
+
+  #[test]
+  fn encoding_matches_the_hardware() {
+    let mut seed: u64 = 1;
+    for _ in 0..100_000 {
+      seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+      // Exponents around f32's range, so that some overflow or are subnormal.
+      let n = f64::from_bits(seed & 0x800f_ffff_ffff_ffff | (0x340 + (seed >> 52) % 0x180) << 52);
+      assert_eq!(F32.encode(n), (n as f32).to_bits() as u64, "{:e}", n);
+      assert_eq!(F64.encode(n), n.to_bits());
+      assert_eq!(F32.to_f64(F32.encode(n)), n as f32 as f64);
+
+      // bf16 is f32's top half, so rounding an f32 to it is a carry into
+      // the top half.
+      let f = n as f32;
+      if f.is_finite() {
+        let bits = f.to_bits();
+        let low = bits & 0xffff;
+        let rounded = (bits >> 16) + (low > 0x8000 || (low == 0x8000 && (bits >> 16) & 1 == 1)) as u32;
+        assert_eq!(BF16.encode(f as f64), rounded as u64, "{:e}", f);
+      }
+    }
+  }
+}
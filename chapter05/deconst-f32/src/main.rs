@@ -1,50 +1,48 @@
-const BIAS: i32 = 127;
-const RADIX: f32 = 2.0;
+use std::env;
+use std::process;
 
-fn main() {
-  let n: f32 = 42.42;
-
-  let (sign, exp, frac) = to_parts(n);
-  let (sign_, exp_, mantissa) = decode(sign, exp, frac);
-  let n_ = from_parts(sign_, exp_, mantissa);
-
-  println!("{} -> {}", n, n_);
-  println!("field    |  as bits | as real number");
-  println!("sign     |        {:01b} | {}", sign, sign_);
-  println!("exponent | {:08b} | {}", exp, exp_);
-  println!("mantissa | {:023b} | {}", frac, mantissa);
-}
+use deconst_f32::{Format, Value, BF16, F16, F32, F64};
 
-fn to_parts(n: f32) -> (u32, u32, u32) {
-  let bits = n.to_bits();
-
-  let sign = (bits >> 31) & 1;
-  let exponent = (bits >> 23) & 0xff;
-  let fraction = bits & 0x7fffff;
-
-  (sign, exponent, fraction)
-}
-
-fn decode(sign: u32, exponent: u32, fraction: u32) -> (f32, f32, f32) {
-  let signed_1 = (-1.0_f32).powf(sign as f32);
-
-  let exponent = (exponent as i32) - BIAS;
-  let exponent = RADIX.powf(exponent as f32);
-
-  let mut mantissa: f32 = 1.0;
-  for i in 0..23 {
-    let mask = 1 << i;
-    let bit_value = fraction & mask;
-    if bit_value != 0 {
-      let i_ = i as f32;
-      let weight = 2_f32.powf(i_ - 23.0);
-      mantissa += weight;
-    }
+fn main() {
+  let n: f64 = match env::args().nth(1) {
+    None => 42.42,
+    Some(arg) => arg.parse().unwrap_or_else(|_| {
+      eprintln!("usage: deconst-f32 [NUMBER]");
+      process::exit(2);
+    }),
+  };
+
+  for format in [F16, BF16, F32, F64] {
+    show(format, n);
+    println!();
   }
-
-  (signed_1, exponent, mantissa)
 }
 
-fn from_parts(sign: f32, exponent: f32, mantissa: f32) -> f32 {
-  sign * exponent * mantissa
-}
\ No newline at end of file
+fn show(format: Format, n: f64) {
+  let bits = format.encode(n);
+  let parts = format.to_parts(bits);
+  let class = format.classify(parts);
+
+  // Wide enough for either field, and for the heading.
+  let width = format.exponent_bits().max(format.fraction_bits()).max(7) as usize;
+  println!("{}: {} -> {} ({})", format.name(), n, format.to_f64(bits), class);
+  println!("field    | {:>width$} | as real number", "as bits", width = width);
+
+  let (sign, exponent, mantissa) = match format.decode(parts) {
+    Value::Finite { sign, exponent, mantissa } => (sign.to_string(), format!("2^{}", exponent), mantissa.to_string()),
+    Value::Infinite { sign } => (sign.to_string(), String::from("infinite"), String::from("-")),
+    Value::Nan { sign, quiet, payload } => {
+      let kind = if quiet { "quiet" } else { "signalling" };
+      (sign.to_string(), format!("{} NaN", kind), format!("payload {:#x}", payload))
+    },
+  };
+  println!("sign     | {:>width$} | {}", format!("{:01b}", parts.sign), sign, width = width);
+  println!(
+    "exponent | {:>width$} | {}",
+    format!("{:0e$b}", parts.exponent, e = format.exponent_bits() as usize), exponent, width = width,
+  );
+  println!(
+    "mantissa | {:>width$} | {}",
+    format!("{:0f$b}", parts.fraction, f = format.fraction_bits() as usize), mantissa, width = width,
+  );
+}
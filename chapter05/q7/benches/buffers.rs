@@ -0,0 +1,72 @@
+//! The buffer functions against the scalar conversions and operators they
+//! replace. Run with `cargo bench --bench buffers`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use q7::{buffer, Q7};
+
+/// One second of a 440 Hz tone at 48 kHz.
+fn tone() -> Vec<f32> {
+  (0..48_000).map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 48_000.0).sin() * 0.9).collect()
+}
+
+fn conversions(c: &mut Criterion) {
+  let samples = tone();
+  let q7s = buffer::from_f32s(&samples);
+
+  c.bench_function("from_f32 scalar", |b| {
+    b.iter(|| black_box(&samples).iter().map(|n| Q7::from(*n)).collect::<Vec<Q7>>())
+  });
+  c.bench_function("from_f32 buffer", |b| b.iter(|| buffer::from_f32s(black_box(&samples))));
+
+  c.bench_function("to_f32 scalar", |b| {
+    b.iter(|| black_box(&q7s).iter().map(|q| f32::from(*q)).collect::<Vec<f32>>())
+  });
+  c.bench_function("to_f32 buffer", |b| b.iter(|| buffer::to_f32s(black_box(&q7s))));
+}
+
+fn arithmetic(c: &mut Criterion) {
+  let a = buffer::from_f32s(&tone());
+  let b: Vec<Q7> = a.iter().rev().copied().collect();
+
+  c.bench_function("mul scalar", |bench| {
+    bench.iter(|| {
+      let mut out = a.clone();
+      for (x, y) in out.iter_mut().zip(black_box(&b)) {
+        *x = *x * *y;
+      }
+      out
+    })
+  });
+  c.bench_function("mul buffer", |bench| {
+    bench.iter(|| {
+      let mut out = a.clone();
+      buffer::mul_assign(&mut out, black_box(&b));
+      out
+    })
+  });
+
+  c.bench_function("add scalar", |bench| {
+    bench.iter(|| {
+      let mut out = a.clone();
+      for (x, y) in out.iter_mut().zip(black_box(&b)) {
+        *x = *x + *y;
+      }
+      out
+    })
+  });
+  c.bench_function("add buffer", |bench| {
+    bench.iter(|| {
+      let mut out = a.clone();
+      buffer::add_assign(&mut out, black_box(&b));
+      out
+    })
+  });
+
+  c.bench_function("dot scalar", |bench| {
+    bench.iter(|| black_box(&a).iter().zip(&b).map(|(x, y)| f64::from(*x) * f64::from(*y)).sum::<f64>())
+  });
+  c.bench_function("dot buffer", |bench| bench.iter(|| buffer::dot(black_box(&a), black_box(&b))));
+}
+
+criterion_group!(benches, conversions, arithmetic);
+criterion_main!(benches);
@@ -0,0 +1,173 @@
+//! Whole-buffer operations on `Q7` samples.
+//!
+//! Each function gives exactly the results of applying the scalar
+//! conversion or operator element by element, but is written as a
+//! branch-free loop over plain integers so the compiler can vectorize it.
+//! `benches/buffers.rs` compares them with the scalar versions.
+//!
+//! Functions taking two buffers panic if their lengths differ, as
+//! `copy_from_slice` does.
+
+use crate::{Fixed, Q7};
+
+/// The scalar `Q7::from`, restated so it vectorizes: `as` saturates,
+/// truncates towards zero and maps NaN to zero, just as the `f64` path does,
+/// and scaling by 128 is exact in `f32` too.
+fn from_f32(n: f32) -> Q7 {
+  Q7((n * 128.0) as i8)
+}
+
+/// `a * b`, rounded to nearest with ties away from zero and saturated, as
+/// `Q7`'s `Mul` does.
+fn mul(a: Q7, b: Q7) -> Q7 {
+  let product = a.0 as i32 * b.0 as i32;
+  // An arithmetic shift floors, so bias negative ties down by one to round
+  // them away from zero.
+  let rounded = (product + 64 - (product < 0) as i32) >> 7;
+  Q7(rounded.clamp(i8::MIN as i32, i8::MAX as i32) as i8)
+}
+
+fn check_lengths(a: usize, b: usize) {
+  assert_eq!(a, b, "buffers must be the same length");
+}
+
+/// Converts samples as `Q7::from` does.
+pub fn from_f32s(input: &[f32]) -> Vec<Q7> {
+  input.iter().map(|n| from_f32(*n)).collect()
+}
+
+/// Converts samples into `output`, for callers reusing a buffer.
+pub fn from_f32s_into(input: &[f32], output: &mut [Q7]) {
+  check_lengths(input.len(), output.len());
+  for (q, n) in output.iter_mut().zip(input) {
+    *q = from_f32(*n);
+  }
+}
+
+/// Converts samples as `f32::from` does. Every `Q7` is exact as an `f32`.
+pub fn to_f32s(input: &[Q7]) -> Vec<f32> {
+  input.iter().map(|q| q.0 as f32 / 128.0).collect()
+}
+
+pub fn to_f32s_into(input: &[Q7], output: &mut [f32]) {
+  check_lengths(input.len(), output.len());
+  for (n, q) in output.iter_mut().zip(input) {
+    *n = q.0 as f32 / 128.0;
+  }
+}
+
+/// `a[i] = a[i] + b[i]`, saturating.
+pub fn add_assign(a: &mut [Q7], b: &[Q7]) {
+  check_lengths(a.len(), b.len());
+  for (x, y) in a.iter_mut().zip(b) {
+    x.0 = x.0.saturating_add(y.0);
+  }
+}
+
+/// `a[i] = a[i] - b[i]`, saturating.
+pub fn sub_assign(a: &mut [Q7], b: &[Q7]) {
+  check_lengths(a.len(), b.len());
+  for (x, y) in a.iter_mut().zip(b) {
+    x.0 = x.0.saturating_sub(y.0);
+  }
+}
+
+/// `a[i] = a[i] * b[i]`, rounded and saturating.
+pub fn mul_assign(a: &mut [Q7], b: &[Q7]) {
+  check_lengths(a.len(), b.len());
+  for (x, y) in a.iter_mut().zip(b) {
+    *x = mul(*x, *y);
+  }
+}
+
+/// `a[i] = a[i] * gain`, rounded and saturating.
+pub fn scale(a: &mut [Q7], gain: Q7) {
+  for x in a.iter_mut() {
+    *x = mul(*x, gain);
+  }
+}
+
+/// The exact sum of `a[i] * b[i]`, with all 14 fractional bits of the
+/// products kept. Round it once at the end, e.g. with `convert::<i8, 7>()`,
+/// rather than rounding every product.
+pub fn dot(a: &[Q7], b: &[Q7]) -> Fixed<i64, 14> {
+  check_lengths(a.len(), b.len());
+  // Products are at most 2^14, so 2^15 of them fit an i32 accumulator, and
+  // i32 lanes vectorize better than i64 ones.
+  let sum: i64 = a.chunks(1 << 15)
+    .zip(b.chunks(1 << 15))
+    .map(|(a, b)| a.iter().zip(b).map(|(x, y)| x.0 as i32 * y.0 as i32).sum::<i32>() as i64)
+    .sum();
+  Fixed::from_bits(sum)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn all_q7s() -> Vec<Q7> {
+    (i8::MIN..=i8::MAX).map(Q7).collect()
+  }
+
+  #[test]
+  fn conversions_match_the_scalar_ones() {
+    let samples = [
+      0.0, -0.0, 0.7, -0.4, 0.999, -0.999, 1.0, -1.0, 123.0, -1e30,
+      f32::INFINITY, f32::NEG_INFINITY, f32::NAN, f32::MIN_POSITIVE, 1.0 / 128.0, -1.5 / 128.0,
+    ];
+    let scalar: Vec<Q7> = samples.iter().map(|n| Q7::from(*n)).collect();
+    assert_eq!(from_f32s(&samples), scalar);
+
+    let mut output = vec![Q7(0); samples.len()];
+    from_f32s_into(&samples, &mut output);
+    assert_eq!(output, scalar);
+
+    let all = all_q7s();
+    let scalar: Vec<f32> = all.iter().map(|q| f32::from(*q)).collect();
+    assert_eq!(to_f32s(&all), scalar);
+    assert_eq!(from_f32s(&to_f32s(&all)), all);
+  }
+
+  #[test]
+  fn element_wise_ops_match_the_operators() {
+    let all = all_q7s();
+    for y in &all {
+      let b = vec![*y; all.len()];
+
+      let mut sum = all.clone();
+      add_assign(&mut sum, &b);
+      let mut difference = all.clone();
+      sub_assign(&mut difference, &b);
+      let mut product = all.clone();
+      mul_assign(&mut product, &b);
+      let mut scaled = all.clone();
+      scale(&mut scaled, *y);
+
+      for (i, x) in all.iter().enumerate() {
+        assert_eq!(sum[i], *x + *y);
+        assert_eq!(difference[i], *x - *y);
+        assert_eq!(product[i], *x * *y, "{:?} * {:?}", x, y);
+        assert_eq!(scaled[i], *x * *y);
+      }
+    }
+  }
+
+  #[test]
+  fn dot_products_are_exact() {
+    let a = from_f32s(&[0.5, -0.25, 0.75]);
+    let b = from_f32s(&[0.5, 0.5, -1.0]);
+    let sum = dot(&a, &b);
+    assert_eq!(f64::from(sum), 0.25 - 0.125 - 0.75);
+    assert_eq!(Q7::from(sum.convert::<i8, 7>()), Q7::from(-0.625));
+
+    // Long enough to overflow a single i32 accumulator.
+    let minus_ones = vec![Q7::MIN; 1 << 18];
+    assert_eq!(dot(&minus_ones, &minus_ones).to_bits(), (1 << 18) * (1 << 14));
+  }
+
+  #[test]
+  #[should_panic(expected = "same length")]
+  fn mismatched_lengths_panic() {
+    add_assign(&mut [Q7(1)], &[]);
+  }
+}
@@ -0,0 +1,426 @@
+//! Fixed-point numbers in any Qm.n format that fits a signed integer.
+//!
+//! `Fixed<T, FRAC>` stores a number as a `T` scaled by 2^FRAC, so it holds
+//! `T::BITS - FRAC` integer bits, sign included, and `FRAC` fractional bits.
+//! The usual formats have aliases: `Q15`, `Q31`, `Q16_16` and `Q32_32`.
+//!
+//! Arithmetic follows `Q7`: the operators saturate, the `wrapping_` and
+//! `checked_` methods wrap or return `None`, and products, quotients and
+//! format conversions round to nearest with ties away from zero. Everything
+//! is worked out in `i128`, which holds any intermediate result exactly as
+//! long as the sign bit is never a fractional bit.
+
+use std::error::Error;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+mod private {
+  pub trait Sealed {}
+}
+
+/// The signed integers a `Fixed` can be stored in.
+pub trait Repr: Copy + Ord + Default + fmt::Debug + private::Sealed {
+  const BITS: u32;
+  const MIN: Self;
+  const MAX: Self;
+
+  fn widen(self) -> i128;
+  /// Keeps the low `BITS` bits of `n`, as `as` does.
+  fn narrow(n: i128) -> Self;
+}
+
+macro_rules! impl_repr {
+  ($($t:ty),*) => {
+    $(
+      impl private::Sealed for $t {}
+
+      impl Repr for $t {
+        const BITS: u32 = <$t>::BITS;
+        const MIN: Self = <$t>::MIN;
+        const MAX: Self = <$t>::MAX;
+
+        fn widen(self) -> i128 {
+          self as i128
+        }
+
+        fn narrow(n: i128) -> Self {
+          n as $t
+        }
+      }
+    )*
+  };
+}
+
+impl_repr!(i8, i16, i32, i64);
+
+/// A signed fixed-point number with `FRAC` fractional bits, stored in a
+/// `T`. `FRAC` may be anything below `T::BITS`, leaving at least the sign
+/// bit for the integer part.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub struct Fixed<T, const FRAC: u32>(T);
+
+/// Q1.15, in [-1, 1).
+pub type Q15 = Fixed<i16, 15>;
+/// Q1.31, in [-1, 1).
+pub type Q31 = Fixed<i32, 31>;
+/// 16 integer and 16 fractional bits.
+#[allow(non_camel_case_types)]
+pub type Q16_16 = Fixed<i32, 16>;
+/// 32 integer and 32 fractional bits.
+#[allow(non_camel_case_types)]
+pub type Q32_32 = Fixed<i64, 32>;
+
+/// How `round_from` turns a float into the nearest representable values.
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Rounding {
+  /// Drops the excess fraction, as `From<f64>` does. Biased towards zero.
+  TowardZero,
+  /// To nearest, with ties to the even neighbour. Unbiased.
+  NearestEven,
+  /// To nearest, with ties away from zero, like `f64::round`.
+  NearestAway,
+  Floor,
+  Ceil,
+  /// Rounds up with a probability equal to the excess fraction, so errors
+  /// average out over many conversions. Holds a sample drawn uniformly from
+  /// [0, 1), from whatever random source suits the caller.
+  Stochastic(f64),
+}
+
+impl Rounding {
+  /// Rounds `x` to an integer.
+  pub fn round(self, x: f64) -> f64 {
+    match self {
+      Rounding::TowardZero => x.trunc(),
+      Rounding::NearestEven => x.round_ties_even(),
+      Rounding::NearestAway => x.round(),
+      Rounding::Floor => x.floor(),
+      Rounding::Ceil => x.ceil(),
+      Rounding::Stochastic(sample) => {
+        let floor = x.floor();
+        if sample < x - floor { floor + 1.0 } else { floor }
+      },
+    }
+  }
+}
+
+/// NaN has no fixed-point value.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct NotANumber;
+
+impl fmt::Display for NotANumber {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "NaN cannot be converted to fixed point")
+  }
+}
+
+impl Error for NotANumber {}
+
+/// `n / d` rounded to the nearest integer, with ties away from zero.
+fn round_div(n: i128, d: i128) -> i128 {
+  let (quotient, remainder) = (n / d, n % d);
+  if 2 * remainder.abs() >= d.abs() {
+    quotient + n.signum() * d.signum()
+  } else {
+    quotient
+  }
+}
+
+impl<T: Repr, const FRAC: u32> Fixed<T, FRAC> {
+  const FRAC_FITS: () = assert!(FRAC < T::BITS, "the sign bit cannot be a fractional bit");
+
+  pub const MIN: Self = Fixed(T::MIN);
+  pub const MAX: Self = Fixed(T::MAX);
+
+  /// The number whose scaled representation is `bits`.
+  pub fn from_bits(bits: T) -> Self {
+    #[allow(clippy::let_unit_value)]
+    let () = Self::FRAC_FITS;
+    Fixed(bits)
+  }
+
+  pub fn to_bits(self) -> T {
+    self.0
+  }
+
+  fn saturate(n: i128) -> Self {
+    Fixed::from_bits(T::narrow(n.clamp(T::MIN.widen(), T::MAX.widen())))
+  }
+
+  fn wrap(n: i128) -> Self {
+    Fixed::from_bits(T::narrow(n))
+  }
+
+  fn fit(n: i128) -> Option<Self> {
+    (T::MIN.widen()..=T::MAX.widen()).contains(&n).then(|| Fixed::wrap(n))
+  }
+
+  fn sum(self, other: Self) -> i128 {
+    self.0.widen() + other.0.widen()
+  }
+
+  fn difference(self, other: Self) -> i128 {
+    self.0.widen() - other.0.widen()
+  }
+
+  fn product(self, other: Self) -> i128 {
+    round_div(self.0.widen() * other.0.widen(), 1 << FRAC)
+  }
+
+  /// # Panics
+  ///
+  /// If `other` is zero.
+  fn quotient(self, other: Self) -> i128 {
+    assert!(other.0.widen() != 0, "attempt to divide by zero");
+    round_div(self.0.widen() << FRAC, other.0.widen())
+  }
+
+  pub fn saturating_add(self, other: Self) -> Self {
+    Fixed::saturate(self.sum(other))
+  }
+
+  pub fn wrapping_add(self, other: Self) -> Self {
+    Fixed::wrap(self.sum(other))
+  }
+
+  pub fn checked_add(self, other: Self) -> Option<Self> {
+    Fixed::fit(self.sum(other))
+  }
+
+  pub fn saturating_sub(self, other: Self) -> Self {
+    Fixed::saturate(self.difference(other))
+  }
+
+  pub fn wrapping_sub(self, other: Self) -> Self {
+    Fixed::wrap(self.difference(other))
+  }
+
+  pub fn checked_sub(self, other: Self) -> Option<Self> {
+    Fixed::fit(self.difference(other))
+  }
+
+  pub fn saturating_mul(self, other: Self) -> Self {
+    Fixed::saturate(self.product(other))
+  }
+
+  pub fn wrapping_mul(self, other: Self) -> Self {
+    Fixed::wrap(self.product(other))
+  }
+
+  pub fn checked_mul(self, other: Self) -> Option<Self> {
+    Fixed::fit(self.product(other))
+  }
+
+  pub fn saturating_div(self, other: Self) -> Self {
+    Fixed::saturate(self.quotient(other))
+  }
+
+  pub fn wrapping_div(self, other: Self) -> Self {
+    Fixed::wrap(self.quotient(other))
+  }
+
+  pub fn checked_div(self, other: Self) -> Option<Self> {
+    if other.0.widen() == 0 {
+      return None;
+    }
+    Fixed::fit(self.quotient(other))
+  }
+
+  /// Only `MIN` has no negation, and saturates to `MAX`.
+  pub fn saturating_neg(self) -> Self {
+    Fixed::saturate(-self.0.widen())
+  }
+
+  pub fn wrapping_neg(self) -> Self {
+    Fixed::wrap(-self.0.widen())
+  }
+
+  pub fn checked_neg(self) -> Option<Self> {
+    Fixed::fit(-self.0.widen())
+  }
+
+  /// `self` rescaled to `G` fractional bits, before it is fitted into a `U`.
+  fn rescale<const G: u32>(self) -> i128 {
+    let bits = self.0.widen();
+    if G >= FRAC {
+      bits << (G - FRAC)
+    } else {
+      round_div(bits, 1 << (FRAC - G))
+    }
+  }
+
+  /// Converts `n` with the given rounding, saturating at `MIN` and `MAX`,
+  /// infinities included.
+  pub fn round_from(n: f64, rounding: Rounding) -> Result<Self, NotANumber> {
+    if n.is_nan() {
+      return Err(NotANumber);
+    }
+    // Scaling by a power of two is exact, so only `rounding` loses precision.
+    let scaled = rounding.round(n * 2_f64.powi(FRAC as i32));
+    Ok(Fixed::saturate(scaled as i128))
+  }
+
+  /// Converts to another format, rounding away fractional bits it lacks and
+  /// saturating at its limits.
+  pub fn convert<U: Repr, const G: u32>(self) -> Fixed<U, G> {
+    Fixed::saturate(self.rescale::<G>())
+  }
+
+  /// Converts to another format, or `None` if the value is out of its range.
+  /// Fractional bits it lacks are still rounded away.
+  pub fn checked_convert<U: Repr, const G: u32>(self) -> Option<Fixed<U, G>> {
+    Fixed::fit(self.rescale::<G>())
+  }
+}
+
+/// Saturates at `MIN` and `MAX`, truncating towards zero in between. NaN is
+/// zero. `round_from` rounds better and rejects NaN.
+impl<T: Repr, const FRAC: u32> From<f64> for Fixed<T, FRAC> {
+  fn from(n: f64) -> Self {
+    Fixed::round_from(n, Rounding::TowardZero).unwrap_or_default()
+  }
+}
+
+/// Exact up to 53 significant bits, as `f64` is.
+impl<T: Repr, const FRAC: u32> From<Fixed<T, FRAC>> for f64 {
+  fn from(n: Fixed<T, FRAC>) -> f64 {
+    n.0.widen() as f64 * 2_f64.powi(-(FRAC as i32))
+  }
+}
+
+impl<T: Repr, const FRAC: u32> From<f32> for Fixed<T, FRAC> {
+  fn from(n: f32) -> Self {
+    Fixed::from(n as f64)
+  }
+}
+
+impl<T: Repr, const FRAC: u32> From<Fixed<T, FRAC>> for f32 {
+  fn from(n: Fixed<T, FRAC>) -> f32 {
+    f64::from(n) as f32
+  }
+}
+
+impl<T: Repr, const FRAC: u32> Add for Fixed<T, FRAC> {
+  type Output = Self;
+
+  fn add(self, other: Self) -> Self {
+    self.saturating_add(other)
+  }
+}
+
+impl<T: Repr, const FRAC: u32> Sub for Fixed<T, FRAC> {
+  type Output = Self;
+
+  fn sub(self, other: Self) -> Self {
+    self.saturating_sub(other)
+  }
+}
+
+impl<T: Repr, const FRAC: u32> Mul for Fixed<T, FRAC> {
+  type Output = Self;
+
+  fn mul(self, other: Self) -> Self {
+    self.saturating_mul(other)
+  }
+}
+
+impl<T: Repr, const FRAC: u32> Div for Fixed<T, FRAC> {
+  type Output = Self;
+
+  fn div(self, other: Self) -> Self {
+    self.saturating_div(other)
+  }
+}
+
+impl<T: Repr, const FRAC: u32> Neg for Fixed<T, FRAC> {
+  type Output = Self;
+
+  fn neg(self) -> Self {
+    self.saturating_neg()
+  }
+}
+
+/// Shows the value as an `f64` would, honouring precision.
+impl<T: Repr, const FRAC: u32> fmt::Display for Fixed<T, FRAC> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Display::fmt(&f64::from(*self), f)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn floats_saturate_like_q7() {
+    assert_eq!(Q15::from(10.), Q15::MAX);
+    assert_eq!(Q15::from(-1.), Q15::MIN);
+    assert_eq!(Q15::from(0.7).to_bits(), 22937);
+    assert_eq!(Q31::from(-0.4).to_bits(), -858993459);
+    assert_eq!(Q16_16::from(1.5).to_bits(), 0x1_8000);
+    assert_eq!(Q16_16::from(1e9), Q16_16::MAX);
+    assert_eq!(Q32_32::from(-1e30), Q32_32::MIN);
+    assert_eq!(Q15::from(f64::NAN), Q15::default());
+
+    assert_eq!(f64::from(Q16_16::from(-2.25)), -2.25);
+    assert_eq!(f32::from(Q15::MIN), -1.0);
+  }
+
+  #[test]
+  fn arithmetic_is_generic_over_formats() {
+    let (a, b) = (Q16_16::from(2.5), Q16_16::from(-1.25));
+    assert_eq!(f64::from(a + b), 1.25);
+    assert_eq!(f64::from(a - b), 3.75);
+    assert_eq!(f64::from(a * b), -3.125);
+    assert_eq!(f64::from(a / b), -2.0);
+    assert_eq!(f64::from(-b), 1.25);
+
+    assert_eq!(Q15::MIN * Q15::MIN, Q15::MAX);
+    assert_eq!(Q15::MIN.checked_mul(Q15::MIN), None);
+    assert_eq!(Q31::MAX.wrapping_add(Q31::from_bits(1)), Q31::MIN);
+
+    // Products of the widest format still round exactly.
+    let tiny = Q32_32::from_bits(3);
+    let half = Q32_32::from(0.5);
+    assert_eq!((tiny * half).to_bits(), 2);
+    assert_eq!((Q32_32::from(65536.0) * Q32_32::from(65536.0)), Q32_32::MAX);
+  }
+
+  #[test]
+  fn formats_convert_with_rounding_and_saturation() {
+    let q15 = Q15::from(0.7);
+    let q7: Fixed<i8, 7> = q15.convert();
+    assert_eq!(q7.to_bits(), 90);
+    assert_eq!(q7.convert::<i16, 15>().to_bits(), 90 << 8);
+
+    assert_eq!(Q16_16::from(3.0).convert::<i16, 15>(), Q15::MAX);
+    assert_eq!(Q16_16::from(3.0).checked_convert::<i16, 15>(), None);
+    assert_eq!(Q16_16::from(-0.5).checked_convert::<i16, 15>(), Some(Q15::from(-0.5)));
+    assert_eq!(f64::from(Q15::MIN.convert::<i64, 32>()), -1.0);
+  }
+
+  #[test]
+  fn rounding_applies_to_every_format() {
+    // 1.5 and 2.5 units in the last place.
+    let (a, b) = (1.5 / 32768.0, -2.5 / 32768.0);
+    assert_eq!(Q15::round_from(a, Rounding::NearestEven).unwrap().to_bits(), 2);
+    assert_eq!(Q15::round_from(b, Rounding::NearestEven).unwrap().to_bits(), -2);
+    assert_eq!(Q15::round_from(b, Rounding::NearestAway).unwrap().to_bits(), -3);
+    assert_eq!(Q15::round_from(b, Rounding::Floor).unwrap().to_bits(), -3);
+    assert_eq!(Q15::round_from(b, Rounding::Ceil).unwrap().to_bits(), -2);
+    assert_eq!(Q15::round_from(b, Rounding::TowardZero).unwrap().to_bits(), -2);
+
+    assert_eq!(Q16_16::round_from(f64::INFINITY, Rounding::Floor), Ok(Q16_16::MAX));
+    assert_eq!(Q32_32::round_from(f64::NAN, Rounding::Ceil), Err(NotANumber));
+  }
+
+  #[test]
+  fn extreme_formats_stay_exact() {
+    type Q1_63 = Fixed<i64, 63>;
+    assert_eq!(Q1_63::MIN * Q1_63::MIN, Q1_63::MAX);
+    assert_eq!(Q1_63::MIN / Q1_63::MIN, Q1_63::MAX);
+    assert_eq!(Fixed::<i64, 0>::MIN.convert::<i64, 63>(), Q1_63::MIN);
+    assert_eq!(Fixed::<i64, 0>::MAX.checked_convert::<i8, 7>(), None);
+    assert_eq!(Fixed::<i32, 0>::from(-7.9).to_bits(), -7);
+  }
+}
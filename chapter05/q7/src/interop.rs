@@ -0,0 +1,143 @@
+//! Impls of other crates' traits, each behind the feature of the same name.
+//! `serde` support is derived on `Q7` itself.
+
+#[cfg(feature = "bytemuck")]
+mod bytemuck_impls {
+  use crate::Q7;
+
+  // Sound because `Q7` is a `repr(transparent)` `i8`, for which every bit
+  // pattern, including zero, is a valid value.
+  unsafe impl bytemuck::Zeroable for Q7 {}
+  unsafe impl bytemuck::Pod for Q7 {}
+}
+
+#[cfg(feature = "num-traits")]
+mod num_traits_impls {
+  use std::error::Error;
+  use std::fmt;
+
+  use num_traits::{Bounded, Num, One, Zero};
+
+  use crate::{Q7, Rounding};
+
+  impl Zero for Q7 {
+    fn zero() -> Q7 {
+      Q7(0)
+    }
+
+    fn is_zero(&self) -> bool {
+      self.0 == 0
+    }
+  }
+
+  /// 1.0 is out of range, so `one()` is `Q7::MAX`, the nearest value, as a
+  /// saturating conversion would give. It is not quite an identity:
+  /// `x * Q7::one()` is one step nearer zero than `x` for |x| > 0.5.
+  impl One for Q7 {
+    fn one() -> Q7 {
+      Q7::MAX
+    }
+  }
+
+  impl Bounded for Q7 {
+    fn min_value() -> Q7 {
+      Q7::MIN
+    }
+
+    fn max_value() -> Q7 {
+      Q7::MAX
+    }
+  }
+
+  /// Parses decimal numbers such as `0.25` or `-1e-2`. Other radixes are
+  /// errors. Values are rounded to the nearest `Q7`, ties to even, and
+  /// saturate outside [-1, 1).
+  impl Num for Q7 {
+    type FromStrRadixErr = ParseQ7Error;
+
+    fn from_str_radix(s: &str, radix: u32) -> Result<Q7, ParseQ7Error> {
+      if radix != 10 {
+        return Err(ParseQ7Error::Radix(radix));
+      }
+      let n: f64 = s.parse().map_err(|_| ParseQ7Error::Invalid)?;
+      Q7::round_from(n, Rounding::NearestEven).map_err(|_| ParseQ7Error::Invalid)
+    }
+  }
+
+  #[derive(Debug,Copy,Clone,PartialEq,Eq)]
+  pub enum ParseQ7Error {
+    /// Not a decimal number, or NaN.
+    Invalid,
+    /// Only radix 10 is supported.
+    Radix(u32),
+  }
+
+  impl fmt::Display for ParseQ7Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+        ParseQ7Error::Invalid => write!(f, "invalid Q7 literal"),
+        ParseQ7Error::Radix(radix) => write!(f, "Q7 can only be parsed in radix 10, not {}", radix),
+      }
+    }
+  }
+
+  impl Error for ParseQ7Error {}
+}
+
+#[cfg(feature = "num-traits")]
+pub use num_traits_impls::ParseQ7Error;
+
+#[cfg(all(test, any(feature = "serde", feature = "bytemuck", feature = "num-traits")))]
+mod tests {
+  use crate::Q7;
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn serializes_as_bits() {
+    let samples = vec![Q7::MIN, Q7::from(0.5), Q7::MAX];
+    let json = serde_json::to_string(&samples).unwrap();
+    assert_eq!(json, "[-128,64,127]");
+    assert_eq!(serde_json::from_str::<Vec<Q7>>(&json).unwrap(), samples);
+    assert!(serde_json::from_str::<Q7>("128").is_err());
+  }
+
+  #[cfg(feature = "bytemuck")]
+  #[test]
+  fn slices_cast_without_copying() {
+    let samples = [Q7::MIN, Q7::from(0.5), Q7::MAX];
+    let bits: &[i8] = bytemuck::cast_slice(&samples);
+    assert_eq!(bits, [-128, 64, 127]);
+    assert_eq!(bits.as_ptr() as usize, samples.as_ptr() as usize);
+
+    let mut raw = [0i8, 32, -64];
+    let samples: &mut [Q7] = bytemuck::cast_slice_mut(&mut raw);
+    samples[0] = Q7::MAX;
+    assert_eq!(samples[1..], [Q7::from(0.25), Q7::from(-0.5)]);
+    assert_eq!(raw[0], 127);
+  }
+
+  #[cfg(feature = "num-traits")]
+  #[test]
+  fn generic_numeric_code_works() {
+    use num_traits::{Bounded, Num, One, Zero};
+    use super::ParseQ7Error;
+
+    fn sum<T: Num + Copy>(values: &[T]) -> T {
+      values.iter().fold(T::zero(), |sum, x| sum + *x)
+    }
+
+    assert_eq!(sum(&[Q7::from(0.25), Q7::from(0.5), Q7::from(-0.125)]), Q7::from(0.625));
+    assert_eq!(sum(&[Q7::MAX, Q7::MAX]), Q7::MAX);
+    assert!(Q7::zero().is_zero() && !Q7::MIN.is_zero());
+    assert_eq!(Q7::one(), Q7::MAX);
+    assert_eq!(Q7::from(0.5) * Q7::one(), Q7::from(0.5));
+    assert_eq!((Q7::min_value(), Q7::max_value()), (Q7::MIN, Q7::MAX));
+
+    assert_eq!(Q7::from_str_radix("0.25", 10), Ok(Q7::from(0.25)));
+    assert_eq!(Q7::from_str_radix("0.7", 10), Ok(Q7(90)));
+    assert_eq!(Q7::from_str_radix("-3", 10), Ok(Q7::MIN));
+    assert_eq!(Q7::from_str_radix("NaN", 10), Err(ParseQ7Error::Invalid));
+    assert_eq!(Q7::from_str_radix("half", 10), Err(ParseQ7Error::Invalid));
+    assert_eq!(Q7::from_str_radix("0.1", 2), Err(ParseQ7Error::Radix(2)));
+  }
+}
@@ -1,21 +1,192 @@
-#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+pub mod buffer;
+pub mod fixed;
+mod interop;
+
+pub use fixed::{Fixed, NotANumber, Repr, Rounding, Q15, Q16_16, Q31, Q32_32};
+#[cfg(feature = "num-traits")]
+pub use interop::ParseQ7Error;
+
+/// A fixed-point number in [-1, 1) with 7 fractional bits: `Fixed<i8, 7>`,
+/// which it converts to and from freely, under its original name.
+///
+/// The operators saturate at `Q7::MIN` and `Q7::MAX`, as DSP arithmetic
+/// usually does. The `wrapping_` and `checked_` methods wrap around or
+/// return `None` instead. Like integer division, dividing by zero panics,
+/// except with `checked_div`.
+///
+/// Products and quotients are rounded to the nearest `Q7`, with ties
+/// rounded away from zero, so that negating an operand negates the result.
+///
+/// A `Q7` has the same layout as its `i8` bits. With the `serde` feature it
+/// serializes as those bits, and with `bytemuck` slices of it cast to and
+/// from `[i8]` without copying. The `num-traits` feature makes it a `Num`.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+#[repr(transparent)]
 pub struct Q7(i8);
 
+impl From<Fixed<i8, 7>> for Q7 {
+  fn from(n: Fixed<i8, 7>) -> Self {
+    Q7(n.to_bits())
+  }
+}
+
+impl From<Q7> for Fixed<i8, 7> {
+  fn from(n: Q7) -> Self {
+    Fixed::from_bits(n.0)
+  }
+}
+
+impl Q7 {
+  /// -1.0
+  pub const MIN: Q7 = Q7(i8::MIN);
+  /// 1.0 - 2^-7, the largest value below 1.0.
+  pub const MAX: Q7 = Q7(i8::MAX);
+
+  fn fixed(self) -> Fixed<i8, 7> {
+    Fixed::from(self)
+  }
+
+  /// Converts `n` with the given rounding, saturating outside [-1, 1).
+  /// Unlike `From<f64>`, which truncates, NaN is an error.
+  pub fn round_from(n: f64, rounding: Rounding) -> Result<Q7, NotANumber> {
+    Fixed::<i8, 7>::round_from(n, rounding).map(Q7::from)
+  }
+
+  pub fn saturating_add(self, other: Q7) -> Q7 {
+    self.fixed().saturating_add(other.fixed()).into()
+  }
+
+  pub fn wrapping_add(self, other: Q7) -> Q7 {
+    self.fixed().wrapping_add(other.fixed()).into()
+  }
+
+  pub fn checked_add(self, other: Q7) -> Option<Q7> {
+    self.fixed().checked_add(other.fixed()).map(Q7::from)
+  }
+
+  pub fn saturating_sub(self, other: Q7) -> Q7 {
+    self.fixed().saturating_sub(other.fixed()).into()
+  }
+
+  pub fn wrapping_sub(self, other: Q7) -> Q7 {
+    self.fixed().wrapping_sub(other.fixed()).into()
+  }
+
+  pub fn checked_sub(self, other: Q7) -> Option<Q7> {
+    self.fixed().checked_sub(other.fixed()).map(Q7::from)
+  }
+
+  pub fn saturating_mul(self, other: Q7) -> Q7 {
+    self.fixed().saturating_mul(other.fixed()).into()
+  }
+
+  pub fn wrapping_mul(self, other: Q7) -> Q7 {
+    self.fixed().wrapping_mul(other.fixed()).into()
+  }
+
+  pub fn checked_mul(self, other: Q7) -> Option<Q7> {
+    self.fixed().checked_mul(other.fixed()).map(Q7::from)
+  }
+
+  pub fn saturating_div(self, other: Q7) -> Q7 {
+    self.fixed().saturating_div(other.fixed()).into()
+  }
+
+  pub fn wrapping_div(self, other: Q7) -> Q7 {
+    self.fixed().wrapping_div(other.fixed()).into()
+  }
+
+  pub fn checked_div(self, other: Q7) -> Option<Q7> {
+    self.fixed().checked_div(other.fixed()).map(Q7::from)
+  }
+
+  /// Only -1 has no negation, and saturates to `Q7::MAX`.
+  pub fn saturating_neg(self) -> Q7 {
+    self.fixed().saturating_neg().into()
+  }
+
+  pub fn wrapping_neg(self) -> Q7 {
+    self.fixed().wrapping_neg().into()
+  }
+
+  pub fn checked_neg(self) -> Option<Q7> {
+    self.fixed().checked_neg().map(Q7::from)
+  }
+}
+
+impl Add for Q7 {
+  type Output = Q7;
+
+  fn add(self, other: Q7) -> Q7 {
+    self.saturating_add(other)
+  }
+}
+
+impl Sub for Q7 {
+  type Output = Q7;
+
+  fn sub(self, other: Q7) -> Q7 {
+    self.saturating_sub(other)
+  }
+}
+
+impl Mul for Q7 {
+  type Output = Q7;
+
+  fn mul(self, other: Q7) -> Q7 {
+    self.saturating_mul(other)
+  }
+}
+
+impl Div for Q7 {
+  type Output = Q7;
+
+  fn div(self, other: Q7) -> Q7 {
+    self.saturating_div(other)
+  }
+}
+
+/// The exact remainder, with the sign of `self`. Panics if `other` is zero.
+impl Rem for Q7 {
+  type Output = Q7;
+
+  fn rem(self, other: Q7) -> Q7 {
+    // Only -1 % -1 overflows, and its remainder is 0.
+    Q7(self.0.wrapping_rem(other.0))
+  }
+}
+
+impl Neg for Q7 {
+  type Output = Q7;
+
+  fn neg(self) -> Q7 {
+    self.saturating_neg()
+  }
+}
+
+/// Shows the exact value, e.g. `0.6953125`. Precision is honoured, so
+/// `{:.2}` gives `0.70`.
+impl fmt::Display for Q7 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Display::fmt(&f64::from(*self), f)
+  }
+}
+
+/// Truncates towards zero, so 0.7 becomes 89/128 rather than the nearer
+/// 90/128, and maps NaN to zero. See `Q7::round_from` for alternatives.
 impl From<f64> for Q7 {
   fn from(n: f64) -> Self {
-    if n >= 1.0 {
-      Q7(127)
-    } else if n <= -1.0 {
-      Q7(-128)
-    } else {
-      Q7((n * 128.0) as i8)
-    }
+    Fixed::<i8, 7>::from(n).into()
   }
 }
 
 impl From<Q7> for f64 {
   fn from(n: Q7) -> f64 {
-    (n.0 as f64) * 2_f64.powf(-7.0)
+    f64::from(n.fixed())
   }
 }
 
@@ -72,4 +243,113 @@ mod tests {
     let f3 = f32::from(q3);
     assert!((-0.4 - f3).abs() < 1.0/128.0);
   }
+
+  #[test]
+  fn rounding_modes() {
+    let round = |n: f64, rounding| Q7::round_from(n, rounding).unwrap();
+
+    assert_eq!(round(0.7, Rounding::TowardZero), Q7(89));
+    assert_eq!(round(0.7, Rounding::NearestEven), Q7(90));
+    assert_eq!(round(0.7, Rounding::Floor), Q7(89));
+    assert_eq!(round(-0.7, Rounding::Floor), Q7(-90));
+    assert_eq!(round(-0.7, Rounding::Ceil), Q7(-89));
+
+    for (n, even, away) in [(88.5, 88, 89), (89.5, 90, 90), (-88.5, -88, -89), (-0.5, 0, -1)] {
+      assert_eq!(round(n / 128.0, Rounding::NearestEven), Q7(even), "{}", n);
+      assert_eq!(round(n / 128.0, Rounding::NearestAway), Q7(away), "{}", n);
+    }
+
+    assert_eq!(round(f64::INFINITY, Rounding::NearestEven), Q7::MAX);
+    assert_eq!(round(-2.0, Rounding::Ceil), Q7::MIN);
+    assert_eq!(Q7::round_from(f64::NAN, Rounding::NearestEven), Err(NotANumber));
+    assert_eq!(Q7::from(f64::NAN), Q7(0));
+  }
+
+  #[test]
+  fn stochastic_rounding_is_unbiased() {
+    // 0.7 is 89.6 / 128, so it should round up 60% of the time.
+    assert_eq!(Q7::round_from(0.7, Rounding::Stochastic(0.59)), Ok(Q7(90)));
+    assert_eq!(Q7::round_from(0.7, Rounding::Stochastic(0.61)), Ok(Q7(89)));
+    assert_eq!(Q7::round_from(-0.7, Rounding::Stochastic(0.39)), Ok(Q7(-89)));
+
+    let mut seed: u64 = 1;
+    let mut sum = 0.0;
+    for _ in 0..10_000 {
+      seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+      let sample = (seed >> 11) as f64 / (1u64 << 53) as f64;
+      sum += f64::from(Q7::round_from(0.7, Rounding::Stochastic(sample)).unwrap());
+    }
+    assert!((sum / 10_000.0 - 0.7).abs() < 0.0005, "{}", sum / 10_000.0);
+  }
+
+  #[test]
+  fn add_and_sub_saturate_or_wrap() {
+    assert_eq!(Q7(100) + Q7(27), Q7::MAX);
+    assert_eq!(Q7(100) + Q7(100), Q7::MAX);
+    assert_eq!(Q7(-100) - Q7(100), Q7::MIN);
+    assert_eq!(Q7(100).wrapping_add(Q7(100)), Q7(-56));
+    assert_eq!(Q7(-100).wrapping_sub(Q7(100)), Q7(56));
+    assert_eq!(Q7(100).checked_add(Q7(100)), None);
+    assert_eq!(Q7(100).checked_sub(Q7(100)), Some(Q7(0)));
+  }
+
+  #[test]
+  fn mul_rounds_to_nearest() {
+    assert_eq!(Q7::from(0.5) * Q7::from(0.5), Q7::from(0.25));
+    // 3/128 * 64/128 is 1.5/128, a tie.
+    assert_eq!(Q7(3) * Q7(64), Q7(2));
+    assert_eq!(Q7(-3) * Q7(64), Q7(-2));
+    // 5/128 * 13/128 is 0.5078/128.
+    assert_eq!(Q7(5) * Q7(13), Q7(1));
+    assert_eq!(Q7(5) * Q7(12), Q7(0));
+
+    assert_eq!(Q7::MIN * Q7::MIN, Q7::MAX);
+    assert_eq!(Q7::MIN.wrapping_mul(Q7::MIN), Q7::MIN);
+    assert_eq!(Q7::MIN.checked_mul(Q7::MIN), None);
+    assert_eq!(Q7::MIN * Q7::MAX, Q7(-127));
+  }
+
+  #[test]
+  fn div_rounds_and_saturates() {
+    assert_eq!(Q7::from(0.25) / Q7::from(0.5), Q7::from(0.5));
+    assert_eq!(Q7(1) / Q7(3), Q7(43));
+    assert_eq!(Q7(-1) / Q7(3), Q7(-43));
+    assert_eq!(Q7::from(0.5) / Q7::from(0.25), Q7::MAX);
+    assert_eq!(Q7::from(0.5) / Q7::from(-0.25), Q7::MIN);
+    assert_eq!(Q7::MIN / Q7::MIN, Q7::MAX);
+    assert_eq!(Q7::MIN.wrapping_div(Q7::MIN), Q7::MIN);
+    assert_eq!(Q7(1).checked_div(Q7(0)), None);
+    assert_eq!(Q7(2).checked_div(Q7(1)), None);
+    assert_eq!(Q7(1).checked_div(Q7(-2)), Some(Q7(-64)));
+    assert_eq!(Q7(1).checked_div(Q7(2)), Some(Q7(64)));
+  }
+
+  #[test]
+  fn rem_is_exact() {
+    assert_eq!(Q7::from(0.75) % Q7::from(0.5), Q7::from(0.25));
+    assert_eq!(Q7::from(-0.75) % Q7::from(0.5), Q7::from(-0.25));
+    assert_eq!(Q7(7) % Q7(-3), Q7(1));
+    assert_eq!(Q7::MIN % Q7::MIN, Q7(0));
+  }
+
+  #[test]
+  #[should_panic(expected = "divide by zero")]
+  fn div_by_zero_panics() {
+    let _ = Q7(1) / Q7(0);
+  }
+
+  #[test]
+  fn neg_order_and_display() {
+    assert_eq!(-Q7(5), Q7(-5));
+    assert_eq!(-Q7::MIN, Q7::MAX);
+    assert_eq!(Q7::MIN.wrapping_neg(), Q7::MIN);
+    assert_eq!(Q7::MIN.checked_neg(), None);
+
+    assert!(Q7::MIN < Q7(0) && Q7(0) < Q7::MAX);
+    assert_eq!(Q7(-3).max(Q7(2)), Q7(2));
+
+    assert_eq!(Q7::from(0.7).to_string(), "0.6953125");
+    assert_eq!(format!("{:.2}", Q7::from(0.7)), "0.70");
+    assert_eq!(Q7::MIN.to_string(), "-1");
+  }
 }
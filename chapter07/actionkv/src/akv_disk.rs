@@ -1,80 +1,126 @@
-use libactionkv::ActionKV;
+mod cli;
+
+use cli::{print_ok, print_value, take_json_flag, CliError};
+use libactionkv::{ActionKV, ActionKvError};
 use std::collections::HashMap;
 
 #[cfg(target_os = "windows")]
 const USAGE: &str = "\
 Usage:
-  akv_mem.exe FILE get KEY
-  akv_mem.exe FILE delete KEY
-  akv_mem.exe FILE insert KEY VALUE
-  akv_mem.exe FILE update KEY VALUE
+  akv_disk.exe FILE get KEY
+  akv_disk.exe FILE delete KEY
+  akv_disk.exe FILE insert KEY VALUE
+  akv_disk.exe FILE update KEY VALUE
+
+Options:
+  --json  print results and errors as JSON, if given before FILE
+
+Exit codes: 1 not found, 2 usage, 3 corruption, 4 I/O error
 ";
 
 #[cfg(not(target_os = "windows"))]
 const USAGE: &str = "\
 Usage:
-  akv_mem FILE get KEY
-  akv_mem FILE delete KEY
-  akv_mem FILE insert KEY VALUE
-  akv_mem FILE update KEY VALUE
+  akv_disk FILE get KEY
+  akv_disk FILE delete KEY
+  akv_disk FILE insert KEY VALUE
+  akv_disk FILE update KEY VALUE
+
+Options:
+  --json  print results and errors as JSON, if given before FILE
+
+Exit codes: 1 not found, 2 usage, 3 corruption, 4 I/O error
 ";
 
 type ByteStr = [u8];
 type ByteString = Vec<u8>;
 
-fn store_index_on_disk(action_kv_db: &mut ActionKV, index_key: &ByteStr) {
+const INDEX_KEY: &ByteStr = b"+index";
+
+fn store_index_on_disk(action_kv_db: &mut ActionKV, index_key: &ByteStr) -> Result<(), CliError> {
   action_kv_db.index_map.remove(index_key);
-  let index_as_bytes = bincode::serialize(&action_kv_db.index_map).unwrap();
+  let index_as_bytes = bincode::serialize(&action_kv_db.index_map)
+    .expect("a HashMap of byte strings always serializes");
   action_kv_db.index_map = HashMap::new();
-  action_kv_db.insert(index_key, &index_as_bytes).unwrap();
+  action_kv_db.insert(index_key, &index_as_bytes)?;
+  action_kv_db.sync()?;
+  Ok(())
 }
 
-fn main() {
-  const INDEX_KEY: &ByteStr = b"+index";
+/// Reads the index saved by `store_index_on_disk`. A store that has never
+/// been written to has no index yet, which is the same as an empty one.
+fn load_index_from_disk(action_kv_db: &mut ActionKV) -> Result<HashMap<ByteString, u64>, CliError> {
+  let index_as_bytes = match action_kv_db.get(INDEX_KEY)? {
+    None => return Ok(HashMap::new()),
+    Some(index_as_bytes) => index_as_bytes,
+  };
 
-  let args: Vec<String> = std::env::args().collect();
-  let file_name = args.get(1).expect(&USAGE);
-  let action = args.get(2).expect(&USAGE).as_ref();
-  let key = args.get(3).expect(&USAGE).as_ref();
+  bincode::deserialize(&index_as_bytes).map_err(|err| {
+    let position = action_kv_db.index_map[INDEX_KEY];
+    let detail = format!("unreadable index: {}", err);
+    CliError::Store(ActionKvError::Corruption { position, detail })
+  })
+}
+
+fn run(args: &[String], json: bool) -> Result<(), CliError> {
+  let file_name = args.get(1).ok_or(CliError::Usage)?;
+  let action = args.get(2).ok_or(CliError::Usage)?.as_str();
+  let key = args.get(3).ok_or(CliError::Usage)?.as_bytes();
   let maybe_value = args.get(4);
 
   let path = std::path::Path::new(&file_name);
-  let mut action_kv_db = ActionKV::open(path).expect("Unable to open file");
+  let mut action_kv_db = ActionKV::open(path)?;
 
-  action_kv_db.load().expect("Unable to load data");
+  action_kv_db.load()?;
 
   match action {
     "get" => {
-      let index_as_bytes = action_kv_db.get(&INDEX_KEY)
-        .unwrap()
-        .unwrap();
-
-      let decoded_index_map = bincode::deserialize(&index_as_bytes);
-      let index_map: HashMap<ByteString, u64> = decoded_index_map.unwrap();
-
-      match index_map.get(key) {
-        None => eprintln!("{:?} not found", String::from_utf8_lossy(&key)),
-        Some(&index) => {
-          let key_value = action_kv_db.get_at(index).unwrap();
-          println!("{:?}", String::from_utf8_lossy(&key_value.value.to_owned() as &[u8]))
-        }
+      let index_map = load_index_from_disk(&mut action_kv_db)?;
+
+      let index = match index_map.get(key) {
+        None => return Err(CliError::NotFound(key.to_vec())),
+        Some(&index) => index,
+      };
+
+      let key_value = action_kv_db.get_at(index)?;
+      if key_value.value.is_empty() {
+        return Err(CliError::NotFound(key.to_vec()));
       }
+      print_value(key, &key_value.value, json);
     },
 
-    "delete" => action_kv_db.delete(key).unwrap(),
+    "delete" => {
+      action_kv_db.delete(key)?;
+      store_index_on_disk(&mut action_kv_db, INDEX_KEY)?;
+      print_ok(json);
+    },
 
     "insert" => {
-      let value = maybe_value.expect(&USAGE).as_ref();
-      action_kv_db.insert(key, value).unwrap();
-      store_index_on_disk(&mut action_kv_db, INDEX_KEY);
+      let value = maybe_value.ok_or(CliError::Usage)?.as_bytes();
+      action_kv_db.insert(key, value)?;
+      store_index_on_disk(&mut action_kv_db, INDEX_KEY)?;
+      print_ok(json);
     }
 
     "update" => {
-      let value = maybe_value.expect(&USAGE).as_ref();
-      action_kv_db.update(key, value).unwrap();
-      store_index_on_disk(&mut action_kv_db, INDEX_KEY);
+      let value = maybe_value.ok_or(CliError::Usage)?.as_bytes();
+      action_kv_db.update(key, value)?;
+      store_index_on_disk(&mut action_kv_db, INDEX_KEY)?;
+      print_ok(json);
     }
 
-    _ => eprintln!("{}", &USAGE),
+    _ => return Err(CliError::Usage),
   }
-}
\ No newline at end of file
+
+  Ok(())
+}
+
+fn main() {
+  let mut args: Vec<String> = std::env::args().collect();
+  let json = take_json_flag(&mut args);
+
+  if let Err(err) = run(&args, json) {
+    err.report(USAGE, json);
+    std::process::exit(err.exit_code());
+  }
+}
@@ -1,4 +1,8 @@
-use libactionkv::ActionKV;
+mod cli;
+
+use cli::{print_ok, print_value, take_json_flag, CliError};
+use libactionkv::{ActionKV, Stats};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[cfg(target_os = "windows")]
 const USAGE: &str = "\
@@ -7,6 +11,13 @@ Usage:
   akv_mem.exe FILE delete KEY
   akv_mem.exe FILE insert KEY VALUE
   akv_mem.exe FILE update KEY VALUE
+  akv_mem.exe FILE stats
+  akv_mem.exe FILE compact
+
+Options:
+  --json  print results and errors as JSON, if given before FILE
+
+Exit codes: 1 not found, 2 usage, 3 corruption, 4 I/O error
 ";
 
 #[cfg(not(target_os = "windows"))]
@@ -16,37 +27,118 @@ Usage:
   akv_mem FILE delete KEY
   akv_mem FILE insert KEY VALUE
   akv_mem FILE update KEY VALUE
+  akv_mem FILE stats
+  akv_mem FILE compact
+
+Options:
+  --json  print results and errors as JSON, if given before FILE
+
+Exit codes: 1 not found, 2 usage, 3 corruption, 4 I/O error
 ";
 
-fn main() {
-  let args: Vec<String> = std::env::args().collect();
-  let file_name = args.get(1).expect(&USAGE);
-  let action = args.get(2).expect(&USAGE).as_ref();
-  let key = args.get(3).expect(&USAGE).as_ref();
+fn unix_seconds(time: Option<SystemTime>) -> Option<u64> {
+  time.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    .map(|d| d.as_secs())
+}
+
+fn print_stats(stats: &Stats) {
+  let last_compaction = match unix_seconds(stats.last_compaction) {
+    None => String::from("never"),
+    Some(secs) => format!("{} (unix time)", secs),
+  };
+
+  println!("live keys:           {}", stats.live_keys);
+  println!("total records:       {}", stats.total_records);
+  println!("dead bytes:          {}", stats.dead_bytes);
+  println!("file size:           {}", stats.file_size);
+  println!("bytes written:       {}", stats.bytes_written);
+  println!("bytes read:          {}", stats.bytes_read);
+  println!("average record size: {:.1}", stats.average_record_size);
+  println!("last compaction:     {}", last_compaction);
+}
+
+fn print_stats_json(stats: &Stats) {
+  let json = serde_json::json!({
+    "live_keys": stats.live_keys,
+    "total_records": stats.total_records,
+    "dead_bytes": stats.dead_bytes,
+    "file_size": stats.file_size,
+    "bytes_written": stats.bytes_written,
+    "bytes_read": stats.bytes_read,
+    "average_record_size": stats.average_record_size,
+    "last_compaction": unix_seconds(stats.last_compaction),
+  });
+  println!("{}", json);
+}
+
+fn run(args: &[String], json: bool) -> Result<(), CliError> {
+  let file_name = args.get(1).ok_or(CliError::Usage)?;
+  let action = args.get(2).ok_or(CliError::Usage)?.as_str();
+  let maybe_key = args.get(3);
   let maybe_value = args.get(4);
 
   let path = std::path::Path::new(&file_name);
-  let mut store = ActionKV::open(path).expect("Unable to open file");
-  store.load().expect("Unable to load data");
+  let mut store = ActionKV::open(path)?;
+  store.load()?;
 
   match action {
-    "get" => match store.get(&key).unwrap() {
-      None => eprintln!("{:?} not found", String::from_utf8_lossy(&key)),
-      Some(value) => println!("{:?}", String::from_utf8_lossy(&value as &[u8])),
+    "get" => {
+      let key = maybe_key.ok_or(CliError::Usage)?.as_bytes();
+      match store.get(key)? {
+        Some(value) if !value.is_empty() => print_value(key, &value, json),
+        _ => return Err(CliError::NotFound(key.to_vec())),
+      }
     },
 
-    "delete" => store.delete(key).unwrap(),
+    "delete" => {
+      let key = maybe_key.ok_or(CliError::Usage)?.as_bytes();
+      store.delete(key)?;
+      store.sync()?;
+      print_ok(json);
+    },
 
     "insert" => {
-      let value = maybe_value.expect(&USAGE).as_ref();
-      store.insert(key, value).unwrap();
+      let key = maybe_key.ok_or(CliError::Usage)?.as_bytes();
+      let value = maybe_value.ok_or(CliError::Usage)?.as_bytes();
+      store.insert(key, value)?;
+      store.sync()?;
+      print_ok(json);
     },
 
     "update" => {
-      let value = maybe_value.expect(&USAGE).as_ref();
-      store.update(key, value).unwrap();
+      let key = maybe_key.ok_or(CliError::Usage)?.as_bytes();
+      let value = maybe_value.ok_or(CliError::Usage)?.as_bytes();
+      store.update(key, value)?;
+      store.sync()?;
+      print_ok(json);
+    },
+
+    "stats" => {
+      let stats = store.stats()?;
+      if json {
+        print_stats_json(&stats);
+      } else {
+        print_stats(&stats);
+      }
+    },
+
+    "compact" => {
+      store.compact()?;
+      print_ok(json);
     },
 
-    _ => eprintln!("{}", &USAGE),
+    _ => return Err(CliError::Usage),
+  }
+
+  Ok(())
+}
+
+fn main() {
+  let mut args: Vec<String> = std::env::args().collect();
+  let json = take_json_flag(&mut args);
+
+  if let Err(err) = run(&args, json) {
+    err.report(USAGE, json);
+    std::process::exit(err.exit_code());
   }
 }
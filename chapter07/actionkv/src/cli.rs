@@ -0,0 +1,101 @@
+//! Error reporting and output shared by the `akv_mem` and `akv_disk`
+//! binaries. Each includes this file as its own module, so none of it is
+//! part of the library's API.
+
+use std::fmt;
+
+use serde_json::json;
+
+use libactionkv::ActionKvError;
+
+pub const EXIT_NOT_FOUND: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CORRUPTION: i32 = 3;
+pub const EXIT_IO: i32 = 4;
+
+#[derive(Debug)]
+pub enum CliError {
+  Usage,
+  NotFound(Vec<u8>),
+  Store(ActionKvError),
+}
+
+impl CliError {
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      CliError::Usage => EXIT_USAGE,
+      CliError::NotFound(_) => EXIT_NOT_FOUND,
+      CliError::Store(ActionKvError::Corruption { .. }) => EXIT_CORRUPTION,
+      CliError::Store(ActionKvError::TooLarge(_)) => EXIT_USAGE,
+      CliError::Store(ActionKvError::Io(_)) => EXIT_IO,
+    }
+  }
+
+  fn kind(&self) -> &'static str {
+    match self {
+      CliError::Usage => "usage",
+      CliError::NotFound(_) => "not_found",
+      CliError::Store(ActionKvError::Corruption { .. }) => "corruption",
+      CliError::Store(ActionKvError::TooLarge(_)) => "too_large",
+      CliError::Store(ActionKvError::Io(_)) => "io",
+    }
+  }
+
+  /// Writes the error to stderr, as a single JSON object if `json` is set.
+  pub fn report(&self, usage: &str, json: bool) {
+    let message = match self {
+      CliError::Usage => usage.to_string(),
+      _ => self.to_string(),
+    };
+
+    if json {
+      eprintln!("{}", json!({ "error": self.kind(), "message": message }));
+    } else {
+      eprintln!("{}", message);
+    }
+  }
+}
+
+impl fmt::Display for CliError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CliError::Usage => write!(f, "invalid arguments"),
+      CliError::NotFound(key) => write!(f, "{:?} not found", String::from_utf8_lossy(key)),
+      CliError::Store(err) => write!(f, "{}", err),
+    }
+  }
+}
+
+impl From<ActionKvError> for CliError {
+  fn from(err: ActionKvError) -> Self {
+    CliError::Store(err)
+  }
+}
+
+/// Removes a `--json` flag from the start of `args`, right after the program
+/// name, returning whether there was one. Anywhere else it is an ordinary
+/// argument, so that "--json" can be used as a key or value.
+pub fn take_json_flag(args: &mut Vec<String>) -> bool {
+  if args.get(1).map(String::as_str) == Some("--json") {
+    args.remove(1);
+    true
+  } else {
+    false
+  }
+}
+
+pub fn print_value(key: &[u8], value: &[u8], json: bool) {
+  let value = String::from_utf8_lossy(value);
+
+  if json {
+    println!("{}", json!({ "key": String::from_utf8_lossy(key), "value": value }));
+  } else {
+    println!("{:?}", value);
+  }
+}
+
+pub fn print_ok(json: bool) {
+  if json {
+    println!("{}", json!({ "ok": true }));
+  }
+}
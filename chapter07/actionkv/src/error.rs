@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ActionKvError {
+  /// The storage underneath the store failed.
+  Io(io::Error),
+  /// The record starting at `position` failed its checksum or could not be
+  /// parsed.
+  Corruption { position: u64, detail: String },
+  /// A key, value or record is too large to be stored or held in memory.
+  TooLarge(&'static str),
+}
+
+impl ActionKvError {
+  /// Classifies an error raised while parsing the record at `position`.
+  pub(crate) fn from_record(position: u64, err: io::Error) -> Self {
+    match err.kind() {
+      io::ErrorKind::InvalidData => ActionKvError::Corruption {
+        position,
+        detail: err.to_string(),
+      },
+      io::ErrorKind::UnexpectedEof => ActionKvError::Corruption {
+        position,
+        detail: String::from("record is cut short"),
+      },
+      io::ErrorKind::OutOfMemory => ActionKvError::TooLarge("record"),
+      _ => ActionKvError::Io(err),
+    }
+  }
+}
+
+impl fmt::Display for ActionKvError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ActionKvError::Io(err) => write!(f, "I/O error: {}", err),
+      ActionKvError::Corruption { position, detail } => {
+        write!(f, "corrupt record at byte {}: {}", position, detail)
+      },
+      ActionKvError::TooLarge(what) => write!(f, "{} is too large", what),
+    }
+  }
+}
+
+impl Error for ActionKvError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ActionKvError::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for ActionKvError {
+  fn from(err: io::Error) -> Self {
+    ActionKvError::Io(err)
+  }
+}
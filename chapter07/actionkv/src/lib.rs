@@ -1,105 +1,378 @@
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::io::{BufReader, BufWriter, SeekFrom};
+use std::io::BufReader;
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use crc::{Crc, CRC_32_CKSUM};
+use crc::{Crc, Digest, CRC_32_CKSUM};
 use serde_derive::{Deserialize, Serialize};
 
+mod error;
+pub mod storage;
+
+pub use error::ActionKvError;
+pub use storage::{FileStorage, MemStorage, Storage};
+use storage::StorageReader;
+
 type ByteString = Vec<u8>;
 type ByteStr = [u8];
 
+static CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
+
+/// Records come in two layouts, told apart by the word after the checksum:
+///
+/// - v1: `checksum: u32 | key_len: u32 | value_len: u32 | key | value`
+/// - v2: `checksum: u32 | 0xffffffff | key_len: u64 | value_len: u64 |
+///   header_checksum: u32 | key | value`
+///
+/// The v1 checksum covers the key and value, the v2 checksum also covers
+/// both lengths. The v2 header checksum covers just the lengths, so that a
+/// record cut short by a crash can be told apart from a corrupted length.
+/// New records are always written as v2; v1 records written by older
+/// versions are still read.
+const RECORD_V2_MARKER: u32 = u32::MAX;
+const HEADER_V1_LEN: u64 = 12;
+const HEADER_V2_LEN: u64 = 28;
+
+const COPY_BUFFER_LEN: usize = 8 * 1024;
+
+/// Compaction starts the new file with a record under this key, holding
+/// the time as seconds (u64) and nanoseconds (u32) since the Unix epoch.
+/// `load` reads it back into `Stats::last_compaction` and keeps it out of
+/// the index, so the key is reserved.
+const LAST_COMPACTION_KEY: &ByteStr = b"+last_compaction";
+
+fn encode_time(time: SystemTime) -> ByteString {
+  let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+  let mut value = ByteString::with_capacity(12);
+  value.extend_from_slice(&since_epoch.as_secs().to_le_bytes());
+  value.extend_from_slice(&since_epoch.subsec_nanos().to_le_bytes());
+  value
+}
+
+fn decode_time(mut value: &ByteStr) -> Option<SystemTime> {
+  if value.len() != 12 {
+    return None;
+  }
+  let secs = value.read_u64::<LittleEndian>().ok()?;
+  let nanos = value.read_u32::<LittleEndian>().ok()?;
+  UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RecordHeader {
+  checksum: u32,
+  key_len: u64,
+  value_len: u64,
+  is_v2: bool,
+}
+
+impl RecordHeader {
+  fn read<R: Read>(file: &mut R) -> io::Result<Self> {
+    let checksum = file.read_u32::<LittleEndian>()?;
+    let first_len = file.read_u32::<LittleEndian>()?;
+
+    if first_len == RECORD_V2_MARKER {
+      let key_len = file.read_u64::<LittleEndian>()?;
+      let value_len = file.read_u64::<LittleEndian>()?;
+      let header_checksum = file.read_u32::<LittleEndian>()?;
+
+      let header = RecordHeader { checksum, key_len, value_len, is_v2: true };
+      if header_checksum != header.header_checksum() {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          "data corruption encountered in record header",
+        ));
+      }
+      Ok(header)
+    } else {
+      let value_len = file.read_u32::<LittleEndian>()?;
+      Ok(RecordHeader {
+        checksum,
+        key_len: first_len as u64,
+        value_len: value_len as u64,
+        is_v2: false,
+      })
+    }
+  }
+
+  fn record_len(&self) -> u64 {
+    let header_len = if self.is_v2 { HEADER_V2_LEN } else { HEADER_V1_LEN };
+    header_len
+      .saturating_add(self.key_len)
+      .saturating_add(self.value_len)
+  }
+
+  fn header_checksum(&self) -> u32 {
+    let mut digest = CRC.digest();
+    digest.update(&self.key_len.to_le_bytes());
+    digest.update(&self.value_len.to_le_bytes());
+    digest.finalize()
+  }
+
+  /// Running out of data part-way through a v2 record means the write was
+  /// cut short, since its lengths have been checked. A v1 record's lengths
+  /// may themselves be corrupt, so there it is reported as corruption.
+  fn truncated(&self, err: io::Error) -> io::Error {
+    if err.kind() == io::ErrorKind::UnexpectedEof && !self.is_v2 {
+      return io::Error::new(
+        io::ErrorKind::InvalidData,
+        "record extends past the end of the file",
+      );
+    }
+    err
+  }
+
+  fn digest(&self) -> Digest<'static, u32> {
+    let mut digest = CRC.digest();
+    if self.is_v2 {
+      digest.update(&self.key_len.to_le_bytes());
+      digest.update(&self.value_len.to_le_bytes());
+    }
+    digest
+  }
+
+  fn verify(&self, digest: Digest<'static, u32>) -> io::Result<()> {
+    let checksum = digest.finalize();
+
+    if checksum != self.checksum {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("data corruption encountered ({:08x} != {:08x})", checksum, self.checksum),
+      ));
+    }
+
+    Ok(())
+  }
+}
+
+fn read_bytes<R: Read>(file: &mut R, len: u64, digest: &mut Digest<u32>) -> io::Result<ByteString> {
+  if usize::try_from(len).is_err() {
+    return Err(io::Error::new(
+      io::ErrorKind::OutOfMemory,
+      "record is too large to be held in memory, use get_reader() instead",
+    ));
+  }
+
+  let mut data = ByteString::new();
+  file.by_ref().take(len).read_to_end(&mut data)?;
+  if (data.len() as u64) < len {
+    return Err(io::ErrorKind::UnexpectedEof.into());
+  }
+
+  digest.update(&data);
+  Ok(data)
+}
+
+fn skip_bytes<R: Read>(file: &mut R, mut len: u64, digest: &mut Digest<u32>) -> io::Result<()> {
+  let mut buffer = [0; COPY_BUFFER_LEN];
+
+  while len > 0 {
+    let chunk_len = len.min(COPY_BUFFER_LEN as u64) as usize;
+    let chunk = &mut buffer[..chunk_len];
+    file.read_exact(chunk)?;
+    digest.update(chunk);
+    len -= chunk_len as u64;
+  }
+
+  Ok(())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyValuePair {
   pub key: ByteString,
   pub value: ByteString,
 }
 
+/// A point-in-time snapshot of the health of a store, as returned by
+/// [`ActionKV::stats`].
+///
+/// `bytes_written` and `bytes_read` count what this handle has done and are
+/// not stored in the file. `last_compaction` is: compaction records it in
+/// the file it writes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+  /// Keys whose latest record holds a value (deleted keys are excluded).
+  pub live_keys: usize,
+  /// Records in the file, including superseded ones and tombstones.
+  pub total_records: u64,
+  /// Bytes that a compaction would reclaim.
+  pub dead_bytes: u64,
+  pub file_size: u64,
+  /// Bytes appended by this handle since it was opened.
+  pub bytes_written: u64,
+  /// Bytes of records read back by this handle since it last ran
+  /// [`ActionKV::load`], the scan included.
+  pub bytes_read: u64,
+  pub average_record_size: f64,
+  /// When the file was last written by [`ActionKV::compact`], if ever.
+  pub last_compaction: Option<SystemTime>,
+}
+
 #[derive(Debug)]
-pub struct ActionKV {
-  file: File,
+pub struct ActionKV<S: Storage = FileStorage> {
+  storage: S,
   pub index_map: HashMap<ByteString, u64>,
+  total_records: u64,
+  bytes_written: u64,
+  bytes_read: u64,
+  last_compaction: Option<SystemTime>,
+  /// Where the record holding `last_compaction` starts, if there is one.
+  compaction_record: Option<u64>,
+  /// Where the last whole record ends, if `load` found a torn record after
+  /// it. The tail is cut off before the next write, not by `load`, so that
+  /// reading a store never modifies it.
+  torn_tail: Option<u64>,
 }
 
-impl ActionKV {
-  pub fn open(path: &Path) -> io::Result<Self> {
-    let file = OpenOptions::new()
-      .read(true)
-      .write(true)
-      .create(true)
-      .append(true)
-      .open(path)?;
-    let index_map = HashMap::new();
-    Ok(ActionKV { file, index_map })
+impl ActionKV<FileStorage> {
+  pub fn open(path: &Path) -> Result<Self, ActionKvError> {
+    let storage = FileStorage::open(path)?;
+    Ok(ActionKV::with_storage(storage))
   }
 
-  fn checksum(data: &Vec<u8>) -> u32 {
-    Crc::<u32>::new(&CRC_32_CKSUM).checksum(&data)
-  }
+  /// Rewrites the file so that it only holds the latest record of every
+  /// live key. The new file is built next to the old one and renamed over
+  /// it, so a crash part-way through leaves the original intact.
+  pub fn compact(&mut self) -> Result<(), ActionKvError> {
+    let path = self.storage.path().to_path_buf();
+    let tmp_path = path.with_extension("compact");
+    if tmp_path.exists() {
+      fs::remove_file(&tmp_path)?;
+    }
 
-  fn process_record<R: Read>(file: &mut R) -> io::Result<KeyValuePair> {
-    let saved_checksum = file.read_u32::<LittleEndian>()?;
-    let key_len = file.read_u32::<LittleEndian>()?;
-    let value_len = file.read_u32::<LittleEndian>()?;
-    let data_len: usize = key_len as usize + value_len as usize;
+    let compacted = FileStorage::open(&tmp_path)?;
+    self.compact_into(compacted)?;
+    fs::rename(&tmp_path, &path)?;
+    self.storage.set_path(path);
 
-    let mut data = ByteString::with_capacity(data_len);
+    Ok(())
+  }
+}
 
-    {
-      file.by_ref()
-        .take(data_len as u64)
-        .read_to_end(&mut data)?;
+impl<S: Storage> ActionKV<S> {
+  pub fn with_storage(storage: S) -> Self {
+    ActionKV {
+      storage,
+      index_map: HashMap::new(),
+      total_records: 0,
+      bytes_written: 0,
+      bytes_read: 0,
+      last_compaction: None,
+      compaction_record: None,
+      torn_tail: None,
     }
-    debug_assert_eq!(data.len(), data_len);
+  }
 
-    let checksum = ActionKV::checksum(&data);
+  pub fn storage(&self) -> &S {
+    &self.storage
+  }
 
-    if checksum != saved_checksum {
-      panic!(
-        "data corruption encountered ({:08x} != {:08x})",
-        checksum, saved_checksum
-      );
-    }
+  pub fn storage_mut(&mut self) -> &mut S {
+    &mut self.storage
+  }
 
-    let value = data.split_off(key_len as usize);
-    let key = data;
+  pub fn into_storage(self) -> S {
+    self.storage
+  }
 
-    Ok(KeyValuePair { key, value })
+  /// Makes every record written so far durable. Until this returns `Ok`,
+  /// writes may be lost in a crash.
+  pub fn sync(&mut self) -> Result<(), ActionKvError> {
+    Ok(self.storage.sync()?)
   }
 
-  pub fn seek_to_end(&mut self) -> io::Result<u64> {
-    self.file.seek(SeekFrom::End(0))
+  fn process_record<R: Read>(file: &mut R) -> io::Result<(RecordHeader, KeyValuePair)> {
+    let header = RecordHeader::read(file)?;
+    let mut digest = header.digest();
+
+    let key = read_bytes(file, header.key_len, &mut digest)
+      .map_err(|err| header.truncated(err))?;
+    let value = read_bytes(file, header.value_len, &mut digest)
+      .map_err(|err| header.truncated(err))?;
+    header.verify(digest)?;
+
+    Ok((header, KeyValuePair { key, value }))
   }
 
-  pub fn load(&mut self) -> io::Result<()> {
-    let mut file = BufReader::new(&mut self.file);
+  /// Like `process_record`, but streams the value through the checksum
+  /// instead of keeping it, so scanning the file never buffers a value.
+  fn process_record_key<R: Read>(file: &mut R) -> io::Result<(RecordHeader, ByteString)> {
+    let header = RecordHeader::read(file)?;
+    let mut digest = header.digest();
+
+    let key = read_bytes(file, header.key_len, &mut digest)
+      .map_err(|err| header.truncated(err))?;
+    skip_bytes(file, header.value_len, &mut digest)
+      .map_err(|err| header.truncated(err))?;
+    header.verify(digest)?;
+
+    Ok((header, key))
+  }
+
+  pub fn seek_to_end(&mut self) -> Result<u64, ActionKvError> {
+    Ok(self.storage.len()?)
+  }
+
+  /// Builds the index from the records in storage, replacing whatever was
+  /// loaded before. A record cut short by a crash can only be the last one.
+  /// It is skipped, and truncated away by the next write so that new
+  /// records are not appended after it.
+  pub fn load(&mut self) -> Result<(), ActionKvError> {
+    self.index_map.clear();
+    self.total_records = 0;
+    self.bytes_read = 0;
+    self.last_compaction = None;
+    self.compaction_record = None;
+    self.torn_tail = None;
+
+    let mut file = BufReader::new(StorageReader::new(&mut self.storage, 0));
+    let mut current_position;
 
     loop {
-      let current_position = file.seek(SeekFrom::Current(0))?;
+      current_position = file.stream_position()?;
 
-      let maybe_kv = ActionKV::process_record(&mut file);
-      let kv = match maybe_kv {
-        Ok(kv) => kv,
+      let maybe_record = Self::process_record_key(&mut file);
+      let (header, key) = match maybe_record {
+        Ok(record) => record,
         Err(err) => {
           match err.kind() {
             io::ErrorKind::UnexpectedEof => {
               break;
             },
-            _ => return Err(err),
+            _ => return Err(ActionKvError::from_record(current_position, err)),
           }
         }
       };
 
-      self.index_map.insert(kv.key, current_position);
+      self.total_records += 1;
+      self.bytes_read += header.record_len();
+      if key == LAST_COMPACTION_KEY {
+        self.compaction_record = Some(current_position);
+      } else {
+        self.index_map.insert(key, current_position);
+      }
     };
 
+    drop(file);
+    if current_position < self.storage.len()? {
+      self.torn_tail = Some(current_position);
+    }
+
+    if let Some(position) = self.compaction_record {
+      let mut file = BufReader::new(StorageReader::new(&mut self.storage, position));
+      let (_, kv) = Self::process_record(&mut file)
+        .map_err(|err| ActionKvError::from_record(position, err))?;
+      self.last_compaction = decode_time(&kv.value);
+    }
+
     Ok(())
   }
 
-  pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+  pub fn get(&mut self, key: &ByteStr) -> Result<Option<ByteString>, ActionKvError> {
     let position = match self.index_map.get(key) {
       None => return Ok(None),
       Some(position) => *position,
@@ -110,85 +383,423 @@ impl ActionKV {
     Ok(Some(kv.value))
   }
 
-  pub fn get_at(&mut self, position: u64) -> io::Result<KeyValuePair> {
-    let mut file = BufReader::new(&mut self.file);
-    file.seek(SeekFrom::Start(position))?;
-    let kv = ActionKV::process_record(&mut file)?;
+  /// Returns a reader over the value stored under `key` without loading it
+  /// into memory. The checksum is verified as the value is consumed: the
+  /// read that reaches the end of a corrupted value fails with
+  /// `io::ErrorKind::InvalidData`.
+  pub fn get_reader(&mut self, key: &ByteStr) -> Result<Option<impl Read + '_>, ActionKvError> {
+    let position = match self.index_map.get(key) {
+      None => return Ok(None),
+      Some(position) => *position,
+    };
+
+    let mut file = BufReader::new(StorageReader::new(&mut self.storage, position));
+
+    let header = RecordHeader::read(&mut file)
+      .map_err(|err| ActionKvError::from_record(position, err))?;
+    let mut digest = header.digest();
+    read_bytes(&mut file, header.key_len, &mut digest)
+      .map_err(|err| ActionKvError::from_record(position, err))?;
+    self.bytes_read += header.record_len() - header.value_len;
+
+    Ok(Some(ValueReader {
+      file: file.take(header.value_len),
+      header,
+      digest: Some(digest),
+      remaining: header.value_len,
+      bytes_read: &mut self.bytes_read,
+    }))
+  }
+
+  pub fn get_at(&mut self, position: u64) -> Result<KeyValuePair, ActionKvError> {
+    let mut file = BufReader::new(StorageReader::new(&mut self.storage, position));
+    let (header, kv) = Self::process_record(&mut file)
+      .map_err(|err| ActionKvError::from_record(position, err))?;
+    self.bytes_read += header.record_len();
 
     Ok(kv)
   }
 
-  pub fn find(&mut self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
-    let mut file = BufReader::new(&mut self.file);
+  pub fn find(&mut self, target: &ByteStr) -> Result<Option<(u64, ByteString)>, ActionKvError> {
+    let mut file = BufReader::new(StorageReader::new(&mut self.storage, 0));
 
-    let mut found: Option<(u64, ByteString)> = None;
+    let mut found: Option<u64> = None;
 
     loop {
-      let position = file.seek(SeekFrom::Current(0))?;
+      let position = file.stream_position()?;
 
-      let maybe_kv = ActionKV::process_record(&mut file);
-      let kv = match maybe_kv {
-        Ok(kv) => kv,
+      let maybe_record = Self::process_record_key(&mut file);
+      let (header, key) = match maybe_record {
+        Ok(record) => record,
         Err(err) => {
           match err.kind() {
             io::ErrorKind::UnexpectedEof => {
               break;
             },
-            _ => return Err(err),
+            _ => return Err(ActionKvError::from_record(position, err)),
           }
         }
       };
 
-      if kv.key == target {
-        found = Some((position, kv.value));
+      self.bytes_read += header.record_len();
+
+      if key == target {
+        found = Some(position);
       }
     }
 
-    Ok(found)
+    match found {
+      None => Ok(None),
+      Some(position) => {
+        let kv = self.get_at(position)?;
+        Ok(Some((position, kv.value)))
+      },
+    }
   }
 
-  pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+  pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> Result<(), ActionKvError> {
     let position = self.insert_but_ignore_index(key, value)?;
 
     self.index_map.insert(key.to_vec(), position);
     Ok(())
   }
 
-  pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
-    let mut file = BufWriter::new(&mut self.file);
+  pub fn insert_but_ignore_index(
+    &mut self,
+    key: &ByteStr,
+    value: &ByteStr,
+  ) -> Result<u64, ActionKvError> {
+    let (head, record_len) = Self::record_head(key, value)?;
 
-    let key_len = key.len();
-    let val_len = value.len();
-    let mut tmp = ByteString::with_capacity(key_len + val_len);
-
-    for byte in key {
-      tmp.push(*byte);
+    if let Some(end) = self.torn_tail {
+      self.storage.truncate(end)?;
+      self.torn_tail = None;
     }
 
-    for byte in value {
-      tmp.push(*byte);
+    let end = self.storage.len()?;
+    let written = self.storage.append(&head)
+      .and_then(|position| self.storage.append(value).map(|_| position));
+    let current_position = match written {
+      Ok(position) => position,
+      Err(err) => {
+        // Best effort: if this fails too, `load` drops the partial record.
+        let _ = self.storage.truncate(end);
+        return Err(err.into());
+      },
+    };
+
+    self.total_records += 1;
+    self.bytes_written += record_len;
+
+    Ok(current_position)
+  }
+
+  /// Encodes everything of a record but its value, which callers append
+  /// separately so that it is never copied. Also returns the record's length.
+  fn record_head(key: &ByteStr, value: &ByteStr) -> Result<(ByteString, u64), ActionKvError> {
+    let key_len = u64::try_from(key.len()).map_err(|_| ActionKvError::TooLarge("key"))?;
+    let val_len = u64::try_from(value.len()).map_err(|_| ActionKvError::TooLarge("value"))?;
+
+    let header = RecordHeader { checksum: 0, key_len, value_len: val_len, is_v2: true };
+    let record_len = header.record_len();
+    if record_len == u64::MAX {
+      return Err(ActionKvError::TooLarge("record"));
     }
 
-    let checksum = ActionKV::checksum(&tmp);
+    let mut digest = header.digest();
+    digest.update(key);
+    digest.update(value);
+    let checksum = digest.finalize();
 
-    let next_byte = SeekFrom::End(0);
-    let current_position = file.seek(SeekFrom::Current(0))?;
-    file.seek(next_byte)?;
-    file.write_u32::<LittleEndian>(checksum)?;
-    file.write_u32::<LittleEndian>(key_len as u32)?;
-    file.write_u32::<LittleEndian>(val_len as u32)?;
-    file.write_all(&tmp)?;
+    let mut head = ByteString::with_capacity(HEADER_V2_LEN as usize + key.len());
+    head.write_u32::<LittleEndian>(checksum)?;
+    head.write_u32::<LittleEndian>(RECORD_V2_MARKER)?;
+    head.write_u64::<LittleEndian>(key_len)?;
+    head.write_u64::<LittleEndian>(val_len)?;
+    head.write_u32::<LittleEndian>(header.header_checksum())?;
+    head.extend_from_slice(key);
 
-    Ok(current_position)
+    Ok((head, record_len))
   }
 
   #[inline]
-  pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+  pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> Result<(), ActionKvError> {
     self.insert(key, value)
   }
 
   #[inline]
-  pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
+  pub fn delete(&mut self, key: &ByteStr) -> Result<(), ActionKvError> {
     self.insert(key, b"")
   }
+
+  /// Reports key and byte counts for the store. Only record headers are
+  /// read, so this is cheap even for stores holding large values, and it
+  /// does not count towards `bytes_read`.
+  pub fn stats(&mut self) -> Result<Stats, ActionKvError> {
+    let file_size = self.storage.len()?;
+
+    let mut live_keys = 0;
+    let mut live_bytes = 0;
+    if let Some(position) = self.compaction_record {
+      let mut file = StorageReader::new(&mut self.storage, position);
+      let header = RecordHeader::read(&mut file)
+        .map_err(|err| ActionKvError::from_record(position, err))?;
+      live_bytes += header.record_len();
+    }
+    for position in self.index_map.values() {
+      let mut file = StorageReader::new(&mut self.storage, *position);
+      let header = RecordHeader::read(&mut file)
+        .map_err(|err| ActionKvError::from_record(*position, err))?;
+
+      if header.value_len > 0 {
+        live_keys += 1;
+        live_bytes += header.record_len();
+      }
+    }
+
+    let average_record_size = match self.total_records {
+      0 => 0.0,
+      n => file_size as f64 / n as f64,
+    };
+
+    Ok(Stats {
+      live_keys,
+      total_records: self.total_records,
+      dead_bytes: file_size.saturating_sub(live_bytes),
+      file_size,
+      bytes_written: self.bytes_written,
+      bytes_read: self.bytes_read,
+      average_record_size,
+      last_compaction: self.last_compaction,
+    })
+  }
+
+  /// Copies the latest record of every live key into `target`, which must
+  /// be empty, after a record of the time, syncs it and switches the store
+  /// over to it. Returns the storage that was replaced.
+  pub fn compact_into(&mut self, mut target: S) -> Result<S, ActionKvError> {
+    if !target.is_empty()? {
+      return Err(ActionKvError::Io(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "compaction target is not empty",
+      )));
+    }
+
+    // Records are copied byte for byte, so values are never buffered and
+    // records written as v1 stay v1.
+    let mut index_map = HashMap::new();
+    let mut total_records = 1;
+    let mut buffer = [0; COPY_BUFFER_LEN];
+
+    let now = SystemTime::now();
+    let time = encode_time(now);
+    let (head, mut bytes_written) = Self::record_head(LAST_COMPACTION_KEY, &time)?;
+    target.append(&head)?;
+    target.append(&time)?;
+
+    for (key, position) in &self.index_map {
+      let mut file = StorageReader::new(&mut self.storage, *position);
+      let header = RecordHeader::read(&mut file)
+        .map_err(|err| ActionKvError::from_record(*position, err))?;
+      if header.value_len == 0 {
+        continue;
+      }
+
+      let new_position = target.len()?;
+      let mut file = StorageReader::new(&mut self.storage, *position)
+        .take(header.record_len());
+      loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+          break;
+        }
+        target.append(&buffer[..n])?;
+      }
+      if target.len()? - new_position < header.record_len() {
+        let err = io::ErrorKind::UnexpectedEof.into();
+        return Err(ActionKvError::from_record(*position, err));
+      }
+
+      index_map.insert(key.clone(), new_position);
+      total_records += 1;
+      bytes_written += header.record_len();
+    }
+    target.sync()?;
+
+    self.index_map = index_map;
+    self.total_records = total_records;
+    self.bytes_written += bytes_written;
+    self.last_compaction = Some(now);
+    self.compaction_record = Some(0);
+    // Only whole records were copied.
+    self.torn_tail = None;
+
+    Ok(std::mem::replace(&mut self.storage, target))
+  }
+}
+
+struct ValueReader<'a, R: Read> {
+  file: io::Take<R>,
+  header: RecordHeader,
+  digest: Option<Digest<'static, u32>>,
+  remaining: u64,
+  bytes_read: &'a mut u64,
+}
+
+impl<R: Read> ValueReader<'_, R> {
+  fn finish(&mut self) -> io::Result<()> {
+    match self.digest.take() {
+      None => Ok(()),
+      Some(digest) => self.header.verify(digest),
+    }
+  }
+}
+
+impl<R: Read> Read for ValueReader<'_, R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.remaining == 0 {
+      self.finish()?;
+      return Ok(0);
+    }
+
+    let n = self.file.read(buf)?;
+    if n == 0 && !buf.is_empty() {
+      return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    if let Some(digest) = self.digest.as_mut() {
+      digest.update(&buf[..n]);
+    }
+    self.remaining -= n as u64;
+    *self.bytes_read += n as u64;
+
+    if self.remaining == 0 {
+      self.finish()?;
+    }
+
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn temp_store(name: &str) -> (std::path::PathBuf, ActionKV) {
+    let path = std::env::temp_dir()
+      .join(format!("actionkv-{}-{}.akv", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    let store = ActionKV::open(&path).unwrap();
+    (path, store)
+  }
+
+  #[test]
+  fn reads_v1_records() {
+    let (path, mut store) = temp_store("v1");
+
+    let mut record = vec![];
+    record.write_u32::<LittleEndian>(CRC.checksum(b"keyvalue")).unwrap();
+    record.write_u32::<LittleEndian>(3).unwrap();
+    record.write_u32::<LittleEndian>(5).unwrap();
+    record.extend_from_slice(b"keyvalue");
+    store.storage.append(&record).unwrap();
+    store.insert(b"new", b"v2").unwrap();
+
+    let mut reopened = ActionKV::open(&path).unwrap();
+    reopened.load().unwrap();
+    assert_eq!(reopened.get(b"key").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(reopened.get(b"new").unwrap(), Some(b"v2".to_vec()));
+
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn writes_v2_records() {
+    let (path, mut store) = temp_store("v2");
+    store.insert(b"key", b"value").unwrap();
+
+    let mut lengths = vec![];
+    lengths.write_u64::<LittleEndian>(3).unwrap();
+    lengths.write_u64::<LittleEndian>(5).unwrap();
+
+    let mut expected = vec![];
+    expected.write_u32::<LittleEndian>(CRC.checksum(&[&lengths[..], b"keyvalue"].concat())).unwrap();
+    expected.write_u32::<LittleEndian>(RECORD_V2_MARKER).unwrap();
+    expected.extend_from_slice(&lengths);
+    expected.write_u32::<LittleEndian>(CRC.checksum(&lengths)).unwrap();
+    assert_eq!(expected.len() as u64, HEADER_V2_LEN);
+    expected.extend_from_slice(b"keyvalue");
+    assert_eq!(fs::read(&path).unwrap(), expected);
+
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn loading_twice_changes_nothing() {
+    let mut store = ActionKV::with_storage(MemStorage::new());
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"a", b"22").unwrap();
+    store.delete(b"b").unwrap();
+
+    store.load().unwrap();
+    let stats = store.stats().unwrap();
+    assert_eq!(stats.total_records, 3);
+    assert_eq!(stats.bytes_read, stats.file_size);
+
+    store.load().unwrap();
+    assert_eq!(store.stats().unwrap(), stats);
+    assert_eq!(store.index_map.len(), 2);
+  }
+
+  #[test]
+  fn compaction_time_is_kept_in_the_file() {
+    let mut store = ActionKV::with_storage(MemStorage::new());
+    store.insert(b"a", b"1").unwrap();
+    store.load().unwrap();
+    assert_eq!(store.stats().unwrap().last_compaction, None);
+
+    store.compact_into(MemStorage::new()).unwrap();
+    let compacted = store.stats().unwrap().last_compaction;
+    assert!(compacted.is_some());
+
+    let mut reopened = ActionKV::with_storage(store.into_storage());
+    reopened.load().unwrap();
+    let stats = reopened.stats().unwrap();
+    assert_eq!(stats.last_compaction, compacted);
+    assert_eq!((stats.live_keys, stats.total_records, stats.dead_bytes), (1, 2, 0));
+    assert_eq!(reopened.get(LAST_COMPACTION_KEY).unwrap(), None);
+  }
+
+  #[test]
+  fn get_reader_streams_value() {
+    let (path, mut store) = temp_store("reader");
+    let value: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    store.insert(b"big", &value).unwrap();
+
+    let mut streamed = vec![];
+    store.get_reader(b"big").unwrap().unwrap().read_to_end(&mut streamed).unwrap();
+    assert_eq!(streamed, value);
+    assert!(store.get_reader(b"missing").unwrap().is_none());
+
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn get_reader_detects_corruption() {
+    let (path, mut store) = temp_store("corrupt");
+    store.insert(b"key", b"value").unwrap();
+
+    let mut bytes = fs::read(&path).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0x01;
+    fs::write(&path, &bytes).unwrap();
+
+    let mut streamed = vec![];
+    let err = store.get_reader(b"key").unwrap().unwrap()
+      .read_to_end(&mut streamed)
+      .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    fs::remove_file(&path).unwrap();
+  }
 }
@@ -0,0 +1,231 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+/// The byte-level operations `ActionKV` needs from the medium that holds
+/// its log. Writes only ever go to the end; `truncate` exists so that a
+/// store can cut off a half-written record.
+pub trait Storage {
+  /// Writes `data` at the end and returns the offset it starts at.
+  fn append(&mut self, data: &[u8]) -> io::Result<u64>;
+
+  /// Reads up to `buf.len()` bytes starting at `offset`, returning how many
+  /// were read. `Ok(0)` means `offset` is at or past the end.
+  fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+  /// Makes every byte appended so far durable.
+  fn sync(&mut self) -> io::Result<()>;
+
+  fn truncate(&mut self, len: u64) -> io::Result<()>;
+
+  fn len(&self) -> io::Result<u64>;
+
+  fn is_empty(&self) -> io::Result<bool> {
+    Ok(self.len()? == 0)
+  }
+}
+
+#[derive(Debug)]
+pub struct FileStorage {
+  file: File,
+  path: PathBuf,
+}
+
+impl FileStorage {
+  pub fn open(path: &Path) -> io::Result<Self> {
+    let file = OpenOptions::new()
+      .read(true)
+      .create(true)
+      .append(true)
+      .open(path)?;
+    Ok(FileStorage { file, path: path.to_path_buf() })
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub(crate) fn set_path(&mut self, path: PathBuf) {
+    self.path = path;
+  }
+}
+
+impl Storage for FileStorage {
+  fn append(&mut self, data: &[u8]) -> io::Result<u64> {
+    let offset = self.file.seek(SeekFrom::End(0))?;
+    self.file.write_all(data)?;
+    Ok(offset)
+  }
+
+  fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+    self.file.seek(SeekFrom::Start(offset))?;
+    self.file.read(buf)
+  }
+
+  fn sync(&mut self) -> io::Result<()> {
+    self.file.sync_data()
+  }
+
+  fn truncate(&mut self, len: u64) -> io::Result<()> {
+    self.file.set_len(len)
+  }
+
+  fn len(&self) -> io::Result<u64> {
+    Ok(self.file.metadata()?.len())
+  }
+}
+
+/// An in-memory `Storage` that remembers which bytes have been synced and
+/// can be told to misbehave, for testing how `ActionKV` copes with crashes.
+///
+/// ```
+/// use libactionkv::storage::{MemStorage, Storage};
+///
+/// let mut storage = MemStorage::new();
+/// storage.append(b"durable").unwrap();
+/// storage.sync().unwrap();
+/// storage.append(b"lost").unwrap();
+///
+/// assert_eq!(storage.crash().as_bytes(), b"durable");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemStorage {
+  data: Vec<u8>,
+  synced_len: usize,
+  tear_next_append: Option<usize>,
+  fail_next_sync: bool,
+}
+
+impl MemStorage {
+  pub fn new() -> Self {
+    MemStorage::default()
+  }
+
+  /// Creates a storage whose contents are all considered durable.
+  pub fn from_bytes(data: Vec<u8>) -> Self {
+    let synced_len = data.len();
+    MemStorage { data, synced_len, ..MemStorage::default() }
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.data
+  }
+
+  pub fn synced_len(&self) -> u64 {
+    self.synced_len as u64
+  }
+
+  /// Makes the next `append` write only its first `keep` bytes and then
+  /// fail, as if the process died mid-write.
+  pub fn tear_next_append(&mut self, keep: usize) {
+    self.tear_next_append = Some(keep);
+  }
+
+  /// Makes the next `sync` fail without making anything durable.
+  pub fn fail_next_sync(&mut self) {
+    self.fail_next_sync = true;
+  }
+
+  /// Flips one bit of the stored bytes, as media corruption would.
+  pub fn flip_bit(&mut self, offset: u64, bit: u8) {
+    self.data[offset as usize] ^= 1 << (bit % 8);
+  }
+
+  /// Returns what would be left after a power failure: the synced bytes
+  /// survive, anything appended since is lost.
+  pub fn crash(&self) -> MemStorage {
+    self.crash_at(self.synced_len as u64)
+  }
+
+  /// Returns what would be left if a power failure kept exactly the first
+  /// `len` bytes, e.g. because the OS had flushed part of an unsynced write.
+  pub fn crash_at(&self, len: u64) -> MemStorage {
+    let len = (len as usize).min(self.data.len());
+    MemStorage::from_bytes(self.data[..len].to_vec())
+  }
+}
+
+impl Storage for MemStorage {
+  fn append(&mut self, data: &[u8]) -> io::Result<u64> {
+    let offset = self.data.len() as u64;
+
+    match self.tear_next_append.take() {
+      None => {
+        self.data.extend_from_slice(data);
+        Ok(offset)
+      },
+      Some(keep) => {
+        self.data.extend_from_slice(&data[..keep.min(data.len())]);
+        Err(io::Error::other("injected torn write"))
+      },
+    }
+  }
+
+  fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+    let start = (offset as usize).min(self.data.len());
+    let n = buf.len().min(self.data.len() - start);
+    buf[..n].copy_from_slice(&self.data[start..start + n]);
+    Ok(n)
+  }
+
+  fn sync(&mut self) -> io::Result<()> {
+    if self.fail_next_sync {
+      self.fail_next_sync = false;
+      return Err(io::Error::other("injected fsync failure"));
+    }
+
+    self.synced_len = self.data.len();
+    Ok(())
+  }
+
+  fn truncate(&mut self, len: u64) -> io::Result<()> {
+    self.data.truncate(len as usize);
+    self.synced_len = self.synced_len.min(self.data.len());
+    Ok(())
+  }
+
+  fn len(&self) -> io::Result<u64> {
+    Ok(self.data.len() as u64)
+  }
+}
+
+/// Adapts a `Storage` to `Read + Seek` so records can be parsed with the
+/// usual `std::io` tooling.
+pub(crate) struct StorageReader<'a, S: Storage> {
+  storage: &'a mut S,
+  position: u64,
+}
+
+impl<'a, S: Storage> StorageReader<'a, S> {
+  pub(crate) fn new(storage: &'a mut S, position: u64) -> Self {
+    StorageReader { storage, position }
+  }
+}
+
+impl<S: Storage> Read for StorageReader<'_, S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.storage.read_at(self.position, buf)?;
+    self.position += n as u64;
+    Ok(n)
+  }
+}
+
+impl<S: Storage> Seek for StorageReader<'_, S> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let position = match pos {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::End(delta) => self.storage.len()?.checked_add_signed(delta),
+      SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+    };
+
+    match position {
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position")),
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      },
+    }
+  }
+}
@@ -0,0 +1,163 @@
+use libactionkv::{ActionKV, ActionKvError, FileStorage, MemStorage, Storage};
+
+fn reopen(storage: MemStorage) -> Result<ActionKV<MemStorage>, ActionKvError> {
+  let mut store = ActionKV::with_storage(storage);
+  store.load()?;
+  Ok(store)
+}
+
+fn synced_store(pairs: &[(&[u8], &[u8])]) -> ActionKV<MemStorage> {
+  let mut store = ActionKV::with_storage(MemStorage::new());
+  for (key, value) in pairs {
+    store.insert(key, value).unwrap();
+  }
+  store.sync().unwrap();
+  store
+}
+
+#[test]
+fn torn_write_is_rolled_back() {
+  let mut store = synced_store(&[(b"apple", b"red")]);
+  let len_before = store.storage().len().unwrap();
+
+  store.storage_mut().tear_next_append(7);
+  assert!(store.insert(b"banana", b"yellow").is_err());
+  assert_eq!(store.storage().len().unwrap(), len_before);
+  assert_eq!(store.get(b"banana").unwrap(), None);
+
+  store.insert(b"cherry", b"dark red").unwrap();
+  store.sync().unwrap();
+
+  let mut store = reopen(store.into_storage().crash()).unwrap();
+  assert_eq!(store.get(b"apple").unwrap(), Some(b"red".to_vec()));
+  assert_eq!(store.get(b"banana").unwrap(), None);
+  assert_eq!(store.get(b"cherry").unwrap(), Some(b"dark red".to_vec()));
+}
+
+#[test]
+fn crash_inside_last_record_drops_only_that_record() {
+  let mut store = synced_store(&[(b"apple", b"red")]);
+  let first_len = store.storage().len().unwrap();
+  store.insert(b"banana", b"yellow").unwrap();
+  let full = store.into_storage();
+  let full_len = full.len().unwrap();
+
+  for cut in first_len..full_len {
+    let mut store = reopen(full.crash_at(cut)).unwrap();
+    assert_eq!(store.get(b"apple").unwrap(), Some(b"red".to_vec()));
+    assert_eq!(store.get(b"banana").unwrap(), None);
+    // Reading leaves the torn tail alone; the next write removes it.
+    assert_eq!(store.storage().len().unwrap(), cut, "cut at {}", cut);
+
+    store.insert(b"cherry", b"dark red").unwrap();
+    assert_eq!(store.index_map[&b"cherry"[..]], first_len, "cut at {}", cut);
+    let mut store = reopen(store.into_storage()).unwrap();
+    assert_eq!(store.get(b"cherry").unwrap(), Some(b"dark red".to_vec()));
+  }
+}
+
+#[test]
+fn failed_sync_keeps_writes_volatile() {
+  let mut store = synced_store(&[(b"apple", b"red")]);
+  store.insert(b"banana", b"yellow").unwrap();
+
+  store.storage_mut().fail_next_sync();
+  assert!(store.sync().is_err());
+
+  let mut crashed = reopen(store.storage().crash()).unwrap();
+  assert_eq!(crashed.get(b"apple").unwrap(), Some(b"red".to_vec()));
+  assert_eq!(crashed.get(b"banana").unwrap(), None);
+
+  store.sync().unwrap();
+  let mut crashed = reopen(store.storage().crash()).unwrap();
+  assert_eq!(crashed.get(b"banana").unwrap(), Some(b"yellow".to_vec()));
+}
+
+#[test]
+fn bit_flip_is_reported_as_corruption() {
+  let store = synced_store(&[(b"apple", b"red"), (b"banana", b"yellow")]);
+  let storage = store.into_storage();
+  let len = storage.len().unwrap();
+
+  for offset in 0..len {
+    let mut flipped = storage.clone();
+    flipped.flip_bit(offset, (offset % 8) as u8);
+
+    match reopen(flipped) {
+      Err(ActionKvError::Corruption { position, .. }) => assert!(position <= offset),
+      other => panic!("flip at {}: expected corruption, got {:?}", offset, other.err()),
+    }
+  }
+}
+
+#[test]
+fn failed_compaction_leaves_store_untouched() {
+  let mut store = synced_store(&[(b"apple", b"red"), (b"apple", b"green")]);
+  let before = store.storage().as_bytes().to_vec();
+
+  let mut target = MemStorage::new();
+  target.fail_next_sync();
+  assert!(store.compact_into(target).is_err());
+
+  assert_eq!(store.storage().as_bytes(), &before[..]);
+  assert_eq!(store.get(b"apple").unwrap(), Some(b"green".to_vec()));
+}
+
+#[test]
+fn compaction_survives_a_crash() {
+  let mut store = synced_store(&[
+    (b"apple", b"red"),
+    (b"apple", b"green"),
+    (b"banana", b"yellow"),
+  ]);
+  store.delete(b"banana").unwrap();
+
+  let old = store.compact_into(MemStorage::new()).unwrap();
+  assert!(store.storage().len().unwrap() < old.len().unwrap());
+
+  let mut crashed = reopen(store.storage().crash()).unwrap();
+  assert_eq!(crashed.get(b"apple").unwrap(), Some(b"green".to_vec()));
+  assert_eq!(crashed.get(b"banana").unwrap(), None);
+}
+
+#[test]
+fn compaction_drops_torn_tail() {
+  let mut store = synced_store(&[(b"apple", b"red")]);
+  let first_len = store.storage().len().unwrap();
+  store.insert(b"banana", b"yellow").unwrap();
+
+  let mut store = reopen(store.into_storage().crash_at(first_len + 3)).unwrap();
+  store.compact_into(MemStorage::new()).unwrap();
+  // What is left is apple and the record of the compaction time.
+  let stats = store.stats().unwrap();
+  assert_eq!((stats.total_records, stats.live_keys, stats.dead_bytes), (2, 1, 0));
+  assert_eq!(store.get(b"apple").unwrap(), Some(b"red".to_vec()));
+}
+
+#[test]
+fn file_storage_drops_torn_tail() {
+  let path = std::env::temp_dir()
+    .join(format!("actionkv-torn-tail-{}.akv", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+
+  let mut store = ActionKV::open(&path).unwrap();
+  store.insert(b"apple", b"red").unwrap();
+  store.sync().unwrap();
+  let good_len = store.storage().len().unwrap();
+
+  let mut storage = FileStorage::open(&path).unwrap();
+  storage.append(&[0xde, 0xad, 0xbe, 0xef, 0xff]).unwrap();
+
+  let mut store = ActionKV::open(&path).unwrap();
+  store.load().unwrap();
+  assert_eq!(store.get(b"apple").unwrap(), Some(b"red".to_vec()));
+  assert_eq!(std::fs::metadata(&path).unwrap().len(), good_len + 5);
+
+  store.insert(b"banana", b"yellow").unwrap();
+  assert_eq!(store.index_map[&b"banana"[..]], good_len);
+  let mut store = ActionKV::open(&path).unwrap();
+  store.load().unwrap();
+  assert_eq!(store.get(b"banana").unwrap(), Some(b"yellow".to_vec()));
+
+  std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,205 @@
+//! Drives random sequences of operations against `ActionKV` and a `HashMap`
+//! reference model and checks that both agree after every step.
+//!
+//! A delete is stored as an empty value, so deleting a key and storing an
+//! empty value under it are the same operation. Either way the key reads
+//! back as an empty value until the next compaction drops its record. The
+//! model records both that way, as an empty value, and compaction removes
+//! them from it.
+
+use std::collections::HashMap;
+
+use libactionkv::{ActionKV, MemStorage, Storage};
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+type Model = HashMap<Vec<u8>, Vec<u8>>;
+
+const KEYS: &[&[u8]] = &[b"a", b"b", b"c", b"apple", b"banana", b"+index", b"", b"\xff\x00"];
+
+#[derive(Debug, Clone)]
+enum Op {
+  Insert(usize, Vec<u8>),
+  Update(usize, Vec<u8>),
+  Delete(usize),
+  Sync,
+  Reopen,
+  Compact,
+  /// Power loss that keeps some of the unsynced bytes, picked by the index.
+  Crash(Index),
+}
+
+fn op_strategy() -> impl Strategy<Value = Op> {
+  let key = 0..KEYS.len();
+  let value = prop::collection::vec(any::<u8>(), 0..64);
+
+  prop_oneof![
+    6 => (key.clone(), value.clone()).prop_map(|(k, v)| Op::Insert(k, v)),
+    3 => (key.clone(), value).prop_map(|(k, v)| Op::Update(k, v)),
+    3 => key.prop_map(Op::Delete),
+    2 => Just(Op::Sync),
+    1 => Just(Op::Reopen),
+    1 => Just(Op::Compact),
+    2 => any::<Index>().prop_map(Op::Crash),
+  ]
+}
+
+struct Harness {
+  store: ActionKV<MemStorage>,
+  model: Model,
+  /// The model as of the end of each record in storage, used to work out
+  /// what a crash should leave behind.
+  history: Vec<(u64, Model)>,
+}
+
+impl Harness {
+  fn new() -> Self {
+    Harness {
+      store: ActionKV::with_storage(MemStorage::new()),
+      model: Model::new(),
+      history: vec![(0, Model::new())],
+    }
+  }
+
+  fn reopen(&mut self, storage: MemStorage) {
+    let mut store = ActionKV::with_storage(storage);
+    store.load().expect("load after reopen");
+    self.store = store;
+  }
+
+  fn record_write(&mut self) {
+    let end = self.store.storage().len().unwrap();
+    self.history.push((end, self.model.clone()));
+  }
+
+  /// Rebuilds the history for a freshly compacted store, which starts with
+  /// a record of the compaction time, followed by records in no particular
+  /// order: each one adds its key to the model.
+  fn record_compaction(&mut self) {
+    let mut records: Vec<(u64, &Vec<u8>)> = self.store.index_map.iter()
+      .map(|(key, position)| (*position, key))
+      .collect();
+    records.sort();
+
+    let mut model = Model::new();
+    let time_end = match records.first() {
+      Some((position, _)) => *position,
+      None => self.store.storage().len().unwrap(),
+    };
+    self.history = vec![(0, model.clone()), (time_end, model.clone())];
+    for (i, (_, key)) in records.iter().enumerate() {
+      let end = match records.get(i + 1) {
+        Some((next, _)) => *next,
+        None => self.store.storage().len().unwrap(),
+      };
+      model.insert(key.to_vec(), self.model[*key].clone());
+      self.history.push((end, model.clone()));
+    }
+  }
+
+  fn apply(&mut self, op: &Op) {
+    match op {
+      Op::Insert(k, v) => {
+        self.store.insert(KEYS[*k], v).unwrap();
+        self.model.insert(KEYS[*k].to_vec(), v.clone());
+        self.record_write();
+      },
+      Op::Update(k, v) => {
+        self.store.update(KEYS[*k], v).unwrap();
+        self.model.insert(KEYS[*k].to_vec(), v.clone());
+        self.record_write();
+      },
+      Op::Delete(k) => {
+        self.store.delete(KEYS[*k]).unwrap();
+        self.model.insert(KEYS[*k].to_vec(), vec![]);
+        self.record_write();
+      },
+      Op::Sync => self.store.sync().unwrap(),
+      Op::Reopen => {
+        let storage = std::mem::replace(self.store.storage_mut(), MemStorage::new());
+        self.reopen(storage);
+      },
+      Op::Compact => {
+        self.store.compact_into(MemStorage::new()).unwrap();
+        self.model.retain(|_, value| !value.is_empty());
+        self.record_compaction();
+      },
+      Op::Crash(index) => {
+        let storage = self.store.storage();
+        let synced = storage.synced_len();
+        let len = storage.len().unwrap();
+        let at = synced + index.index((len - synced + 1) as usize) as u64;
+
+        let (end, survivor) = self.history.iter()
+          .rev()
+          .find(|(end, _)| *end <= at)
+          .cloned()
+          .expect("synced data is always covered by the history");
+
+        self.reopen(storage.crash_at(at));
+        // A torn tail is left in place until the next write cuts it off.
+        assert_eq!(self.store.storage().len().unwrap(), at);
+        self.model = survivor;
+        self.history.retain(|(e, _)| *e <= end);
+      },
+    }
+  }
+
+  fn check(&mut self) {
+    for key in KEYS {
+      let observed = self.store.get(key).unwrap();
+      assert_eq!(observed.as_ref(), self.model.get(*key), "key {:?}", key);
+    }
+
+    let stats = self.store.stats().unwrap();
+    let live_keys = self.model.values().filter(|value| !value.is_empty()).count();
+    assert_eq!(stats.live_keys, live_keys);
+    assert_eq!(stats.file_size, self.store.storage().len().unwrap());
+  }
+}
+
+proptest! {
+  #![proptest_config(ProptestConfig::with_cases(256))]
+
+  #[test]
+  fn store_matches_model(ops in prop::collection::vec(op_strategy(), 1..80)) {
+    let mut harness = Harness::new();
+
+    for op in &ops {
+      harness.apply(op);
+      harness.check();
+    }
+  }
+
+  #[test]
+  fn crash_at_any_offset_recovers_a_prefix(
+    ops in prop::collection::vec(op_strategy(), 1..30),
+    cut in any::<Index>(),
+  ) {
+    let mut harness = Harness::new();
+    for op in &ops {
+      harness.apply(op);
+    }
+
+    // Unlike `Op::Crash`, this also cuts into bytes that were synced, as a
+    // lost disk cache would, including those written by a compaction, and
+    // the survivors must still load cleanly.
+    let storage = harness.store.storage().clone();
+    let len = storage.len().unwrap();
+    let at = cut.index(len as usize + 1) as u64;
+
+    let (end, survivor) = harness.history.iter()
+      .rev()
+      .find(|(end, _)| *end <= at)
+      .cloned()
+      .unwrap();
+
+    harness.reopen(storage.crash_at(at));
+    prop_assert_eq!(harness.store.storage().len().unwrap(), at);
+    harness.model = survivor;
+    harness.check();
+
+    harness.store.insert(b"a", b"after").unwrap();
+    prop_assert_eq!(harness.store.index_map[&b"a"[..]], end);
+  }
+}